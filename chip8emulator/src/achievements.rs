@@ -0,0 +1,249 @@
+// Achievements: sets of conditions over RAM and the V registers, evaluated
+// once per frame. A condition compares two operands, either of which can be
+// a constant, a current value or last frame's value (for deltas). It can
+// require a number of frames it held true (hit count), and reset conditions
+// clear the hit counts of their achievement whenever they are true.
+
+use crate::{Chip8Emulator, NUM_GP_REGISTERS};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Achievement {
+    pub id: String,
+    pub title: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: String,
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Condition {
+    pub left: Operand,
+    pub cmp: Comparison,
+    pub right: Operand,
+    /// Frames the comparison must have held (not necessarily in a row); 0
+    /// means it must hold on the current frame.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hits: u32,
+    /// A reset condition clears the achievement's hit counts while true.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reset: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Operand {
+    Value(u8),
+    Mem(u16),
+    Reg(u8),
+    PrevMem(u16),
+    PrevReg(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AchievementError {
+    pub id: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for AchievementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "achievement {}: {}", self.id, self.message)
+    }
+}
+
+impl std::error::Error for AchievementError {}
+
+struct Progress {
+    unlocked: bool,
+    hits: Vec<u32>,
+}
+
+pub struct AchievementEngine {
+    achievements: Vec<Achievement>,
+    progress: Vec<Progress>,
+    previous: Option<(Vec<u8>, [u8; NUM_GP_REGISTERS])>,
+}
+
+impl AchievementEngine {
+    /// Validates the definitions; ids in `unlocked` are skipped from the start.
+    pub fn new(achievements: Vec<Achievement>, unlocked: &[String]) -> Result<Self, AchievementError> {
+        for achievement in &achievements {
+            let error = |message: &str| AchievementError { id: achievement.id.clone(), message: message.to_string() };
+            if !achievement.conditions.iter().any(|condition| !condition.reset) {
+                return Err(error("needs at least one non-reset condition"));
+            }
+            for condition in &achievement.conditions {
+                for operand in [condition.left, condition.right] {
+                    match operand {
+                        Operand::Reg(idx) | Operand::PrevReg(idx) if idx as usize >= NUM_GP_REGISTERS => {
+                            return Err(error("register out of range"));
+                        },
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        let progress = achievements
+            .iter()
+            .map(|achievement| Progress {
+                unlocked: unlocked.contains(&achievement.id),
+                hits: vec![0; achievement.conditions.len()],
+            })
+            .collect();
+        Ok(Self { achievements, progress, previous: None })
+    }
+
+    pub fn achievements(&self) -> &[Achievement] {
+        &self.achievements
+    }
+
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.achievements.iter().zip(&self.progress).any(|(achievement, progress)| achievement.id == id && progress.unlocked)
+    }
+
+    /// Evaluates every locked achievement against the current frame and calls
+    /// `on_unlock` for each one that unlocks.
+    pub fn evaluate(&mut self, chip8_emulator: &Chip8Emulator, mut on_unlock: impl FnMut(&Achievement)) {
+        // Addresses past the end of a smaller RAM read as 0.
+        let current = (chip8_emulator.memory.ram.clone(), chip8_emulator.registers.gp_registers);
+        let previous = self.previous.take().unwrap_or_else(|| current.clone());
+        let read = |operand: Operand| match operand {
+            Operand::Value(value) => value,
+            Operand::Mem(address) => current.0.get(address as usize).copied().unwrap_or(0),
+            Operand::Reg(idx) => current.1[idx as usize],
+            Operand::PrevMem(address) => previous.0.get(address as usize).copied().unwrap_or(0),
+            Operand::PrevReg(idx) => previous.1[idx as usize],
+        };
+
+        for (achievement, progress) in self.achievements.iter().zip(self.progress.iter_mut()) {
+            if progress.unlocked {
+                continue;
+            }
+
+            let results: Vec<bool> = achievement
+                .conditions
+                .iter()
+                .map(|condition| compare(read(condition.left), condition.cmp, read(condition.right)))
+                .collect();
+
+            let reset = achievement.conditions.iter().zip(&results).any(|(condition, result)| condition.reset && *result);
+            if reset {
+                progress.hits.iter_mut().for_each(|hits| *hits = 0);
+                continue;
+            }
+
+            let mut satisfied = true;
+            for ((condition, result), hits) in achievement.conditions.iter().zip(&results).zip(progress.hits.iter_mut()) {
+                if condition.reset {
+                    continue;
+                }
+                if *result && *hits < condition.hits {
+                    *hits += 1;
+                }
+                satisfied &= if condition.hits == 0 { *result } else { *hits >= condition.hits };
+            }
+
+            if satisfied {
+                progress.unlocked = true;
+                on_unlock(achievement);
+            }
+        }
+
+        self.previous = Some(current);
+    }
+}
+
+fn compare(left: u8, cmp: Comparison, right: u8) -> bool {
+    match cmp {
+        Comparison::Eq => left == right,
+        Comparison::Ne => left != right,
+        Comparison::Lt => left < right,
+        Comparison::Le => left <= right,
+        Comparison::Gt => left > right,
+        Comparison::Ge => left >= right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(left: Operand, cmp: Comparison, right: Operand) -> Condition {
+        Condition { left, cmp, right, hits: 0, reset: false }
+    }
+
+    fn achievement(id: &str, conditions: Vec<Condition>) -> Achievement {
+        Achievement { id: id.to_string(), title: id.to_string(), description: String::new(), conditions }
+    }
+
+    fn unlocks(engine: &mut AchievementEngine, chip8_emulator: &Chip8Emulator) -> Vec<String> {
+        let mut unlocked = Vec::new();
+        engine.evaluate(chip8_emulator, |achievement| unlocked.push(achievement.id.clone()));
+        unlocked
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let reset_only = Condition { reset: true, ..condition(Operand::Reg(0), Comparison::Eq, Operand::Value(0)) };
+        let error = AchievementEngine::new(vec![achievement("a", vec![reset_only])], &[]).err().unwrap();
+        assert_eq!(error.message, "needs at least one non-reset condition");
+
+        let bad_register = condition(Operand::PrevReg(16), Comparison::Eq, Operand::Value(0));
+        let error = AchievementEngine::new(vec![achievement("b", vec![bad_register])], &[]).err().unwrap();
+        assert_eq!(error, AchievementError { id: "b".to_string(), message: "register out of range".to_string() });
+    }
+
+    #[test]
+    fn delta_conditions_compare_with_the_last_frame() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x12, 0x00]);
+        let score_up = condition(Operand::Mem(0x300), Comparison::Gt, Operand::PrevMem(0x300));
+        let mut engine = AchievementEngine::new(vec![achievement("score", vec![score_up])], &[]).unwrap();
+
+        assert!(unlocks(&mut engine, &chip8_emulator).is_empty());
+        assert!(unlocks(&mut engine, &chip8_emulator).is_empty());
+        chip8_emulator.memory.ram[0x300] = 1;
+        assert_eq!(unlocks(&mut engine, &chip8_emulator), ["score"]);
+        assert!(engine.is_unlocked("score"));
+        chip8_emulator.memory.ram[0x300] = 2;
+        assert!(unlocks(&mut engine, &chip8_emulator).is_empty(), "unlocks only once");
+    }
+
+    #[test]
+    fn hit_counts_are_cleared_by_reset_conditions() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x12, 0x00]);
+        let held = Condition { hits: 3, ..condition(Operand::Reg(1), Comparison::Eq, Operand::Value(1)) };
+        let reset = Condition { reset: true, ..condition(Operand::Reg(2), Comparison::Ne, Operand::Value(0)) };
+        let mut engine = AchievementEngine::new(vec![achievement("hold", vec![held, reset])], &[]).unwrap();
+
+        chip8_emulator.set_register(1, 1).unwrap();
+        unlocks(&mut engine, &chip8_emulator);
+        unlocks(&mut engine, &chip8_emulator);
+        chip8_emulator.set_register(2, 1).unwrap();
+        unlocks(&mut engine, &chip8_emulator);
+        chip8_emulator.set_register(2, 0).unwrap();
+        assert!(unlocks(&mut engine, &chip8_emulator).is_empty());
+        assert!(unlocks(&mut engine, &chip8_emulator).is_empty());
+        assert_eq!(unlocks(&mut engine, &chip8_emulator), ["hold"]);
+
+        let engine = AchievementEngine::new(vec![achievement("hold", vec![held])], &["hold".to_string()]).unwrap();
+        assert!(engine.is_unlocked("hold"));
+    }
+}
@@ -0,0 +1,417 @@
+// RCA CDP1802 CPU core, the processor of the COSMAC VIP. It runs directly on
+// the CHIP-8 interpreter's RAM; port I/O and the EF input lines go through
+// `Cdp1802Io`, so the machine around it decides what they mean. DMA and the
+// interrupt timing of the 1861 video chip are left to the caller.
+//
+// Addresses wrap at the end of the given memory, like the mirrored RAM of
+// the VIP.
+
+pub trait Cdp1802Io {
+    /// OUT 1-7.
+    fn output(&mut self, port: u8, value: u8);
+    /// INP 1-7.
+    fn input(&mut self, port: u8) -> u8;
+    /// EF1-EF4; `line` is 1 to 4.
+    fn flag(&mut self, line: u8) -> bool;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cdp1802 {
+    /// R0 to RF.
+    pub r: [u16; 16],
+    pub p: u8,
+    pub x: u8,
+    pub d: u8,
+    pub df: bool,
+    pub q: bool,
+    pub t: u8,
+    /// Interrupt enable.
+    pub ie: bool,
+    /// Set by IDL; the CPU waits for an interrupt or DMA until cleared.
+    pub idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cdp1802 {
+    /// State after reset: P = X = 0, R0 = 0, interrupts enabled.
+    pub fn new() -> Self {
+        Self { r: [0; 16], p: 0, x: 0, d: 0, df: false, q: false, t: 0, ie: true, idle: false }
+    }
+
+    /// Takes an interrupt if enabled: T = XP, X = 2, P = 1.
+    pub fn interrupt(&mut self) {
+        if self.ie {
+            self.t = self.x << 4 | self.p;
+            self.x = 2;
+            self.p = 1;
+            self.ie = false;
+            self.idle = false;
+        }
+    }
+
+    /// Executes one instruction and returns the machine cycles it took (2 or
+    /// 3, at 8 clocks each). Does nothing while idle.
+    pub fn step(&mut self, memory: &mut [u8], io: &mut impl Cdp1802Io) -> u8 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(memory);
+        let n = (opcode & 0x0F) as usize;
+        let (x, p) = (self.x as usize, self.p as usize);
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = read(memory, self.r[n]),
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let taken = self.condition(n as u8, io);
+                let target = read(memory, self.r[p]);
+                if taken {
+                    self.r[p] = self.r[p] & 0xFF00 | target as u16;
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(1);
+                }
+            },
+            0x4 => {
+                self.d = read(memory, self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            0x5 => write(memory, self.r[n], self.d),
+            0x6 => match n {
+                0 => self.r[x] = self.r[x].wrapping_add(1),
+                1..=7 => {
+                    io.output(n as u8, read(memory, self.r[x]));
+                    self.r[x] = self.r[x].wrapping_add(1);
+                },
+                8 => (),
+                _ => {
+                    let value = io.input(n as u8 - 8);
+                    write(memory, self.r[x], value);
+                    self.d = value;
+                },
+            },
+            0x7 => self.execute_7n(n, memory),
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.execute_long(n as u8, memory, io);
+                return 3;
+            },
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => self.execute_alu(n, memory),
+        }
+        2
+    }
+
+    fn fetch(&mut self, memory: &[u8]) -> u8 {
+        let p = self.p as usize;
+        let byte = read(memory, self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        byte
+    }
+
+    /// Branch conditions of the 3N group; 8-F are the negations of 0-7.
+    fn condition(&mut self, n: u8, io: &mut impl Cdp1802Io) -> bool {
+        let condition = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            line => io.flag(line - 3),
+        };
+        condition != (n >= 8)
+    }
+
+    fn execute_long(&mut self, n: u8, memory: &[u8], io: &mut impl Cdp1802Io) {
+        let p = self.p as usize;
+        let skip = match n {
+            // Long branches, C8-CB being the negated forms with C8 = LSKP.
+            0x0..=0x3 | 0x8..=0xB => {
+                if self.condition(n & 0x8 | n & 0x3, io) {
+                    self.r[p] = (read(memory, self.r[p]) as u16) << 8 | read(memory, self.r[p].wrapping_add(1)) as u16;
+                    return;
+                }
+                true
+            },
+            0x4 => false,
+            0x5 => !self.q,
+            0x6 => self.d != 0,
+            0x7 => !self.df,
+            0xC => self.ie,
+            0xD => self.q,
+            0xE => self.d == 0,
+            _ => self.df,
+        };
+        if skip {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    fn execute_7n(&mut self, n: usize, memory: &mut [u8]) {
+        let x = self.x as usize;
+        match n {
+            0x0 | 0x1 => {
+                let value = read(memory, self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0x0F;
+                self.ie = n == 0;
+            },
+            0x2 => {
+                self.d = read(memory, self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+            },
+            0x3 => {
+                write(memory, self.r[x], self.d);
+                self.r[x] = self.r[x].wrapping_sub(1);
+            },
+            0x4 => self.add(read(memory, self.r[x]), self.df),
+            0x5 => self.subtract(read(memory, self.r[x]), self.d, !self.df),
+            0x6 => {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            },
+            0x7 => self.subtract(self.d, read(memory, self.r[x]), !self.df),
+            0x8 => write(memory, self.r[x], self.t),
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                write(memory, self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            },
+            0xA => self.q = false,
+            0xB => self.q = true,
+            0xC => {
+                let immediate = self.fetch(memory);
+                self.add(immediate, self.df);
+            },
+            0xD => {
+                let immediate = self.fetch(memory);
+                self.subtract(immediate, self.d, !self.df);
+            },
+            0xE => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            },
+            _ => {
+                let immediate = self.fetch(memory);
+                self.subtract(self.d, immediate, !self.df);
+            },
+        }
+    }
+
+    /// FN group: operand is M(R(X)) for F0-F7 and the immediate byte for F8-FF.
+    fn execute_alu(&mut self, n: usize, memory: &[u8]) {
+        match n {
+            0x6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+                return;
+            },
+            0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+                return;
+            },
+            _ => (),
+        }
+
+        let operand = if n < 8 { read(memory, self.r[self.x as usize]) } else { self.fetch(memory) };
+        match n & 0x7 {
+            0x0 => self.d = operand,
+            0x1 => self.d |= operand,
+            0x2 => self.d &= operand,
+            0x3 => self.d ^= operand,
+            0x4 => self.add(operand, false),
+            0x5 => self.subtract(operand, self.d, false),
+            _ => self.subtract(self.d, operand, false),
+        }
+    }
+
+    fn add(&mut self, operand: u8, carry: bool) {
+        let sum = self.d as u16 + operand as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// D = minuend - subtrahend - borrow; DF set means no borrow occurred.
+    fn subtract(&mut self, minuend: u8, subtrahend: u8, borrow: bool) {
+        let difference = minuend as i16 - subtrahend as i16 - borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+fn read(memory: &[u8], address: u16) -> u8 {
+    memory[address as usize % memory.len()]
+}
+
+fn write(memory: &mut [u8], address: u16, value: u8) {
+    let len = memory.len();
+    memory[address as usize % len] = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestIo {
+        outputs: Vec<(u8, u8)>,
+        ef1: bool,
+    }
+
+    impl Cdp1802Io for TestIo {
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn input(&mut self, _port: u8) -> u8 {
+            0x5A
+        }
+
+        fn flag(&mut self, line: u8) -> bool {
+            line == 1 && self.ef1
+        }
+    }
+
+    /// Runs `program` from address 0 for `steps` instructions in 256 bytes of RAM.
+    fn run(program: &[u8], steps: usize, io: &mut TestIo) -> (Cdp1802, Vec<u8>) {
+        let mut memory = vec![0; 0x100];
+        memory[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::new();
+        for _ in 0..steps {
+            cpu.step(&mut memory, io);
+        }
+        (cpu, memory)
+    }
+
+    #[test]
+    fn loads_and_stores_through_registers() {
+        // R3 = 0x0040 via PLO/PHI, store 0xAB there, read it back with LDN and LDA.
+        let program = [0xF8, 0x40, 0xA3, 0xF8, 0x00, 0xB3, 0xF8, 0xAB, 0x53, 0xF8, 0x00, 0x03, 0xF8, 0x00, 0x43];
+        let (cpu, memory) = run(&program, 10, &mut TestIo::default());
+        assert_eq!(memory[0x40], 0xAB);
+        assert_eq!(cpu.d, 0xAB);
+        assert_eq!(cpu.r[3], 0x41);
+        assert_eq!(cpu.r[0], program.len() as u16);
+    }
+
+    #[test]
+    fn arithmetic_sets_df_as_carry_and_not_borrow() {
+        let mut cpu = Cdp1802::new();
+        let mut io = TestIo::default();
+        // LDI F0, ADI 20, ADCI 00, SMI 12, SMBI 00, SDI 00
+        let mut memory = vec![0xF8, 0xF0, 0xFC, 0x20, 0x7C, 0x00, 0xFF, 0x12, 0x7F, 0x00, 0xFD, 0x00];
+        let mut expect = |d: u8, df: bool| {
+            cpu.step(&mut memory, &mut io);
+            assert_eq!((cpu.d, cpu.df), (d, df));
+        };
+        expect(0xF0, false);
+        expect(0x10, true);
+        expect(0x11, false);
+        expect(0xFF, false);
+        expect(0xFE, true);
+        expect(0x02, false);
+    }
+
+    #[test]
+    fn shifts_move_bits_through_df() {
+        // LDI 81, SHR, SHLC, SHL, RSHR
+        let (cpu, _) = run(&[0xF8, 0x81, 0xF6], 2, &mut TestIo::default());
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        let (cpu, _) = run(&[0xF8, 0x81, 0xF6, 0x7E], 3, &mut TestIo::default());
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+        let (cpu, _) = run(&[0xF8, 0x81, 0xFE, 0x76], 3, &mut TestIo::default());
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+    }
+
+    #[test]
+    fn short_and_long_branches() {
+        let program = [
+            0x7B,             // 00 SEQ
+            0x31, 0x05,       // 01 BQ 05
+            0xF8, 0x01,       // 03 LDI 01 (skipped)
+            0x32, 0x09,       // 05 BZ 09
+            0xF8, 0x02,       // 07 LDI 02 (skipped)
+            0xC4,             // 09 NOP
+            0xCE,             // 0A LSZ
+            0xF8, 0x03,       // 0B LDI 03 (skipped)
+            0x34, 0x11,       // 0D B1 11
+            0xF8, 0x04,       // 0F LDI 04 (skipped)
+            0x39, 0x00,       // 11 BNQ 00 (not taken)
+            0xC0, 0x00, 0x80, // 13 LBR 0080
+        ];
+        let mut memory = vec![0; 0x100];
+        memory[..program.len()].copy_from_slice(&program);
+        let mut cpu = Cdp1802::new();
+        let mut io = TestIo { ef1: true, ..TestIo::default() };
+        let cycles: Vec<u8> = (0..8).map(|_| cpu.step(&mut memory, &mut io)).collect();
+        assert_eq!(cycles, [2, 2, 2, 3, 3, 2, 2, 3]);
+        assert_eq!((cpu.r[0], cpu.d), (0x80, 0));
+    }
+
+    #[test]
+    fn io_goes_through_the_x_register() {
+        // SEX 2, R2 = 0x10, OUT 3 (M(0x10) = 0x77), INP 4 into M(0x11).
+        let mut program = vec![0xE2, 0xF8, 0x10, 0xA2, 0x63, 0x6C];
+        program.resize(0x11, 0);
+        program[0x10] = 0x77;
+        let mut io = TestIo::default();
+        let (cpu, memory) = run(&program, 5, &mut io);
+        assert_eq!(io.outputs, vec![(3, 0x77)]);
+        assert_eq!((cpu.d, memory[0x11], cpu.r[2]), (0x5A, 0x5A, 0x11));
+    }
+
+    #[test]
+    fn interrupts_save_xp_and_return_restores_it() {
+        let mut cpu = Cdp1802::new();
+        cpu.x = 3;
+        cpu.p = 4;
+        cpu.interrupt();
+        assert_eq!((cpu.t, cpu.x, cpu.p, cpu.ie), (0x34, 2, 1, false));
+        // Disabled, so a second interrupt is ignored.
+        cpu.interrupt();
+        assert_eq!((cpu.x, cpu.p), (2, 1));
+
+        // R1 = 0x20 holds RET; R2 = 0x30 holds the saved XP.
+        let mut memory = vec![0; 0x100];
+        memory[0x20] = 0x70;
+        memory[0x30] = 0x34;
+        cpu.r[1] = 0x20;
+        cpu.r[2] = 0x30;
+        cpu.step(&mut memory, &mut TestIo::default());
+        assert_eq!((cpu.x, cpu.p, cpu.ie, cpu.r[2]), (3, 4, true, 0x31));
+    }
+
+    #[test]
+    fn idle_waits_for_an_interrupt() {
+        let mut memory = vec![0; 0x100];
+        let mut cpu = Cdp1802::new();
+        let mut io = TestIo::default();
+        cpu.step(&mut memory, &mut io);
+        assert!(cpu.idle);
+        assert_eq!(cpu.step(&mut memory, &mut io), 1);
+        assert_eq!(cpu.r[0], 1);
+        cpu.interrupt();
+        assert!(!cpu.idle);
+    }
+
+    #[test]
+    fn addresses_wrap_at_the_end_of_memory() {
+        // R3 = 0x0105 in 256 bytes of RAM, STR writes to 0x05.
+        let (_, memory) = run(&[0xF8, 0x05, 0xA3, 0xF8, 0x01, 0xB3, 0xF8, 0xEE, 0x53], 7, &mut TestIo::default());
+        assert_eq!(memory[0x05], 0xEE);
+    }
+}
@@ -0,0 +1,235 @@
+// Cheats: value search over RAM and the V registers to locate things like
+// lives or score, and frozen values re-applied after every instruction.
+//
+// Cheat codes are text, one per line, hex values, `#` starts a comment:
+//
+//     2F4:09      # RAM[0x2F4] = 0x09
+//     V3=09       # V3 = 0x09
+
+use std::fmt;
+
+use crate::{Chip8Emulator, NUM_GP_REGISTERS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CheatTarget {
+    Memory(u16),
+    Register(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub target: CheatTarget,
+    pub value: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.target {
+            CheatTarget::Memory(address) => write!(f, "{:03X}:{:02X}", address, self.value),
+            CheatTarget::Register(idx) => write!(f, "V{:X}={:02X}", idx, self.value),
+        }
+    }
+}
+
+impl Cheat {
+    pub fn parse_codes(text: &str) -> Result<Vec<Cheat>, CheatError> {
+        let mut cheats = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let code = line.split('#').next().unwrap().trim();
+            if code.is_empty() {
+                continue;
+            }
+            let error = |message: &str| CheatError { line: idx + 1, message: message.to_string() };
+            let hex = |s: &str| u16::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok();
+
+            let cheat = if let Some((register, value)) = code.split_once('=') {
+                let register = register.trim();
+                let idx = register
+                    .strip_prefix(['V', 'v'])
+                    .and_then(hex)
+                    .filter(|idx| (*idx as usize) < NUM_GP_REGISTERS)
+                    .ok_or_else(|| error("expected a register V0-VF"))?;
+                Cheat { target: CheatTarget::Register(idx as u8), value: parse_value(value).ok_or_else(|| error("bad value"))? }
+            } else if let Some((address, value)) = code.split_once(':') {
+                let address = hex(address).ok_or_else(|| error("bad address"))?;
+                Cheat { target: CheatTarget::Memory(address), value: parse_value(value).ok_or_else(|| error("bad value"))? }
+            } else {
+                return Err(error("expected addr:value or Vx=value"));
+            };
+            cheats.push(cheat);
+        }
+        Ok(cheats)
+    }
+
+    pub fn codes_to_text(cheats: &[Cheat]) -> String {
+        cheats.iter().map(|cheat| format!("{}\n", cheat)).collect()
+    }
+}
+
+fn parse_value(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchFilter {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+/// Iterative value search. Each filter compares the current value of every
+/// remaining candidate against the value seen at the previous step.
+pub struct CheatSearch {
+    candidates: Vec<(CheatTarget, u8)>,
+}
+
+impl CheatSearch {
+    /// Starts a search with every RAM byte and V register as a candidate.
+    pub fn new(chip8_emulator: &Chip8Emulator) -> Self {
+        let memory = (0..chip8_emulator.memory.ram.len()).map(|address| CheatTarget::Memory(address as u16));
+        let registers = (0..NUM_GP_REGISTERS).map(|idx| CheatTarget::Register(idx as u8));
+        let candidates = memory.chain(registers).map(|target| (target, chip8_emulator.cheat_value(target))).collect();
+        Self { candidates }
+    }
+
+    pub fn filter(&mut self, chip8_emulator: &Chip8Emulator, filter: SearchFilter) {
+        self.candidates.retain_mut(|(target, previous)| {
+            let current = chip8_emulator.cheat_value(*target);
+            let keep = match filter {
+                SearchFilter::Equal(value) => current == value,
+                SearchFilter::Changed => current != *previous,
+                SearchFilter::Unchanged => current == *previous,
+                SearchFilter::Increased => current > *previous,
+                SearchFilter::Decreased => current < *previous,
+            };
+            *previous = current;
+            keep
+        });
+    }
+
+    /// Remaining candidates with the value seen at the last step.
+    pub fn candidates(&self) -> &[(CheatTarget, u8)] {
+        &self.candidates
+    }
+}
+
+impl Chip8Emulator {
+    pub fn cheat_value(&self, target: CheatTarget) -> u8 {
+        match target {
+            CheatTarget::Memory(address) => self.memory.ram.get(address as usize).copied().unwrap_or(0),
+            CheatTarget::Register(idx) => self.registers.gp_registers[idx as usize],
+        }
+    }
+
+    /// Freezes a value; it is written back after every instruction. Replaces
+    /// an existing cheat on the same target.
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cheats.retain(|existing| existing.target != cheat.target);
+        self.cheats.push(cheat);
+        self.apply_cheats();
+    }
+
+    pub fn remove_cheat(&mut self, target: CheatTarget) {
+        self.cheats.retain(|existing| existing.target != target);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub(crate) fn apply_cheats(&mut self) {
+        for cheat in &self.cheats {
+            match cheat.target {
+                CheatTarget::Memory(address) => {
+                    // Addresses past the end of a smaller RAM are ignored.
+                    if let Some(byte) = self.memory.ram.get_mut(address as usize) {
+                        *byte = cheat.value;
+                    }
+                },
+                CheatTarget::Register(idx) => self.registers.gp_registers[idx as usize] = cheat.value,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_codes_and_comments() {
+        let text = "2F4:09      # lives\n\n  v3 = 0x1F\n0x300:ff\n";
+        let cheats = Cheat::parse_codes(text).unwrap();
+        assert_eq!(cheats, [
+            Cheat { target: CheatTarget::Memory(0x2F4), value: 0x09 },
+            Cheat { target: CheatTarget::Register(3), value: 0x1F },
+            Cheat { target: CheatTarget::Memory(0x300), value: 0xFF },
+        ]);
+        assert_eq!(Cheat::codes_to_text(&cheats), "2F4:09\nV3=1F\n300:FF\n");
+        assert_eq!(Cheat::parse_codes(&Cheat::codes_to_text(&cheats)).unwrap(), cheats);
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let error = |text: &str| Cheat::parse_codes(text).unwrap_err();
+        assert_eq!(error("2F4:09\nVG=01"), CheatError { line: 2, message: "expected a register V0-VF".to_string() });
+        assert_eq!(error("2F4:100").message, "bad value");
+        assert_eq!(error("zz:01").message, "bad address");
+        assert_eq!(error("# ok\nlives").line, 2);
+    }
+
+    #[test]
+    fn frozen_values_survive_execution() {
+        // V3 = 0, store V0-V3 at 0x300, loop.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x63, 0x00, 0xA3, 0x00, 0xF3, 0x55, 0x12, 0x00]);
+        chip8_emulator.add_cheat(Cheat { target: CheatTarget::Register(3), value: 7 });
+        chip8_emulator.add_cheat(Cheat { target: CheatTarget::Register(3), value: 9 });
+        assert_eq!(chip8_emulator.cheats().len(), 1);
+        for _ in 0..3 {
+            chip8_emulator.emulate_cycle();
+        }
+        // Written back right after 6300, so the store sees the frozen value.
+        assert_eq!(chip8_emulator.registers()[3], 9);
+        assert_eq!(chip8_emulator.memory()[0x303], 9);
+
+        chip8_emulator.remove_cheat(CheatTarget::Register(3));
+        chip8_emulator.emulate_cycle();
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.registers()[3], 0);
+    }
+
+    #[test]
+    fn search_narrows_down_candidates() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        // V7 = V7 + 1, loop.
+        chip8_emulator.init(&[0x77, 0x01, 0x12, 0x00]);
+        let mut search = CheatSearch::new(&chip8_emulator);
+        for _ in 0..4 {
+            chip8_emulator.emulate_cycle();
+        }
+        search.filter(&chip8_emulator, SearchFilter::Increased);
+        search.filter(&chip8_emulator, SearchFilter::Equal(2));
+        assert_eq!(search.candidates(), &[(CheatTarget::Register(7), 2)]);
+    }
+}
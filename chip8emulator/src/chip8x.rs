@@ -0,0 +1,210 @@
+// CHIP-8X, the COSMAC VIP interpreter for the VP-590 colour board. On top of
+// CHIP-8 it has:
+//
+//     02A0  step the background colour: blue, black, green, red
+//     5XY1  VX = VX + VY, each nibble added on its own modulo 8
+//     BXY0  colour zones from VX and VX+1, colour VY: the low nibble is the
+//           first zone, the high nibble how many more follow; zones are 8
+//           pixels wide and 4 rows high
+//     BXYN  colour N rows from (VX, VY), one 8-pixel column wide, colour VX+1
+//     EXF2  skip if key VX is down on the second keypad
+//     EXF5  skip if key VX is up on the second keypad
+//
+// B is taken by the colour instructions, so BNNN does not exist. The colour
+// map is kept at the finest resolution of the board, 8x1 pixels, alongside
+// the pixels; BXY0 fills whole 4-row bands of it. Unlit pixels show the
+// background colour.
+
+use crate::{Chip8Emulator, MovieRecord, HEIGHT, NUM_GP_REGISTERS, NUM_KEYS, WIDTH};
+
+pub(crate) const COLOR_COLUMNS: usize = WIDTH / 8;
+const ZONE_ROWS: usize = 4;
+/// The interpreter starts out drawing in red.
+pub(crate) const DEFAULT_FOREGROUND: u8 = 1;
+
+/// VP-590 foreground colours by code.
+pub const CHIP8X_FOREGROUNDS: [[u8; 3]; 8] = [
+    [0x00, 0x00, 0x00], // black
+    [0xFF, 0x00, 0x00], // red
+    [0x00, 0x00, 0xFF], // blue
+    [0xFF, 0x00, 0xFF], // violet
+    [0x00, 0xFF, 0x00], // green
+    [0xFF, 0xFF, 0x00], // yellow
+    [0x00, 0xFF, 0xFF], // aqua
+    [0xFF, 0xFF, 0xFF], // white
+];
+
+/// Background colours in the order 02A0 steps through them.
+pub const CHIP8X_BACKGROUNDS: [[u8; 3]; 4] = [
+    [0x00, 0x00, 0x80], // blue
+    [0x00, 0x00, 0x00], // black
+    [0x00, 0x80, 0x00], // green
+    [0x80, 0x00, 0x00], // red
+];
+
+/// 5XY1: both nibbles added separately, modulo 8.
+pub(crate) fn add_octal(vx: u8, vy: u8) -> u8 {
+    let high = ((vx >> 4) + (vy >> 4)) & 0x7;
+    let low = ((vx & 0xF) + (vy & 0xF)) & 0x7;
+    high << 4 | low
+}
+
+impl Chip8Emulator {
+    pub(crate) fn reset_colors(&mut self) {
+        self.graphic.colors = [DEFAULT_FOREGROUND; COLOR_COLUMNS * HEIGHT];
+        self.graphic.background = 0;
+    }
+
+    /// 02A0.
+    pub(crate) fn cycle_background(&mut self) {
+        self.graphic.background = (self.graphic.background + 1) % CHIP8X_BACKGROUNDS.len() as u8;
+        self.color_changed();
+    }
+
+    /// BXY0.
+    pub(crate) fn color_zones(&mut self, x: usize, y: usize) {
+        let horizontal = self.registers.gp_registers[x];
+        let vertical = self.registers.gp_registers[(x + 1) % NUM_GP_REGISTERS];
+        let color = self.registers.gp_registers[y] & 0x7;
+        let columns = (horizontal & 0xF) as usize..=(horizontal & 0xF) as usize + (horizontal >> 4) as usize;
+        let bands = (vertical & 0xF) as usize..=(vertical & 0xF) as usize + (vertical >> 4) as usize;
+        for band in bands.filter(|band| band * ZONE_ROWS < HEIGHT) {
+            for row in band * ZONE_ROWS..(band + 1) * ZONE_ROWS {
+                for column in columns.clone().filter(|column| *column < COLOR_COLUMNS) {
+                    self.graphic.colors[row * COLOR_COLUMNS + column] = color;
+                }
+            }
+        }
+        self.color_changed();
+    }
+
+    /// BXYN with N > 0.
+    pub(crate) fn color_rows(&mut self, x: usize, y: usize, n: u16) {
+        let column = self.registers.gp_registers[x] as usize / 8 % COLOR_COLUMNS;
+        let top = self.registers.gp_registers[y] as usize;
+        let color = self.registers.gp_registers[(x + 1) % NUM_GP_REGISTERS] & 0x7;
+        for row in top..top + n as usize {
+            self.graphic.colors[row % HEIGHT * COLOR_COLUMNS + column] = color;
+        }
+        self.color_changed();
+    }
+
+    fn color_changed(&mut self) {
+        self.set_draw_flag(true);
+        self.idle.side_effect();
+    }
+
+    /// Foreground colour code of every 8x1 pixel cell, row by row.
+    pub fn color_map(&self) -> &[u8] {
+        &self.graphic.colors
+    }
+
+    /// Index into `CHIP8X_BACKGROUNDS`.
+    pub fn background_color(&self) -> u8 {
+        self.graphic.background
+    }
+
+    /// Key on the second keypad, read by EXF2 and EXF5.
+    pub fn set_second_key(&mut self, index: usize, pressed: bool) {
+        if index < NUM_KEYS && self.input.second[index] != pressed {
+            self.input.second[index] = pressed;
+            self.idle.input_changed();
+            if let Some(movie) = self.movie.as_mut() {
+                movie.records.push(MovieRecord::SecondKey { frame: self.frames, cycle: self.cycles, key: index as u8, pressed });
+            }
+        }
+    }
+
+    pub(crate) fn second_key_pressed(&self, key: u8) -> bool {
+        self.input.second[(key & 0xF) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryConfig, Palette, Variant};
+
+    fn chip8x(rom: &[u8], cycles: usize) -> Chip8Emulator {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig::CHIP_8X).unwrap();
+        chip8_emulator.set_variant(Variant::Chip8X);
+        chip8_emulator.init(rom);
+        for _ in 0..cycles {
+            chip8_emulator.emulate_cycle();
+        }
+        chip8_emulator
+    }
+
+    #[test]
+    fn adds_each_nibble_modulo_8() {
+        assert_eq!(add_octal(0x12, 0x34), 0x46);
+        assert_eq!(add_octal(0x77, 0x11), 0x00);
+        assert_eq!(add_octal(0x0F, 0x01), 0x00);
+
+        // V0 = 0x25, V1 = 0x63, 5011
+        let chip8_emulator = chip8x(&[0x60, 0x25, 0x61, 0x63, 0x50, 0x11], 3);
+        assert_eq!(chip8_emulator.registers()[0], 0x00);
+    }
+
+    #[test]
+    fn background_steps_through_four_colours() {
+        let mut chip8_emulator = chip8x(&[0x02, 0xA0, 0x02, 0xA0, 0x02, 0xA0, 0x02, 0xA0], 0);
+        assert_eq!(chip8_emulator.pixel_rgb(&Palette::default(), 0), CHIP8X_BACKGROUNDS[0]);
+        let backgrounds: Vec<u8> = (0..4).map(|_| {
+            chip8_emulator.emulate_cycle();
+            chip8_emulator.background_color()
+        }).collect();
+        assert_eq!(backgrounds, [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn colours_zones_of_8x4_pixels() {
+        // V0 = 0x12 (columns 2-3), V1 = 0x01 (band 1), V2 = green, B020
+        let chip8_emulator = chip8x(&[0x60, 0x12, 0x61, 0x01, 0x62, 0x04, 0xB0, 0x20], 4);
+        let colors = chip8_emulator.color_map();
+        for row in 0..8 {
+            let expected = if row >= 4 { [DEFAULT_FOREGROUND, DEFAULT_FOREGROUND, 4, 4, DEFAULT_FOREGROUND] } else { [DEFAULT_FOREGROUND; 5] };
+            assert_eq!(colors[row * COLOR_COLUMNS..row * COLOR_COLUMNS + 5], expected, "row {}", row);
+        }
+    }
+
+    #[test]
+    fn colours_rows_wrapping_at_the_bottom() {
+        // V3 = 16 (column 2), V4 = white, V5 = 30, B353
+        let chip8_emulator = chip8x(&[0x63, 0x10, 0x64, 0x07, 0x65, 0x1E, 0xB3, 0x53], 4);
+        let colors = chip8_emulator.color_map();
+        for row in [30, 31, 0] {
+            assert_eq!(colors[row * COLOR_COLUMNS + 2], 7);
+        }
+        assert_eq!(colors[COLOR_COLUMNS + 2], DEFAULT_FOREGROUND);
+    }
+
+    #[test]
+    fn lit_pixels_take_the_zone_colour() {
+        // V0 = 0x00, V1 = 0x00, V2 = green, B020, draw the font's first row at 0,0
+        let chip8_emulator = chip8x(&[0x62, 0x04, 0xB0, 0x20, 0xA0, 0x00, 0xD0, 0x01], 4);
+        assert_eq!(chip8_emulator.pixel_rgb(&Palette::default(), 0), CHIP8X_FOREGROUNDS[4]);
+        assert_eq!(chip8_emulator.pixel_rgb(&Palette::default(), 4), CHIP8X_BACKGROUNDS[0]);
+    }
+
+    #[test]
+    fn second_keypad_skips() {
+        // V6 = 5, EXF2 skips over a jump, EXF5 does not.
+        let rom = [0x66, 0x05, 0xE6, 0xF2, 0x13, 0x00, 0xE6, 0xF5, 0x13, 0x00];
+        let mut chip8_emulator = chip8x(&rom, 0);
+        chip8_emulator.set_second_key(5, true);
+        for _ in 0..3 {
+            chip8_emulator.emulate_cycle();
+        }
+        assert_eq!(chip8_emulator.program_counter(), 0x308);
+
+        // Only the second keypad counts.
+        let mut chip8_emulator = chip8x(&rom, 0);
+        chip8_emulator.set_key(5, true);
+        for _ in 0..3 {
+            chip8_emulator.emulate_cycle();
+        }
+        assert_eq!(chip8_emulator.program_counter(), 0x300);
+    }
+}
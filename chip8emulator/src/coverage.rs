@@ -0,0 +1,249 @@
+// Code coverage: which addresses were executed as instructions, read as data
+// (DXYN sprites, FX65) and written (FX33, FX55). Reports are listings over
+// the ROM where executed words are disassembled and everything else is shown
+// as data bytes, so code and sprite data separate naturally.
+
+use std::fmt::Write;
+
+use crate::disasm::disassemble;
+use crate::protection::Access;
+use crate::Chip8Emulator;
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    flags: Vec<u8>,
+    rom: Vec<u8>,
+    rom_start: usize,
+}
+
+/// Counts of ROM bytes by how they were touched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoverageSummary {
+    pub rom_bytes: usize,
+    pub executed: usize,
+    pub read: usize,
+    pub written: usize,
+    pub untouched: usize,
+}
+
+enum Line {
+    Instruction(usize, u16),
+    Data(usize, u8),
+}
+
+impl Coverage {
+    fn new(memory_size: usize) -> Self {
+        Self { flags: vec![0; memory_size], rom: Vec::new(), rom_start: 0 }
+    }
+
+    /// Follows a change of RAM size; flags past the new end are dropped.
+    pub(crate) fn resize(&mut self, memory_size: usize) {
+        self.flags.resize(memory_size, 0);
+    }
+
+    pub(crate) fn record(&mut self, accesses: &[Access]) {
+        for access in accesses {
+            let (range, flag) = match access {
+                Access::Execute(address) => (*address..*address + 2, EXECUTED),
+                Access::Read(range) => (range.clone(), READ),
+                Access::Write(range) => (range.clone(), WRITTEN),
+            };
+            let end = range.end.min(self.flags.len());
+            for address in range.start..end {
+                self.flags[address] |= flag;
+            }
+        }
+    }
+
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.flags.get(address).is_some_and(|flags| flags & EXECUTED != 0)
+    }
+
+    pub fn is_read(&self, address: usize) -> bool {
+        self.flags.get(address).is_some_and(|flags| flags & READ != 0)
+    }
+
+    pub fn is_written(&self, address: usize) -> bool {
+        self.flags.get(address).is_some_and(|flags| flags & WRITTEN != 0)
+    }
+
+    pub fn summary(&self) -> CoverageSummary {
+        let rom = &self.flags[self.rom_start..self.rom_start + self.rom.len()];
+        let count = |flag: u8| rom.iter().filter(|flags| *flags & flag != 0).count();
+        CoverageSummary {
+            rom_bytes: rom.len(),
+            executed: count(EXECUTED),
+            read: count(READ),
+            written: count(WRITTEN),
+            untouched: rom.iter().filter(|flags| **flags == 0).count(),
+        }
+    }
+
+    /// Executed words become instructions; any other byte is listed on its own.
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = self.rom_start + offset;
+            if self.is_executed(address) && offset + 1 < self.rom.len() {
+                lines.push(Line::Instruction(address, (self.rom[offset] as u16) << 8 | self.rom[offset + 1] as u16));
+                offset += 2;
+            } else {
+                lines.push(Line::Data(address, self.rom[offset]));
+                offset += 1;
+            }
+        }
+        lines
+    }
+
+    fn marks(&self, address: usize) -> String {
+        [(EXECUTED, 'X'), (READ, 'R'), (WRITTEN, 'W')]
+            .iter()
+            .map(|(flag, mark)| if self.flags[address] & flag != 0 { *mark } else { '.' })
+            .collect()
+    }
+
+    /// Plain-text listing: address, X/R/W marks, bytes and disassembly or a
+    /// bit picture of the data byte.
+    pub fn to_listing(&self) -> String {
+        let summary = self.summary();
+        let mut text = String::new();
+        writeln!(text, "; {} ROM bytes: {} executed, {} read, {} written, {} untouched",
+            summary.rom_bytes, summary.executed, summary.read, summary.written, summary.untouched).unwrap();
+        for line in self.lines() {
+            match line {
+                Line::Instruction(address, opcode) => {
+                    writeln!(text, "{:03X}  {}  {:04X}  {}", address, self.marks(address), opcode, disassemble(opcode)).unwrap();
+                },
+                Line::Data(address, byte) => {
+                    writeln!(text, "{:03X}  {}  {:02X}    DB {:#04x}  ; {}", address, self.marks(address), byte, byte, bit_picture(byte)).unwrap();
+                },
+            }
+        }
+        text
+    }
+
+    pub fn to_html(&self) -> String {
+        let summary = self.summary();
+        let mut html = String::from(concat!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>CHIP-8 coverage</title><style>\n",
+            "body { font-family: monospace; } td { padding: 0 8px; white-space: pre; }\n",
+            ".code { background: #c8f0c8; } .data { background: #c8dcf8; } .written { background: #f8e0b0; }\n",
+            ".dead { background: #f0c8c8; }\n",
+            "</style></head><body>\n",
+        ));
+        writeln!(html, "<p>{} ROM bytes: {} executed, {} read, {} written, {} untouched</p>\n<table>",
+            summary.rom_bytes, summary.executed, summary.read, summary.written, summary.untouched).unwrap();
+        for line in self.lines() {
+            let (address, bytes, text) = match line {
+                Line::Instruction(address, opcode) => (address, format!("{:04X}", opcode), disassemble(opcode)),
+                Line::Data(address, byte) => (address, format!("{:02X}", byte), format!("DB {:#04x}  {}", byte, bit_picture(byte))),
+            };
+            let flags = self.flags[address];
+            let class = if flags & EXECUTED != 0 {
+                "code"
+            } else if flags & READ != 0 {
+                "data"
+            } else if flags & WRITTEN != 0 {
+                "written"
+            } else {
+                "dead"
+            };
+            writeln!(html, "<tr class=\"{}\"><td>{:03X}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                class, address, self.marks(address), bytes, text).unwrap();
+        }
+        html.push_str("</table>\n</body></html>\n");
+        html
+    }
+}
+
+fn bit_picture(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+impl Chip8Emulator {
+    /// Starts tracking coverage. It survives `reset`, so it can span a whole
+    /// movie playback.
+    pub fn start_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.memory.ram.len()));
+    }
+
+    /// Ends tracking and returns the coverage over the currently loaded ROM.
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        let mut coverage = self.coverage.take()?;
+        coverage.rom = self.rom.clone();
+        coverage.rom_start = self.memory.config.program_start as usize;
+        Some(coverage)
+    }
+
+    pub(crate) fn record_coverage(&mut self, opcode: u16) {
+        if self.coverage.is_some() {
+            let accesses = self.memory_accesses(opcode);
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(&accesses);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cover() -> Coverage {
+        // I = 0x206, draw the 1-byte sprite there, spin; 0x207 is never touched.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0xF0, 0xAA]);
+        chip8_emulator.start_coverage();
+        for _ in 0..4 {
+            chip8_emulator.emulate_cycle();
+        }
+        chip8_emulator.stop_coverage().unwrap()
+    }
+
+    #[test]
+    fn separates_code_from_sprite_data() {
+        let coverage = cover();
+        assert!(coverage.is_executed(0x202) && !coverage.is_read(0x202));
+        assert!(coverage.is_read(0x206) && !coverage.is_executed(0x206));
+        assert!(!coverage.is_read(0x207) && !coverage.is_written(0x207));
+        assert_eq!(coverage.summary(), CoverageSummary { rom_bytes: 8, executed: 6, read: 1, written: 0, untouched: 1 });
+    }
+
+    #[test]
+    fn listing_disassembles_code_and_pictures_data() {
+        let listing = cover().to_listing();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "; 8 ROM bytes: 6 executed, 1 read, 0 written, 1 untouched");
+        assert_eq!(lines.len(), 6);
+        assert!(lines[1].starts_with("200  X..  A206  "));
+        assert_eq!(lines[4], "206  .R.  F0    DB 0xf0  ; ####....");
+        assert_eq!(lines[5], "207  ...  AA    DB 0xaa  ; #.#.#.#.");
+    }
+
+    #[test]
+    fn follows_a_larger_memory_config() {
+        // Started with 4 KiB, then an 8 KiB ROM that spins at its start.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.start_coverage();
+        chip8_emulator.set_memory_config(crate::MemoryConfig { ram_size: 0x2000, ..crate::MemoryConfig::default() }).unwrap();
+        let mut rom = vec![0; 0x1E00];
+        rom[..2].copy_from_slice(&[0x12, 0x00]);
+        chip8_emulator.init(&rom);
+        chip8_emulator.emulate_cycle();
+        let coverage = chip8_emulator.stop_coverage().unwrap();
+        assert!(coverage.is_executed(0x200));
+        assert_eq!(coverage.summary().rom_bytes, 0x1E00);
+    }
+
+    #[test]
+    fn html_marks_untouched_bytes_as_dead() {
+        let html = cover().to_html();
+        assert!(html.contains("<tr class=\"data\"><td>206</td>"));
+        assert!(html.contains("<tr class=\"dead\"><td>207</td>"));
+    }
+}
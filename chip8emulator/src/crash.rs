@@ -0,0 +1,379 @@
+// Crash dumps. Invalid opcodes, stack overflow or underflow and memory traps
+// stop the machine before the offending instruction runs and capture the
+// full state, the ROM, the configuration and the last instructions executed.
+// Loading a dump puts the machine back right before the crash, so the next
+// `emulate_cycle` reproduces it.
+//
+// The file format is plain text, one field per line:
+//
+//     chip8-crash 1
+//     reason <reason>
+//     at <pc> <opcode>
+//     rom <sha1>
+//     quirks <key_wait_on_press>
+//     protection <Off|Report|Trap>
+//     layout <Separate|Vip>
+//     variant <Chip8|Chip8X|HiRes>
+//     memory <ram size> <program start> <font address>
+//     font <hex font bytes>
+//     seed <rng seed>
+//     trace <pc> <opcode> ; <disassembly>     (oldest first)
+//     rom-data <hex bytes>
+//     state <hex snapshot bytes>
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+
+use crate::disasm::disassemble;
+use crate::hash::{sha1, to_hex};
+use crate::{Chip8Emulator, CycleState, Font, MemoryConfig, MemoryFaultKind, MemoryLayout, MemoryProtection, Quirks, Snapshot, SnapshotError, Variant};
+
+const MAGIC: &str = "chip8-crash 1";
+pub(crate) const CRASH_HISTORY_LEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashReason {
+    InvalidOpcode,
+    StackOverflow,
+    StackUnderflow,
+    Memory { kind: MemoryFaultKind, address: usize },
+}
+
+impl fmt::Display for CrashReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrashReason::InvalidOpcode => write!(f, "InvalidOpcode"),
+            CrashReason::StackOverflow => write!(f, "StackOverflow"),
+            CrashReason::StackUnderflow => write!(f, "StackUnderflow"),
+            CrashReason::Memory { kind, address } => write!(f, "{:?} {:#05x}", kind, address),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrashDump {
+    pub reason: CrashReason,
+    pub pc: u16,
+    pub opcode: u16,
+    pub rom_sha1: String,
+    pub quirks: Quirks,
+    pub memory_protection: MemoryProtection,
+    pub memory_layout: MemoryLayout,
+    pub variant: Variant,
+    pub memory_config: MemoryConfig,
+    pub font: Font,
+    pub rng_seed: u64,
+    /// Last executed instructions as (pc, opcode), oldest first.
+    pub history: Vec<(u16, u16)>,
+    pub rom: Vec<u8>,
+    pub state: Snapshot,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CrashDumpError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CrashDumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CrashDumpError {}
+
+impl fmt::Display for CrashDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at PC {:#05x} (opcode {:#06x})", self.reason, self.pc, self.opcode)
+    }
+}
+
+impl CrashDump {
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", MAGIC);
+        writeln!(out, "reason {}", self.reason).unwrap();
+        writeln!(out, "at {:03x} {:04x}", self.pc, self.opcode).unwrap();
+        writeln!(out, "rom {}", self.rom_sha1).unwrap();
+        writeln!(out, "quirks {}", self.quirks.key_wait_on_press as u8).unwrap();
+        writeln!(out, "protection {:?}", self.memory_protection).unwrap();
+        writeln!(out, "layout {:?}", self.memory_layout).unwrap();
+        writeln!(out, "variant {:?}", self.variant).unwrap();
+        let config = &self.memory_config;
+        writeln!(out, "memory {:x} {:03x} {:03x}", config.ram_size, config.program_start, config.font_address).unwrap();
+        writeln!(out, "font {}", to_hex(&self.font.as_bytes())).unwrap();
+        writeln!(out, "seed {}", self.rng_seed).unwrap();
+        for (pc, opcode) in &self.history {
+            writeln!(out, "trace {:03x} {:04x} ; {}", pc, opcode, disassemble(*opcode)).unwrap();
+        }
+        writeln!(out, "rom-data {}", to_hex(&self.rom)).unwrap();
+        writeln!(out, "state {}", to_hex(self.state.as_bytes())).unwrap();
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, CrashDumpError> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line.split(';').next().unwrap().trim()));
+        match lines.next() {
+            Some((_, MAGIC)) => (),
+            _ => return Err(CrashDumpError { line: 1, message: "not a crash dump".to_string() }),
+        }
+
+        let (mut reason, mut at, mut rom_sha1, mut rom, mut state) = (None, None, None, None, None);
+        let mut quirks = Quirks::default();
+        let mut memory_protection = MemoryProtection::Off;
+        let mut memory_layout = MemoryLayout::Separate;
+        let mut variant = Variant::Chip8;
+        let mut memory_config = MemoryConfig::default();
+        let mut font = Font::default();
+        let mut rng_seed = 0;
+        let mut history = Vec::new();
+
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let error = |message: &str| CrashDumpError { line, message: message.to_string() };
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let hex = |idx: usize| fields.get(idx).and_then(|s| u16::from_str_radix(s, 16).ok()).ok_or_else(|| error("bad number"));
+            let bytes = || fields.get(1).and_then(|s| from_hex(s)).ok_or_else(|| error("bad hex data"));
+
+            match fields[0] {
+                "reason" => reason = Some(parse_reason(&fields[1..]).ok_or_else(|| error("unknown reason"))?),
+                "at" => at = Some((hex(1)?, hex(2)?)),
+                "rom" => rom_sha1 = Some(fields.get(1).ok_or_else(|| error("missing hash"))?.to_string()),
+                "quirks" => quirks.key_wait_on_press = fields.get(1) == Some(&"1"),
+                "protection" => memory_protection = fields.get(1).copied().and_then(parse_protection).ok_or_else(|| error("unknown protection mode"))?,
+                "layout" => memory_layout = fields.get(1).copied().and_then(parse_layout).ok_or_else(|| error("unknown memory layout"))?,
+                "variant" => variant = fields.get(1).copied().and_then(parse_variant).ok_or_else(|| error("unknown variant"))?,
+                "memory" => {
+                    let ram_size = fields.get(1).and_then(|s| usize::from_str_radix(s, 16).ok()).ok_or_else(|| error("bad number"))?;
+                    memory_config = MemoryConfig { ram_size, program_start: hex(2)?, font_address: hex(3)? };
+                    memory_config.validate().map_err(|err| error(&err.to_string()))?;
+                },
+                "font" => font = Font::from_bytes(&bytes()?).map_err(|err| error(&err.to_string()))?,
+                "seed" => rng_seed = fields.get(1).and_then(|s| s.parse().ok()).ok_or_else(|| error("bad number"))?,
+                "trace" => history.push((hex(1)?, hex(2)?)),
+                "rom-data" => rom = Some(bytes()?),
+                "state" => state = Some(Snapshot::from_bytes(bytes()?).ok_or_else(|| error("invalid machine state"))?),
+                _ => return Err(error("unknown field")),
+            }
+        }
+
+        let missing = |field: &str| CrashDumpError { line: text.lines().count(), message: format!("missing {}", field) };
+        let (pc, opcode) = at.ok_or_else(|| missing("at"))?;
+        let rom = rom.ok_or_else(|| missing("rom-data"))?;
+        let rom_sha1 = rom_sha1.ok_or_else(|| missing("rom"))?;
+        if to_hex(&sha1(&rom)) != rom_sha1 {
+            return Err(CrashDumpError { line: 1, message: "ROM data does not match the recorded hash".to_string() });
+        }
+        let state = state.ok_or_else(|| missing("state"))?;
+        if state.memory_size() != memory_config.ram_size || rom.len() > memory_config.capacity() {
+            return Err(CrashDumpError { line: 1, message: "ROM or state does not fit the memory configuration".to_string() });
+        }
+        memory_config.check_font(font.len()).map_err(|err| CrashDumpError { line: 1, message: err.to_string() })?;
+        Ok(Self {
+            reason: reason.ok_or_else(|| missing("reason"))?,
+            pc,
+            opcode,
+            rom_sha1,
+            quirks,
+            memory_protection,
+            memory_layout,
+            variant,
+            memory_config,
+            font,
+            rng_seed,
+            history,
+            rom,
+            state,
+        })
+    }
+}
+
+fn parse_reason(fields: &[&str]) -> Option<CrashReason> {
+    let kind = match *fields.first()? {
+        "InvalidOpcode" => return Some(CrashReason::InvalidOpcode),
+        "StackOverflow" => return Some(CrashReason::StackOverflow),
+        "StackUnderflow" => return Some(CrashReason::StackUnderflow),
+        "ReservedWrite" => MemoryFaultKind::ReservedWrite,
+        "OutOfBounds" => MemoryFaultKind::OutOfBounds,
+        "DataExecution" => MemoryFaultKind::DataExecution,
+        "RunawayPc" => MemoryFaultKind::RunawayPc,
+        _ => return None,
+    };
+    let address = usize::from_str_radix(fields.get(1)?.trim_start_matches("0x"), 16).ok()?;
+    Some(CrashReason::Memory { kind, address })
+}
+
+pub(crate) fn parse_protection(field: &str) -> Option<MemoryProtection> {
+    match field {
+        "Off" => Some(MemoryProtection::Off),
+        "Report" => Some(MemoryProtection::Report),
+        "Trap" => Some(MemoryProtection::Trap),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_layout(field: &str) -> Option<MemoryLayout> {
+    match field {
+        "Separate" => Some(MemoryLayout::Separate),
+        "Vip" => Some(MemoryLayout::Vip),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_variant(field: &str) -> Option<Variant> {
+    match field {
+        "Chip8" => Some(Variant::Chip8),
+        "Chip8X" => Some(Variant::Chip8X),
+        "HiRes" => Some(Variant::HiRes),
+        _ => None,
+    }
+}
+
+pub(crate) fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok()).collect()
+}
+
+pub(crate) fn push_history(history: &mut VecDeque<(u16, u16)>, pc: u16, opcode: u16) {
+    if history.len() == CRASH_HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back((pc, opcode));
+}
+
+impl Chip8Emulator {
+    /// Stops the machine in front of the instruction at `pc` and captures a dump.
+    pub(crate) fn crash(&mut self, reason: CrashReason, pc: u16, opcode: u16) -> CycleState {
+        self.registers.program_counter = pc;
+        self.crash_reason = Some(reason);
+        self.crash_dump = Some(CrashDump {
+            reason,
+            pc,
+            opcode,
+            rom_sha1: self.rom_sha1(),
+            quirks: self.quirks,
+            memory_protection: self.memory_protection(),
+            memory_layout: self.memory_layout(),
+            variant: self.variant(),
+            memory_config: self.memory_config(),
+            font: self.font.clone(),
+            rng_seed: self.rng_seed,
+            history: self.history.iter().copied().collect(),
+            rom: self.rom.clone(),
+            state: self.snapshot(),
+        });
+        CycleState::Halted
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.crash_reason.is_some()
+    }
+
+    /// The dump of a crash that happened since the last call, if any.
+    pub fn take_crash_dump(&mut self) -> Option<CrashDump> {
+        self.crash_dump.take()
+    }
+
+    /// Rebuilds the machine from a dump, stopped right before the crashing
+    /// instruction. Fails if the dump's memory configuration is invalid or
+    /// does not fit its ROM, or if its state was taken with extensions other
+    /// than the registered ones.
+    pub fn load_crash_dump(&mut self, dump: &CrashDump) -> Result<(), SnapshotError> {
+        self.set_quirks(dump.quirks);
+        self.set_memory_protection(dump.memory_protection);
+        self.set_rng_seed(dump.rng_seed);
+        self.font = dump.font.clone();
+        self.set_variant(dump.variant);
+        self.set_memory_config(dump.memory_config).map_err(SnapshotError::Config)?;
+        self.try_init(&dump.rom).map_err(SnapshotError::Config)?;
+        self.restore(&dump.state)?;
+        self.set_memory_layout(dump.memory_layout);
+        self.history = dump.history.iter().copied().collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryConfigError;
+
+    fn crash_dump() -> CrashDump {
+        // V1 = 5, then an invalid opcode.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_protection(MemoryProtection::Report);
+        chip8_emulator.set_rng_seed(42);
+        chip8_emulator.init(&[0x61, 0x05, 0xFF, 0xFF]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Halted);
+        chip8_emulator.take_crash_dump().unwrap()
+    }
+
+    #[test]
+    fn dumps_survive_a_text_round_trip() {
+        let dump = crash_dump();
+        assert_eq!(dump.reason, CrashReason::InvalidOpcode);
+        assert_eq!((dump.pc, dump.opcode), (0x202, 0xFFFF));
+        assert_eq!(dump.history, vec![(0x200, 0x6105)]);
+
+        let text = dump.to_text();
+        assert!(text.starts_with("chip8-crash 1\nreason InvalidOpcode\nat 202 ffff\n"));
+        assert_eq!(CrashDump::parse(&text).unwrap(), dump);
+    }
+
+    #[test]
+    fn loading_a_dump_reproduces_the_crash() {
+        let dump = CrashDump::parse(&crash_dump().to_text()).unwrap();
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.load_crash_dump(&dump).unwrap();
+        assert_eq!(chip8_emulator.program_counter(), 0x202);
+        assert_eq!(chip8_emulator.registers()[1], 5);
+        assert_eq!(chip8_emulator.memory_protection(), MemoryProtection::Report);
+
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Halted);
+        assert_eq!(chip8_emulator.take_crash_dump().unwrap().reason, CrashReason::InvalidOpcode);
+    }
+
+    #[test]
+    fn loading_an_edited_dump_fails_instead_of_panicking() {
+        let mut dump = crash_dump();
+        dump.memory_config.ram_size = 0x123;
+        let mut chip8_emulator = Chip8Emulator::new();
+        assert_eq!(chip8_emulator.load_crash_dump(&dump), Err(SnapshotError::Config(MemoryConfigError::RamSize(0x123))));
+
+        let mut dump = crash_dump();
+        dump.rom = vec![0; 0xF00];
+        assert_eq!(chip8_emulator.load_crash_dump(&dump), Err(SnapshotError::Config(MemoryConfigError::RomTooLarge { len: 0xF00, capacity: 0xE00 })));
+    }
+
+    #[test]
+    fn parses_memory_fault_reasons() {
+        assert_eq!(parse_reason(&["OutOfBounds", "0x1000"]), Some(CrashReason::Memory { kind: MemoryFaultKind::OutOfBounds, address: 0x1000 }));
+        assert_eq!(parse_reason(&["RunawayPc"]), None);
+        assert_eq!(parse_reason(&["Overheated"]), None);
+    }
+
+    #[test]
+    fn rejects_broken_dumps() {
+        let text = crash_dump().to_text();
+        assert_eq!(CrashDump::parse("chip8-movie 1\n").unwrap_err().line, 1);
+
+        let unknown = text.replacen("seed", "sneed", 1);
+        let error = CrashDump::parse(&unknown).unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (11, "unknown field"));
+
+        let tampered = text.replacen("rom-data 6105", "rom-data 6106", 1);
+        assert!(CrashDump::parse(&tampered).unwrap_err().message.contains("does not match"));
+
+        let truncated: String = text.lines().filter(|line| !line.starts_with("state")).map(|line| format!("{}\n", line)).collect();
+        assert_eq!(CrashDump::parse(&truncated).unwrap_err().message, "missing state");
+    }
+
+    #[test]
+    fn hex_decoding_rejects_odd_and_invalid_input() {
+        assert_eq!(from_hex("00ff7a"), Some(vec![0x00, 0xFF, 0x7A]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -0,0 +1,445 @@
+// Debugger with breakpoints, watchpoints and reverse execution.
+//
+// While attached, the debugger owns the machine's time: it steps
+// instructions, ticks the timers every `cycles_per_frame` instructions and
+// logs every key change against the cycle it happened on. A snapshot is
+// taken every `checkpoint_interval` cycles. Going backwards restores the
+// nearest earlier checkpoint and re-executes from there with the logged
+// events, which lands on exactly the same state. Stepping forward again
+// after going back replays the log until the end of history is reached;
+// new input while in the past discards the old future.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Range;
+
+use crate::protection::Access;
+use crate::{Chip8Emulator, Snapshot};
+
+const MAX_CHECKPOINTS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// A single step completed.
+    Step,
+    /// PC reached a breakpoint; the instruction there has not run yet.
+    Breakpoint { pc: u16 },
+    /// The instruction at `pc` wrote to a watched address.
+    Watchpoint { address: usize, pc: u16, opcode: u16 },
+    /// The run limit was reached without hitting anything.
+    CycleLimit,
+    /// The machine refused to execute (e.g. a memory protection trap).
+    Stalled,
+    /// Went back as far as the recorded history allows.
+    StartOfHistory,
+}
+
+/// The instruction executed on `cycle` that wrote a memory cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteHit {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+#[derive(Clone, Copy)]
+enum Event {
+    Key { key: u8, pressed: bool },
+    TimerTick,
+}
+
+struct Checkpoint {
+    cycle: u64,
+    event_index: usize,
+    snapshot: Snapshot,
+}
+
+struct Executed {
+    pc: u16,
+    opcode: u16,
+    write: Option<Range<usize>>,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<usize>,
+    cycles_per_frame: u64,
+    checkpoint_interval: u64,
+    checkpoints: VecDeque<Checkpoint>,
+    events: VecDeque<(u64, Event)>,
+    /// Absolute index of `events[0]`; older events are dropped with their checkpoints.
+    events_base: usize,
+    /// Absolute index of the next event to replay.
+    cursor: usize,
+    /// Cycle count at the end of recorded history.
+    end_cycle: u64,
+}
+
+impl Debugger {
+    pub fn new(cycles_per_frame: u64, checkpoint_interval: u64) -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            cycles_per_frame: cycles_per_frame.max(1),
+            checkpoint_interval: checkpoint_interval.max(1),
+            checkpoints: VecDeque::new(),
+            events: VecDeque::new(),
+            events_base: 0,
+            cursor: 0,
+            end_cycle: 0,
+        }
+    }
+
+    /// Starts recording history from the emulator's current state.
+    pub fn attach(&mut self, chip8_emulator: &Chip8Emulator) {
+        self.checkpoints.clear();
+        self.events.clear();
+        self.events_base = 0;
+        self.cursor = 0;
+        self.end_cycle = chip8_emulator.cycles();
+        self.checkpoints.push_back(Checkpoint {
+            cycle: chip8_emulator.cycles(),
+            event_index: 0,
+            snapshot: chip8_emulator.snapshot(),
+        });
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) {
+        self.watchpoints.remove(&address);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// True while the emulator is behind the end of recorded history.
+    pub fn is_replaying(&self, chip8_emulator: &Chip8Emulator) -> bool {
+        chip8_emulator.cycles() < self.end_cycle || self.cursor < self.events_end()
+    }
+
+    /// Logged key change. Pressing a key in the past discards the old future.
+    pub fn set_key(&mut self, chip8_emulator: &mut Chip8Emulator, key: usize, pressed: bool) {
+        if self.is_replaying(chip8_emulator) {
+            self.truncate_history(chip8_emulator);
+        }
+        self.events.push_back((chip8_emulator.cycles(), Event::Key { key: key as u8, pressed }));
+        self.cursor += 1;
+        chip8_emulator.set_key(key, pressed);
+    }
+
+    pub fn step(&mut self, chip8_emulator: &mut Chip8Emulator) -> StopReason {
+        match self.step_once(chip8_emulator) {
+            Some(executed) => self.watch_hit(&executed).unwrap_or(StopReason::Step),
+            None => StopReason::Stalled,
+        }
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit, or `max_cycles` instructions.
+    pub fn run(&mut self, chip8_emulator: &mut Chip8Emulator, max_cycles: u64) -> StopReason {
+        for _ in 0..max_cycles {
+            let executed = match self.step_once(chip8_emulator) {
+                Some(executed) => executed,
+                None => return StopReason::Stalled,
+            };
+            if let Some(hit) = self.watch_hit(&executed) {
+                return hit;
+            }
+            let pc = chip8_emulator.program_counter();
+            if self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint { pc };
+            }
+        }
+        StopReason::CycleLimit
+    }
+
+    /// Goes back to the state right before the previous instruction ran.
+    pub fn step_back(&mut self, chip8_emulator: &mut Chip8Emulator) -> StopReason {
+        let current = chip8_emulator.cycles();
+        if current <= self.history_start() {
+            return StopReason::StartOfHistory;
+        }
+        self.seek(chip8_emulator, current - 1);
+        StopReason::Step
+    }
+
+    /// Goes back to the most recent breakpoint or watchpoint hit before the current cycle.
+    pub fn reverse_continue(&mut self, chip8_emulator: &mut Chip8Emulator) -> StopReason {
+        let current = chip8_emulator.cycles();
+        let hit = self.search_back(chip8_emulator, current, |debugger, position, pc, executed| {
+            match executed {
+                Some(executed) => debugger.watch_hit(executed).filter(|_| position < current),
+                None if position < current && debugger.breakpoints.contains(&pc) => Some(StopReason::Breakpoint { pc }),
+                None => None,
+            }
+        });
+
+        match hit {
+            Some((position, reason)) => {
+                self.seek(chip8_emulator, position);
+                reason
+            },
+            None => {
+                let start = self.history_start();
+                self.seek(chip8_emulator, start);
+                StopReason::StartOfHistory
+            },
+        }
+    }
+
+    /// Finds the last instruction before the current cycle that wrote `address`,
+    /// without moving the emulator.
+    pub fn last_write(&mut self, chip8_emulator: &mut Chip8Emulator, address: usize) -> Option<WriteHit> {
+        let current = chip8_emulator.cycles();
+        let snapshot = chip8_emulator.snapshot();
+        let cursor = self.cursor;
+
+        let hit = self.search_back(chip8_emulator, current, |_, position, _, executed| {
+            executed
+                .filter(|executed| executed.write.as_ref().is_some_and(|range| range.contains(&address)))
+                .map(|executed| WriteHit { cycle: position - 1, pc: executed.pc, opcode: executed.opcode })
+        });
+
+        chip8_emulator.restore(&snapshot).expect("snapshot was just taken from this machine");
+        self.cursor = cursor;
+        hit.map(|(_, write)| write)
+    }
+
+    /// Replays checkpoint segments from newest to oldest looking for the latest
+    /// position before `current` where `matcher` returns something. The matcher
+    /// sees every position with the PC there, once before execution (`None`)
+    /// and once after the instruction that led to it (`Some`).
+    fn search_back<T>(
+        &mut self,
+        chip8_emulator: &mut Chip8Emulator,
+        current: u64,
+        mut matcher: impl FnMut(&Self, u64, u16, Option<&Executed>) -> Option<T>,
+    ) -> Option<(u64, T)> {
+        let mut segment_end = current;
+        for idx in (0..self.checkpoints.len()).rev() {
+            let start = self.checkpoints[idx].cycle;
+            if start >= segment_end {
+                continue;
+            }
+            self.restore_checkpoint(chip8_emulator, idx);
+
+            let mut found = None;
+            loop {
+                let position = chip8_emulator.cycles();
+                if let Some(hit) = matcher(self, position, chip8_emulator.program_counter(), None) {
+                    found = Some((position, hit));
+                }
+                if position >= segment_end {
+                    break;
+                }
+                let executed = match self.step_once(chip8_emulator) {
+                    Some(executed) => executed,
+                    None => break,
+                };
+                let position = chip8_emulator.cycles();
+                if position <= segment_end {
+                    if let Some(hit) = matcher(self, position, chip8_emulator.program_counter(), Some(&executed)) {
+                        found = Some((position, hit));
+                    }
+                }
+            }
+
+            if found.is_some() {
+                return found;
+            }
+            segment_end = start;
+        }
+        None
+    }
+
+    fn seek(&mut self, chip8_emulator: &mut Chip8Emulator, target: u64) {
+        let idx = self.checkpoints.iter().rposition(|checkpoint| checkpoint.cycle <= target).unwrap_or(0);
+        self.restore_checkpoint(chip8_emulator, idx);
+        while chip8_emulator.cycles() < target {
+            if self.step_once(chip8_emulator).is_none() {
+                break;
+            }
+        }
+    }
+
+    fn restore_checkpoint(&mut self, chip8_emulator: &mut Chip8Emulator, idx: usize) {
+        let checkpoint = &self.checkpoints[idx];
+        chip8_emulator.restore(&checkpoint.snapshot).expect("machine was reconfigured while debugging");
+        self.cursor = checkpoint.event_index;
+    }
+
+    /// Executes one instruction, live or from the log. Returns None if the
+    /// emulator did not advance.
+    fn step_once(&mut self, chip8_emulator: &mut Chip8Emulator) -> Option<Executed> {
+        self.replay_events(chip8_emulator);
+
+        let opcode = chip8_emulator.peek_opcode()?;
+        let write = chip8_emulator.memory_accesses(opcode).into_iter().find_map(|access| match access {
+            Access::Write(range) => Some(range),
+            _ => None,
+        });
+        let executed = Executed { pc: chip8_emulator.program_counter(), opcode, write };
+
+        let before = chip8_emulator.cycles();
+        chip8_emulator.emulate_cycle();
+        let after = chip8_emulator.cycles();
+        if after == before {
+            return None;
+        }
+
+        if before >= self.end_cycle {
+            self.end_cycle = after;
+            if after.is_multiple_of(self.cycles_per_frame) {
+                self.events.push_back((after, Event::TimerTick));
+                self.cursor += 1;
+                chip8_emulator.advance_timers();
+            }
+            if after.is_multiple_of(self.checkpoint_interval) {
+                self.push_checkpoint(chip8_emulator);
+            }
+        } else if after == self.end_cycle {
+            // Back at the end of history: catch up on events logged after the last step.
+            self.replay_events(chip8_emulator);
+        }
+        Some(executed)
+    }
+
+    fn replay_events(&mut self, chip8_emulator: &mut Chip8Emulator) {
+        while self.cursor < self.events_end() {
+            let (cycle, event) = self.events[self.cursor - self.events_base];
+            if cycle != chip8_emulator.cycles() {
+                break;
+            }
+            match event {
+                Event::Key { key, pressed } => chip8_emulator.set_key(key as usize, pressed),
+                Event::TimerTick => chip8_emulator.advance_timers(),
+            }
+            self.cursor += 1;
+        }
+    }
+
+    fn push_checkpoint(&mut self, chip8_emulator: &Chip8Emulator) {
+        self.checkpoints.push_back(Checkpoint {
+            cycle: chip8_emulator.cycles(),
+            event_index: self.cursor,
+            snapshot: chip8_emulator.snapshot(),
+        });
+
+        if self.checkpoints.len() > MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
+            let oldest = self.checkpoints[0].event_index;
+            self.events.drain(..oldest - self.events_base);
+            self.events_base = oldest;
+        }
+    }
+
+    fn truncate_history(&mut self, chip8_emulator: &Chip8Emulator) {
+        let current = chip8_emulator.cycles();
+        let cursor = self.cursor;
+        self.events.truncate(cursor - self.events_base);
+        self.checkpoints.retain(|checkpoint| checkpoint.cycle < current || (checkpoint.cycle == current && checkpoint.event_index <= cursor));
+        self.end_cycle = current;
+    }
+
+    fn watch_hit(&self, executed: &Executed) -> Option<StopReason> {
+        let range = executed.write.as_ref()?;
+        self.watchpoints
+            .range(range.clone())
+            .next()
+            .map(|address| StopReason::Watchpoint { address: *address, pc: executed.pc, opcode: executed.opcode })
+    }
+
+    fn history_start(&self) -> u64 {
+        self.checkpoints.front().map_or(0, |checkpoint| checkpoint.cycle)
+    }
+
+    fn events_end(&self) -> usize {
+        self.events_base + self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // V0 = 0, I = 0x300; loop: V0 += 1, store V0 at 0x300.
+    const ROM: [u8; 10] = [0x60, 0x00, 0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x04];
+
+    fn attach() -> (Chip8Emulator, Debugger) {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&ROM);
+        let mut debugger = Debugger::new(4, 5);
+        debugger.attach(&chip8_emulator);
+        (chip8_emulator, debugger)
+    }
+
+    #[test]
+    fn step_back_retraces_every_state() {
+        let (mut chip8_emulator, mut debugger) = attach();
+        let mut hashes = vec![chip8_emulator.state_hash()];
+        for _ in 0..23 {
+            assert_eq!(debugger.step(&mut chip8_emulator), StopReason::Step);
+            hashes.push(chip8_emulator.state_hash());
+        }
+        // Keys pressed along the way are part of the replayed history.
+        debugger.set_key(&mut chip8_emulator, 4, true);
+        hashes.push(chip8_emulator.state_hash());
+        debugger.step(&mut chip8_emulator);
+
+        for expected in hashes.iter().rev().skip(1) {
+            assert_eq!(debugger.step_back(&mut chip8_emulator), StopReason::Step);
+            assert_eq!(chip8_emulator.state_hash(), *expected);
+        }
+        assert_eq!(debugger.step_back(&mut chip8_emulator), StopReason::StartOfHistory);
+        assert!(debugger.is_replaying(&chip8_emulator));
+    }
+
+    #[test]
+    fn breakpoints_work_in_both_directions() {
+        let (mut chip8_emulator, mut debugger) = attach();
+        debugger.add_breakpoint(0x206);
+        assert_eq!(debugger.run(&mut chip8_emulator, 100), StopReason::Breakpoint { pc: 0x206 });
+        let first = chip8_emulator.cycles();
+        assert_eq!(debugger.run(&mut chip8_emulator, 100), StopReason::Breakpoint { pc: 0x206 });
+        assert_eq!(debugger.run(&mut chip8_emulator, 100), StopReason::Breakpoint { pc: 0x206 });
+
+        assert_eq!(debugger.reverse_continue(&mut chip8_emulator), StopReason::Breakpoint { pc: 0x206 });
+        assert_eq!(debugger.reverse_continue(&mut chip8_emulator), StopReason::Breakpoint { pc: 0x206 });
+        assert_eq!(chip8_emulator.cycles(), first);
+        assert_eq!(chip8_emulator.registers()[0], 1);
+        assert_eq!(debugger.reverse_continue(&mut chip8_emulator), StopReason::StartOfHistory);
+    }
+
+    #[test]
+    fn watchpoints_and_last_write() {
+        let (mut chip8_emulator, mut debugger) = attach();
+        debugger.add_watchpoint(0x300);
+        assert_eq!(
+            debugger.run(&mut chip8_emulator, 100),
+            StopReason::Watchpoint { address: 0x300, pc: 0x206, opcode: 0xF055 }
+        );
+        debugger.remove_watchpoint(0x300);
+        assert_eq!(debugger.run(&mut chip8_emulator, 10), StopReason::CycleLimit);
+
+        let cycles = chip8_emulator.cycles();
+        let hit = debugger.last_write(&mut chip8_emulator, 0x300).unwrap();
+        assert_eq!((hit.pc, hit.opcode), (0x206, 0xF055));
+        assert!(hit.cycle < cycles);
+        assert_eq!(chip8_emulator.cycles(), cycles, "last_write does not move the machine");
+        assert_eq!(debugger.last_write(&mut chip8_emulator, 0x400), None);
+    }
+}
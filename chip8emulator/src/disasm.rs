@@ -0,0 +1,52 @@
+// Disassembler using the mnemonics from Cowgod's CHIP-8 technical reference.
+
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nnn = opcode & 0x0FFF;
+    let kk = opcode & 0x00FF;
+
+    match (opcode >> 12, n, kk) {
+        (0x0, _, _) if opcode == 0x00E0 => "CLS".to_string(),
+        (0x0, _, _) if opcode == 0x00EE => "RET".to_string(),
+        (0x0, _, _) => format!("SYS {:#05x}", nnn),
+        (0x1, _, _) => format!("JP {:#05x}", nnn),
+        (0x2, _, _) => format!("CALL {:#05x}", nnn),
+        (0x3, _, _) => format!("SE V{:X}, {:#04x}", x, kk),
+        (0x4, _, _) => format!("SNE V{:X}, {:#04x}", x, kk),
+        (0x5, 0x0, _) => format!("SE V{:X}, V{:X}", x, y),
+        (0x5, 0x1, _) => format!("ADDN V{:X}, V{:X}", x, y),
+        (0x6, _, _) => format!("LD V{:X}, {:#04x}", x, kk),
+        (0x7, _, _) => format!("ADD V{:X}, {:#04x}", x, kk),
+        (0x8, 0x0, _) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, 0x1, _) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, 0x2, _) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, 0x3, _) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, 0x4, _) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, 0x5, _) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, 0x6, _) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, 0x7, _) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, 0xE, _) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, 0x0, _) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _) => format!("LD I, {:#05x}", nnn),
+        (0xB, _, _) => format!("JP V0, {:#05x}", nnn),
+        (0xC, _, _) => format!("RND V{:X}, {:#04x}", x, kk),
+        (0xD, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9E) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA1) => format!("SKNP V{:X}", x),
+        (0xE, _, 0xF2) => format!("SKP2 V{:X}", x),
+        (0xE, _, 0xF5) => format!("SKNP2 V{:X}", x),
+        (0xF, _, 0x07) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0A) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x15) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x18) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1E) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x29) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x30) => format!("LD HF, V{:X}", x),
+        (0xF, _, 0x33) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x55) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x65) => format!("LD V{:X}, [I]", x),
+        _ => format!("DW {:#06x}", opcode),
+    }
+}
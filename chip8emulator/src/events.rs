@@ -0,0 +1,160 @@
+// Observable events. Frontends opt into categories with an `EventFilter`;
+// matching events are queued as they happen and drained with `take_events`,
+// the same way as memory faults. Nothing is recorded with the default filter.
+
+use std::collections::VecDeque;
+
+use crate::Chip8Emulator;
+
+/// Oldest events are dropped beyond this, so an undrained queue stays bounded.
+const MAX_QUEUED_EVENTS: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Timer {
+    Delay,
+    Sound,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EmulatorEvent {
+    BeforeInstruction { pc: u16, opcode: u16 },
+    AfterInstruction { pc: u16, opcode: u16 },
+    ClearScreen,
+    /// DXYN with the sprite's screen position, row count and whether it erased a pixel.
+    SpriteDraw { x: u8, y: u8, height: u8, collision: bool },
+    SoundStart,
+    SoundStop,
+    /// FX15 or FX18.
+    TimerWrite { timer: Timer, value: u8 },
+    /// FX0A started waiting.
+    KeyWaitStart,
+    /// FX0A finished waiting with `key`.
+    KeyWaitEnd { key: u8 },
+    SubroutineCall { from: u16, to: u16 },
+    SubroutineReturn { from: u16, to: u16 },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Before and after every instruction.
+    pub instructions: bool,
+    /// Screen clears and sprite draws.
+    pub display: bool,
+    pub sound: bool,
+    pub timers: bool,
+    pub key_waits: bool,
+    pub calls: bool,
+}
+
+impl EventFilter {
+    pub const NONE: Self = Self { instructions: false, display: false, sound: false, timers: false, key_waits: false, calls: false };
+    pub const ALL: Self = Self { instructions: true, display: true, sound: true, timers: true, key_waits: true, calls: true };
+
+    pub fn accepts(&self, event: &EmulatorEvent) -> bool {
+        match event {
+            EmulatorEvent::BeforeInstruction { .. } | EmulatorEvent::AfterInstruction { .. } => self.instructions,
+            EmulatorEvent::ClearScreen | EmulatorEvent::SpriteDraw { .. } => self.display,
+            EmulatorEvent::SoundStart | EmulatorEvent::SoundStop => self.sound,
+            EmulatorEvent::TimerWrite { .. } => self.timers,
+            EmulatorEvent::KeyWaitStart | EmulatorEvent::KeyWaitEnd { .. } => self.key_waits,
+            EmulatorEvent::SubroutineCall { .. } | EmulatorEvent::SubroutineReturn { .. } => self.calls,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EventQueue {
+    pub(crate) filter: EventFilter,
+    events: VecDeque<EmulatorEvent>,
+}
+
+impl EventQueue {
+    pub(crate) fn push(&mut self, event: EmulatorEvent) {
+        if !self.filter.accepts(&event) {
+            return;
+        }
+        if self.events.len() == MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+impl Chip8Emulator {
+    /// Selects which events are queued; events already queued are kept.
+    pub fn set_event_filter(&mut self, filter: EventFilter) {
+        self.events.filter = filter;
+    }
+
+    pub fn event_filter(&self) -> EventFilter {
+        self.events.filter
+    }
+
+    /// Events since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<EmulatorEvent> {
+        self.events.events.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clear, call 0x20A (draw the font's first row, return), start a two-tick
+    // sound, spin.
+    const ROM: [u8; 16] = [0x00, 0xE0, 0x22, 0x0A, 0x60, 0x02, 0xF0, 0x18, 0x12, 0x08, 0xA0, 0x00, 0xD0, 0x01, 0x00, 0xEE];
+
+    fn run(filter: EventFilter) -> Vec<EmulatorEvent> {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&ROM);
+        chip8_emulator.set_event_filter(filter);
+        for _ in 0..7 {
+            chip8_emulator.emulate_cycle();
+        }
+        chip8_emulator.advance_timers();
+        chip8_emulator.advance_timers();
+        chip8_emulator.take_events()
+    }
+
+    #[test]
+    fn nothing_is_queued_by_default() {
+        assert_eq!(Chip8Emulator::new().event_filter(), EventFilter::NONE);
+        assert!(run(EventFilter::default()).is_empty());
+    }
+
+    #[test]
+    fn queues_events_in_order() {
+        let filter = EventFilter { instructions: false, ..EventFilter::ALL };
+        assert_eq!(run(filter), vec![
+            EmulatorEvent::ClearScreen,
+            EmulatorEvent::SubroutineCall { from: 0x202, to: 0x20A },
+            EmulatorEvent::SpriteDraw { x: 0, y: 0, height: 1, collision: false },
+            EmulatorEvent::SubroutineReturn { from: 0x20E, to: 0x204 },
+            EmulatorEvent::TimerWrite { timer: Timer::Sound, value: 2 },
+            EmulatorEvent::SoundStart,
+            EmulatorEvent::SoundStop,
+        ]);
+    }
+
+    #[test]
+    fn filters_by_category() {
+        let filter = EventFilter { calls: true, ..EventFilter::NONE };
+        assert!(run(filter).iter().all(|event| matches!(event, EmulatorEvent::SubroutineCall { .. } | EmulatorEvent::SubroutineReturn { .. })));
+
+        let events = run(EventFilter { instructions: true, ..EventFilter::NONE });
+        assert_eq!(events.len(), 14);
+        assert_eq!(events[0], EmulatorEvent::BeforeInstruction { pc: 0x200, opcode: 0x00E0 });
+        assert_eq!(events[13], EmulatorEvent::AfterInstruction { pc: 0x206, opcode: 0xF018 });
+    }
+
+    #[test]
+    fn queue_drops_the_oldest_events_when_full() {
+        let mut queue = EventQueue { filter: EventFilter::ALL, ..EventQueue::default() };
+        queue.push(EmulatorEvent::KeyWaitStart);
+        for key in 0..MAX_QUEUED_EVENTS {
+            queue.push(EmulatorEvent::KeyWaitEnd { key: key as u8 });
+        }
+        assert_eq!(queue.events.len(), MAX_QUEUED_EVENTS);
+        assert_eq!(queue.events[0], EmulatorEvent::KeyWaitEnd { key: 0 });
+    }
+}
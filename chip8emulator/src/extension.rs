@@ -0,0 +1,189 @@
+// Opcode extensions. Opcodes the core leaves unimplemented, including every
+// 0NNN machine-code call other than 00E0 and 00EE, are offered to the
+// registered extensions in order; the first one that claims an opcode runs
+// it with full access to the machine. Unclaimed opcodes still crash.
+//
+// Extensions that keep state of their own save it into snapshots (and so
+// crash dumps) through `save_state`; restoring needs the same extensions
+// registered in the same order. Movies start from power-on and do not
+// record extension state.
+
+use std::collections::BTreeMap;
+
+use crate::Chip8Emulator;
+
+pub trait OpcodeExtension: ExtensionClone {
+    fn claims(&self, opcode: u16) -> bool;
+
+    /// Runs a claimed opcode. PC already points at the next instruction.
+    fn execute(&mut self, chip8_emulator: &mut Chip8Emulator, opcode: u16);
+
+    /// State carried in snapshots. Stateless extensions keep the default.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Loads bytes from `save_state`. Returns false if they do not fit this extension.
+    fn load_state(&mut self, state: &[u8]) -> bool {
+        state.is_empty()
+    }
+}
+
+/// Lets boxed extensions be cloned along with the emulator; implemented for
+/// every `Clone` extension.
+pub trait ExtensionClone {
+    fn clone_box(&self) -> Box<dyn OpcodeExtension>;
+}
+
+impl<T: OpcodeExtension + Clone + 'static> ExtensionClone for T {
+    fn clone_box(&self) -> Box<dyn OpcodeExtension> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn OpcodeExtension> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Native stand-ins for 0NNN machine-code routines, keyed by address.
+#[derive(Clone, Default)]
+pub struct MachineRoutines {
+    routines: BTreeMap<u16, fn(&mut Chip8Emulator)>,
+}
+
+impl MachineRoutines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, address: u16, routine: fn(&mut Chip8Emulator)) -> &mut Self {
+        self.routines.insert(address & 0x0FFF, routine);
+        self
+    }
+}
+
+impl OpcodeExtension for MachineRoutines {
+    fn claims(&self, opcode: u16) -> bool {
+        opcode & 0xF000 == 0 && self.routines.contains_key(&opcode)
+    }
+
+    fn execute(&mut self, chip8_emulator: &mut Chip8Emulator, opcode: u16) {
+        (self.routines[&opcode])(chip8_emulator);
+    }
+}
+
+impl Chip8Emulator {
+    /// Registers an extension after the ones already present.
+    pub fn add_extension(&mut self, extension: Box<dyn OpcodeExtension>) {
+        self.extensions.push(extension);
+    }
+
+    pub fn clear_extensions(&mut self) {
+        self.extensions.clear();
+    }
+
+    /// Runs `opcode` through the first extension claiming it. Returns false if none does.
+    pub(crate) fn run_extension(&mut self, opcode: u16) -> bool {
+        let mut extensions = std::mem::take(&mut self.extensions);
+        let handled = match extensions.iter_mut().find(|extension| extension.claims(opcode)) {
+            Some(extension) => {
+                extension.execute(self, opcode);
+                true
+            },
+            None => false,
+        };
+        // Keep anything the handler registered in the meantime.
+        extensions.append(&mut self.extensions);
+        self.extensions = extensions;
+        handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CrashReason, CycleState, SnapshotError};
+
+    /// Counts 0ABC calls into V1 and keeps the count as its state.
+    #[derive(Clone, Default)]
+    struct Counter {
+        count: u8,
+    }
+
+    impl OpcodeExtension for Counter {
+        fn claims(&self, opcode: u16) -> bool {
+            opcode == 0x0ABC
+        }
+
+        fn execute(&mut self, chip8_emulator: &mut Chip8Emulator, _opcode: u16) {
+            self.count += 1;
+            chip8_emulator.set_register(1, self.count).unwrap();
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            vec![self.count]
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> bool {
+            match state {
+                [count] => {
+                    self.count = *count;
+                    true
+                },
+                _ => false,
+            }
+        }
+    }
+
+    fn set_v0(chip8_emulator: &mut Chip8Emulator) {
+        chip8_emulator.set_register(0, 7).unwrap();
+    }
+
+    #[test]
+    fn machine_routines_run_registered_addresses() {
+        // Call 0x300, then the unregistered 0x400.
+        let mut routines = MachineRoutines::new();
+        routines.add(0x300, set_v0);
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.add_extension(Box::new(routines));
+        chip8_emulator.init(&[0x03, 0x00, 0x04, 0x00]);
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Running);
+        assert_eq!(chip8_emulator.registers()[0], 7);
+        assert_eq!(chip8_emulator.program_counter(), 0x202);
+
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Halted);
+        assert_eq!(chip8_emulator.take_crash_dump().unwrap().reason, CrashReason::InvalidOpcode);
+    }
+
+    #[test]
+    fn the_first_claiming_extension_wins() {
+        let mut routines = MachineRoutines::new();
+        routines.add(0xABC, set_v0);
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.add_extension(Box::new(Counter::default()));
+        chip8_emulator.add_extension(Box::new(routines));
+        chip8_emulator.init(&[0x0A, 0xBC]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.registers()[..2], [0, 1]);
+    }
+
+    #[test]
+    fn extension_state_travels_in_snapshots() {
+        // Three counted calls; the snapshot is taken after the first.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.add_extension(Box::new(Counter::default()));
+        chip8_emulator.init(&[0x0A, 0xBC, 0x0A, 0xBC, 0x0A, 0xBC]);
+        chip8_emulator.emulate_cycle();
+        let snapshot = chip8_emulator.snapshot();
+        chip8_emulator.emulate_cycle();
+        chip8_emulator.restore(&snapshot).unwrap();
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.registers()[1], 2);
+
+        chip8_emulator.clear_extensions();
+        assert_eq!(chip8_emulator.restore(&snapshot), Err(SnapshotError::Extensions));
+        assert_eq!(chip8_emulator.registers()[1], 2);
+    }
+}
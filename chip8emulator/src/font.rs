@@ -0,0 +1,480 @@
+// Hex fonts. A font is the 16 small 4x5 glyphs FX29 points at, optionally
+// followed by SCHIP-style 8x10 glyphs for FX30 (digits only, or all 16).
+// Both are loaded at the configured font address, the big glyphs right after
+// the small ones.
+//
+// Custom fonts come either as raw bytes (small glyphs, then big ones) or as
+// text that can be drawn by hand:
+//
+//     chip8-font 1
+//     small 0          ; followed by 5 rows of up to 4 pixels
+//     ####
+//     #..#
+//     ...
+//     big 0            ; followed by 10 rows of up to 8 pixels
+//
+// `#` or `X` is a lit pixel, `.` or a space an unlit one. Every small glyph
+// is required; big glyphs are optional, but must cover 0-9 or 0-F.
+
+use std::fmt::{self, Write};
+
+use crate::{Chip8Emulator, MemoryConfigError, FONT_ADDRESS_OFFSET, NUM_FONTS};
+
+const MAGIC: &str = "chip8-font 1";
+pub(crate) const SMALL_FONT_LEN: usize = NUM_FONTS * FONT_ADDRESS_OFFSET;
+pub(crate) const BIG_GLYPH_LEN: usize = 10;
+
+#[rustfmt::skip]
+const STANDARD_SMALL: [u8; SMALL_FONT_LEN] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const OCTO_BIG: [u8; 16 * BIG_GLYPH_LEN] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+#[rustfmt::skip]
+const VIP_SMALL: [u8; SMALL_FONT_LEN] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0x70, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const ETI_660_SMALL: [u8; SMALL_FONT_LEN] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const DREAM_6800_SMALL: [u8; SMALL_FONT_LEN] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const FISH_N_CHIPS_SMALL: [u8; SMALL_FONT_LEN] = [
+    0x60, 0xA0, 0xA0, 0xA0, 0xC0, // 0
+    0x40, 0xC0, 0x40, 0x40, 0xE0, // 1
+    0xC0, 0x20, 0x40, 0x80, 0xE0, // 2
+    0xC0, 0x20, 0x40, 0x20, 0xC0, // 3
+    0x20, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xC0, 0x20, 0xC0, // 5
+    0x40, 0x80, 0xC0, 0xA0, 0x40, // 6
+    0xE0, 0x20, 0x60, 0x40, 0x40, // 7
+    0x40, 0xA0, 0x40, 0xA0, 0x40, // 8
+    0x40, 0xA0, 0x60, 0x20, 0x40, // 9
+    0x40, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xC0, 0xA0, 0xC0, // B
+    0x60, 0x80, 0x80, 0x80, 0x60, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xC0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+#[rustfmt::skip]
+const FISH_N_CHIPS_BIG: [u8; 16 * BIG_GLYPH_LEN] = [
+    0x7C, 0xC6, 0xCE, 0xDE, 0xD6, 0xF6, 0xE6, 0xC6, 0x7C, 0x00, // 0
+    0x10, 0x30, 0xF0, 0x30, 0x30, 0x30, 0x30, 0x30, 0xFC, 0x00, // 1
+    0x78, 0xCC, 0xCC, 0x0C, 0x18, 0x30, 0x60, 0xCC, 0xFC, 0x00, // 2
+    0x78, 0xCC, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0xCC, 0x78, 0x00, // 3
+    0x0C, 0x1C, 0x3C, 0x6C, 0xCC, 0xFE, 0x0C, 0x0C, 0x1E, 0x00, // 4
+    0xFC, 0xC0, 0xC0, 0xC0, 0xF8, 0x0C, 0x0C, 0xCC, 0x78, 0x00, // 5
+    0x38, 0x60, 0xC0, 0xC0, 0xF8, 0xCC, 0xCC, 0xCC, 0x78, 0x00, // 6
+    0xFE, 0xC6, 0xC6, 0x06, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x00, // 7
+    0x78, 0xCC, 0xCC, 0xEC, 0x78, 0xDC, 0xCC, 0xCC, 0x78, 0x00, // 8
+    0x7C, 0xC6, 0xC6, 0xC6, 0x7C, 0x18, 0x18, 0x30, 0x70, 0x00, // 9
+    0x30, 0x78, 0xCC, 0xCC, 0xCC, 0xFC, 0xCC, 0xCC, 0xCC, 0x00, // A
+    0xFC, 0x66, 0x66, 0x66, 0x7C, 0x66, 0x66, 0x66, 0xFC, 0x00, // B
+    0x3C, 0x66, 0xC6, 0xC0, 0xC0, 0xC0, 0xC6, 0x66, 0x3C, 0x00, // C
+    0xF8, 0x6C, 0x66, 0x66, 0x66, 0x66, 0x66, 0x6C, 0xF8, 0x00, // D
+    0xFE, 0x62, 0x60, 0x64, 0x7C, 0x64, 0x60, 0x62, 0xFE, 0x00, // E
+    0xFE, 0x66, 0x62, 0x64, 0x7C, 0x64, 0x60, 0x60, 0xF0, 0x00, // F
+];
+
+#[rustfmt::skip]
+const SCHIP_BIG: [u8; 10 * BIG_GLYPH_LEN] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BuiltinFont {
+    /// The common small font on its own.
+    #[default]
+    Standard,
+    /// The common small font with Octo's big glyphs.
+    Octo,
+    CosmacVip,
+    Eti660,
+    Dream6800,
+    FishNChips,
+    /// SUPER-CHIP 1.1: the common small font and big digits 0-9.
+    Schip,
+}
+
+impl BuiltinFont {
+    pub const ALL: [BuiltinFont; 7] = [
+        BuiltinFont::Standard,
+        BuiltinFont::Octo,
+        BuiltinFont::CosmacVip,
+        BuiltinFont::Eti660,
+        BuiltinFont::Dream6800,
+        BuiltinFont::FishNChips,
+        BuiltinFont::Schip,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltinFont::Standard => "standard",
+            BuiltinFont::Octo => "octo",
+            BuiltinFont::CosmacVip => "vip",
+            BuiltinFont::Eti660 => "eti660",
+            BuiltinFont::Dream6800 => "dream6800",
+            BuiltinFont::FishNChips => "fishnchips",
+            BuiltinFont::Schip => "schip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|font| font.name() == name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FontError {
+    /// Line of a text font; 0 for raw fonts.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            line => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Font {
+    small: Vec<u8>,
+    big: Vec<u8>,
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Self::builtin(BuiltinFont::default())
+    }
+}
+
+impl Font {
+    pub fn builtin(font: BuiltinFont) -> Self {
+        let (small, big): (&[u8], &[u8]) = match font {
+            BuiltinFont::Standard => (&STANDARD_SMALL, &[]),
+            BuiltinFont::Octo => (&STANDARD_SMALL, &OCTO_BIG),
+            BuiltinFont::CosmacVip => (&VIP_SMALL, &[]),
+            BuiltinFont::Eti660 => (&ETI_660_SMALL, &[]),
+            BuiltinFont::Dream6800 => (&DREAM_6800_SMALL, &[]),
+            BuiltinFont::FishNChips => (&FISH_N_CHIPS_SMALL, &FISH_N_CHIPS_BIG),
+            BuiltinFont::Schip => (&STANDARD_SMALL, &SCHIP_BIG),
+        };
+        Self { small: small.to_vec(), big: big.to_vec() }
+    }
+
+    /// Raw font: 80 bytes of small glyphs, then optionally 100 or 160 bytes
+    /// of big ones.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FontError> {
+        let error = |message: String| FontError { line: 0, message };
+        if bytes.len() < SMALL_FONT_LEN {
+            return Err(error(format!("{} bytes is too short for 16 small glyphs", bytes.len())));
+        }
+        let (small, big) = bytes.split_at(SMALL_FONT_LEN);
+        if ![0, 10 * BIG_GLYPH_LEN, 16 * BIG_GLYPH_LEN].contains(&big.len()) {
+            return Err(error(format!("{} bytes of big glyphs is neither 10 nor 16 glyphs", big.len())));
+        }
+        if let Some(idx) = small.iter().position(|row| row & 0x0F != 0) {
+            let glyph = idx / FONT_ADDRESS_OFFSET;
+            return Err(error(format!("small glyph {:X} is wider than 4 pixels", glyph)));
+        }
+        Ok(Self { small: small.to_vec(), big: big.to_vec() })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, FontError> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line.split(';').next().unwrap().trim_end()));
+        match lines.next() {
+            Some((_, line)) if line.trim() == MAGIC => (),
+            _ => return Err(FontError { line: 1, message: "not a font".to_string() }),
+        }
+
+        let mut small: [Option<[u8; FONT_ADDRESS_OFFSET]>; NUM_FONTS] = [None; NUM_FONTS];
+        let mut big: [Option<[u8; BIG_GLYPH_LEN]>; NUM_FONTS] = [None; NUM_FONTS];
+        let mut last_line = 1;
+        let mut lines = lines.filter(|(_, line)| !line.trim().is_empty()).peekable();
+        while let Some((line, header)) = lines.next() {
+            let error = |message: &str| FontError { line, message: message.to_string() };
+            let fields: Vec<&str> = header.split_whitespace().collect();
+            let glyph = fields.get(1).and_then(|s| usize::from_str_radix(s, 16).ok()).filter(|glyph| *glyph < NUM_FONTS);
+            let (height, width) = match fields[0] {
+                "small" => (FONT_ADDRESS_OFFSET, 4),
+                "big" => (BIG_GLYPH_LEN, 8),
+                _ => return Err(error("expected small or big glyph header")),
+            };
+            let glyph = glyph.ok_or_else(|| error("expected a glyph 0-F"))?;
+
+            let mut rows = [0u8; BIG_GLYPH_LEN];
+            for row in rows.iter_mut().take(height) {
+                let (line, text) = lines.next().ok_or_else(|| error("glyph is missing rows"))?;
+                last_line = line;
+                let error = |message: &str| FontError { line, message: message.to_string() };
+                if text.chars().count() > width {
+                    return Err(error("row is wider than the glyph"));
+                }
+                for (col, pixel) in text.chars().enumerate() {
+                    match pixel {
+                        '#' | 'X' => *row |= 0x80 >> col,
+                        '.' | ' ' => (),
+                        _ => return Err(error("expected # or . pixels")),
+                    }
+                }
+            }
+            match fields[0] {
+                "small" => small[glyph] = Some(rows[..FONT_ADDRESS_OFFSET].try_into().unwrap()),
+                _ => big[glyph] = Some(rows),
+            }
+        }
+
+        let error = |message: String| FontError { line: last_line, message };
+        let mut bytes = Vec::with_capacity(SMALL_FONT_LEN + 16 * BIG_GLYPH_LEN);
+        for (glyph, rows) in small.iter().enumerate() {
+            bytes.extend_from_slice(&rows.ok_or_else(|| error(format!("missing small glyph {:X}", glyph)))?);
+        }
+        let big_glyphs = big.iter().take_while(|rows| rows.is_some()).count();
+        if ![0, 10, 16].contains(&big_glyphs) || big[big_glyphs..].iter().any(Option::is_some) {
+            return Err(error("big glyphs must cover 0-9 or 0-F".to_string()));
+        }
+        big.iter().flatten().for_each(|rows| bytes.extend_from_slice(rows));
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", MAGIC);
+        let glyphs = self.small.chunks(FONT_ADDRESS_OFFSET).map(|rows| ("small", 4, rows));
+        let big_glyphs = self.big.chunks(BIG_GLYPH_LEN).map(|rows| ("big", 8, rows));
+        for (idx, (kind, width, rows)) in glyphs.chain(big_glyphs).enumerate() {
+            writeln!(out, "{} {:X}", kind, idx % NUM_FONTS).unwrap();
+            for row in rows {
+                let pixels: String = (0..width).map(|col| if row & (0x80 >> col) != 0 { '#' } else { '.' }).collect();
+                writeln!(out, "{}", pixels).unwrap();
+            }
+        }
+        out
+    }
+
+    /// Small glyphs followed by big ones, as loaded into RAM.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [self.small.as_slice(), self.big.as_slice()].concat()
+    }
+
+    pub fn len(&self) -> usize {
+        self.small.len() + self.big.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of big glyphs: 0, 10 or 16.
+    pub fn big_glyphs(&self) -> usize {
+        self.big.len() / BIG_GLYPH_LEN
+    }
+}
+
+impl Chip8Emulator {
+    /// Switches fonts and writes the new one into RAM right away. Fails if it
+    /// does not fit below the program start.
+    pub fn set_font(&mut self, font: Font) -> Result<(), MemoryConfigError> {
+        self.memory.config.check_font(font.len())?;
+        self.font = font;
+        self.load_font_set();
+        Ok(())
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CrashReason, MemoryConfig};
+
+    #[test]
+    fn builtin_fonts_are_valid_and_named() {
+        for builtin in BuiltinFont::ALL {
+            let font = Font::builtin(builtin);
+            assert_eq!(Font::from_bytes(&font.as_bytes()), Ok(font.clone()));
+            assert_eq!(BuiltinFont::from_name(builtin.name()), Some(builtin));
+        }
+        assert_eq!(Font::builtin(BuiltinFont::Standard).big_glyphs(), 0);
+        assert_eq!(Font::builtin(BuiltinFont::Schip).big_glyphs(), 10);
+        assert_eq!(Font::builtin(BuiltinFont::Octo).big_glyphs(), 16);
+        assert_eq!(BuiltinFont::from_name("comic"), None);
+    }
+
+    #[test]
+    fn builtin_fonts_survive_a_text_round_trip() {
+        for builtin in BuiltinFont::ALL {
+            let font = Font::builtin(builtin);
+            assert_eq!(Font::parse(&font.to_text()), Ok(font));
+        }
+    }
+
+    #[test]
+    fn raw_fonts_are_validated() {
+        let small = Font::builtin(BuiltinFont::Standard).as_bytes();
+        assert!(Font::from_bytes(&small[..79]).unwrap_err().message.contains("too short"));
+        let uneven = [small.as_slice(), &[0; 50]].concat();
+        assert!(Font::from_bytes(&uneven).unwrap_err().message.contains("neither 10 nor 16"));
+        let mut wide = small.clone();
+        wide[5 * 0xB + 2] = 0xF8;
+        assert_eq!(Font::from_bytes(&wide).unwrap_err().message, "small glyph B is wider than 4 pixels");
+    }
+
+    #[test]
+    fn text_fonts_accept_hand_drawn_glyphs() {
+        // Glyph 0 drawn with X and spaces, trailing pixels left out.
+        let text = Font::builtin(BuiltinFont::Standard).to_text().replacen("small 0\n####\n#..#\n#..#\n#..#\n####", "small 0 ; zero\nXXXX\nX  X\nX  X\nX\nXXXX", 1);
+        let font = Font::parse(&text).unwrap();
+        assert_eq!(font.as_bytes()[..5], [0xF0, 0x90, 0x90, 0x80, 0xF0]);
+    }
+
+    #[test]
+    fn text_fonts_report_the_offending_line() {
+        let text = Font::builtin(BuiltinFont::Standard).to_text();
+        assert_eq!(Font::parse("chip8-movie 1\n").unwrap_err().line, 1);
+        assert_eq!(Font::parse(&text.replacen("small 1", "tiny 1", 1)).unwrap_err(),
+            FontError { line: 8, message: "expected small or big glyph header".to_string() });
+        assert_eq!(Font::parse(&text.replacen("small 1\n..#.", "small 1\n..#..", 1)).unwrap_err().line, 9);
+        assert_eq!(Font::parse(&text.replacen("small 1\n..#.", "small 1\n..o.", 1)).unwrap_err().message, "expected # or . pixels");
+
+        let missing: String = text.lines().take(6 * 15 + 1).map(|line| format!("{}\n", line)).collect();
+        assert_eq!(Font::parse(&missing).unwrap_err().message, "missing small glyph F");
+
+        // Octo's big glyphs with 5 left out.
+        let big = Font::builtin(BuiltinFont::Octo).to_text();
+        let lines: Vec<&str> = big.lines().collect();
+        let gappy: String = lines.iter().enumerate()
+            .filter(|(idx, _)| !(97 + 11 * 5..97 + 11 * 6).contains(idx))
+            .map(|(_, line)| format!("{}\n", line))
+            .collect();
+        assert_eq!(Font::parse(&gappy).unwrap_err().message, "big glyphs must cover 0-9 or 0-F");
+    }
+
+    #[test]
+    fn set_font_loads_glyphs_at_the_font_address() {
+        // V0 = 3, FX30: I points at big glyph 3.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig { font_address: 0x50, ..MemoryConfig::default() }).unwrap();
+        chip8_emulator.set_font(Font::builtin(BuiltinFont::Schip)).unwrap();
+        chip8_emulator.init(&[0x60, 0x03, 0xF0, 0x30]);
+        let font = Font::builtin(BuiltinFont::Schip).as_bytes();
+        assert_eq!(chip8_emulator.memory.ram[0x50..0x50 + font.len()], font[..]);
+        chip8_emulator.emulate_cycle();
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.registers.i, 0x50 + 80 + 3 * 10);
+        assert_eq!(chip8_emulator.font().big_glyphs(), 10);
+    }
+
+    #[test]
+    fn fx30_needs_big_glyphs() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0xF0, 0x30]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.take_crash_dump().unwrap().reason, CrashReason::InvalidOpcode);
+    }
+}
@@ -0,0 +1,363 @@
+// Animated GIF recorder. Frames are fed once per 60 Hz tick; identical
+// consecutive frames are merged into a single image with a longer delay.
+// The image takes its height from the first frame, so hi-res displays are
+// recorded at their full size. The global colour table holds the palette
+// followed by the CHIP-8X foregrounds and backgrounds.
+
+use std::collections::HashMap;
+
+use crate::chip8x::COLOR_COLUMNS;
+use crate::{Chip8Emulator, Palette, Variant, CHIP8X_BACKGROUNDS, CHIP8X_FOREGROUNDS, HEIGHT, WIDTH};
+
+const TICKS_PER_SECOND: usize = 60;
+const MIN_CODE_SIZE: u8 = 4;
+const TABLE_SIZE: usize = 1 << MIN_CODE_SIZE;
+const FOREGROUNDS_START: u8 = 2;
+const BACKGROUNDS_START: u8 = FOREGROUNDS_START + CHIP8X_FOREGROUNDS.len() as u8;
+const MAX_CODE: u16 = 4096;
+
+pub struct GifRecorder {
+    palette: Palette,
+    scale: usize,
+    decimation: usize,
+    ticks_seen: usize,
+    pending: Option<Vec<u8>>,
+    pending_ticks: usize,
+    elapsed_ticks: usize,
+    out: Vec<u8>,
+}
+
+impl GifRecorder {
+    /// `decimation` samples every n-th tick; 1 records at the full 60 fps.
+    pub fn new(palette: Palette, scale: usize, decimation: usize) -> Self {
+        let scale = scale.max(1);
+        Self {
+            palette,
+            scale,
+            decimation: decimation.max(1),
+            ticks_seen: 0,
+            pending: None,
+            pending_ticks: 0,
+            elapsed_ticks: 0,
+            out: Vec::new(),
+        }
+    }
+
+    /// Records one 60 Hz tick of the emulator's screen.
+    pub fn capture(&mut self, chip8_emulator: &Chip8Emulator) {
+        let sample = self.ticks_seen.is_multiple_of(self.decimation);
+        self.ticks_seen += 1;
+
+        if sample {
+            let indices = color_indices(chip8_emulator);
+            if self.pending.as_ref() != Some(&indices) {
+                self.flush_pending();
+                self.pending = Some(indices);
+            }
+        }
+        self.pending_ticks += 1;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush_pending();
+        if self.out.is_empty() {
+            self.write_header(HEIGHT);
+        }
+        self.out.push(0x3B);
+        self.out
+    }
+
+    fn write_header(&mut self, rows: usize) {
+        let width = (WIDTH * self.scale) as u16;
+        let height = (rows * self.scale) as u16;
+
+        self.out.extend_from_slice(b"GIF89a");
+        self.out.extend_from_slice(&width.to_le_bytes());
+        self.out.extend_from_slice(&height.to_le_bytes());
+        self.out.extend_from_slice(&[0x80 | (MIN_CODE_SIZE - 1), 0x00, 0x00]); // global table of 16 colors
+        let colors = [self.palette.background, self.palette.foreground].into_iter().chain(CHIP8X_FOREGROUNDS).chain(CHIP8X_BACKGROUNDS);
+        for color in colors.chain(std::iter::repeat([0; 3])).take(TABLE_SIZE) {
+            self.out.extend_from_slice(&color);
+        }
+
+        // Loop forever.
+        self.out.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        self.out.extend_from_slice(b"NETSCAPE2.0");
+        self.out.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+    }
+
+    fn flush_pending(&mut self) {
+        let pixels = match self.pending.take() {
+            Some(pixels) => pixels,
+            None => return,
+        };
+
+        // Delays are in 1/100 s; round on the running total so 60 fps does not drift.
+        let start_cs = self.elapsed_ticks * 100 / TICKS_PER_SECOND;
+        self.elapsed_ticks += self.pending_ticks;
+        let end_cs = self.elapsed_ticks * 100 / TICKS_PER_SECOND;
+        let delay = (end_cs - start_cs).min(u16::MAX as usize) as u16;
+        self.pending_ticks = 0;
+
+        let rows = pixels.len() / WIDTH;
+        if self.out.is_empty() {
+            self.write_header(rows);
+        }
+
+        self.out.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        self.out.extend_from_slice(&delay.to_le_bytes());
+        self.out.extend_from_slice(&[0x00, 0x00]);
+
+        let width = (WIDTH * self.scale) as u16;
+        let height = (rows * self.scale) as u16;
+        self.out.extend_from_slice(&[0x2C, 0x00, 0x00, 0x00, 0x00]);
+        self.out.extend_from_slice(&width.to_le_bytes());
+        self.out.extend_from_slice(&height.to_le_bytes());
+        self.out.push(0x00);
+
+        let mut indices = Vec::with_capacity(pixels.len() * self.scale * self.scale);
+        for row in pixels.chunks(WIDTH) {
+            for _ in 0..self.scale {
+                for val in row {
+                    for _ in 0..self.scale {
+                        indices.push(*val);
+                    }
+                }
+            }
+        }
+
+        self.out.push(MIN_CODE_SIZE);
+        for block in lzw_encode(&indices).chunks(255) {
+            self.out.push(block.len() as u8);
+            self.out.extend_from_slice(block);
+        }
+        self.out.push(0x00);
+    }
+}
+
+/// Colour table index of every pixel, row by row.
+fn color_indices(chip8_emulator: &Chip8Emulator) -> Vec<u8> {
+    let pixels = chip8_emulator.get_color_array();
+    if chip8_emulator.variant() != Variant::Chip8X {
+        return pixels.iter().map(|val| val & 0x01).collect();
+    }
+    let background = BACKGROUNDS_START + chip8_emulator.background_color() % CHIP8X_BACKGROUNDS.len() as u8;
+    let colors = chip8_emulator.color_map();
+    pixels.iter().enumerate().map(|(idx, val)| match val & 0x01 {
+        0 => background,
+        _ => FOREGROUNDS_START + colors[idx / WIDTH * COLOR_COLUMNS + idx % WIDTH / 8] % CHIP8X_FOREGROUNDS.len() as u8,
+    }).collect()
+}
+
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = MIN_CODE_SIZE as u32 + 1;
+
+    writer.write(clear, size);
+
+    let mut iter = indices.iter();
+    let mut prefix = match iter.next() {
+        Some(first) => *first as u16,
+        None => {
+            writer.write(end, size);
+            return writer.finish();
+        }
+    };
+
+    for &k in iter {
+        if let Some(&code) = table.get(&(prefix, k)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, size);
+        if next > (1 << size) - 1 && size < 12 {
+            size += 1;
+        }
+
+        if next < MAX_CODE {
+            table.insert((prefix, k), next);
+            next += 1;
+        } else {
+            writer.write(clear, size);
+            table.clear();
+            next = end + 1;
+            size = MIN_CODE_SIZE as u32 + 1;
+        }
+        prefix = k as u16;
+    }
+
+    writer.write(prefix, size);
+    if next > (1 << size) - 1 && size < 12 {
+        size += 1;
+    }
+    writer.write(end, size);
+    writer.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryConfig;
+
+    /// Plain GIF LZW decoder, as an image viewer would run it.
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let clear = 1u16 << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = MIN_CODE_SIZE as u32 + 1;
+        let mut prev: Option<u16> = None;
+        let mut out = Vec::new();
+        let (mut acc, mut bits, mut bytes) = (0u32, 0u32, data.iter());
+
+        loop {
+            while bits < size {
+                acc |= (*bytes.next().expect("stream ended without an end code") as u32) << bits;
+                bits += 8;
+            }
+            let code = (acc & ((1 << size) - 1)) as u16;
+            acc >>= size;
+            bits -= size;
+
+            if code == clear {
+                table = (0..clear).map(|idx| vec![idx as u8]).chain([vec![], vec![]]).collect();
+                size = MIN_CODE_SIZE as u32 + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code as usize), prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => {
+                    let mut entry = table[prev as usize].clone();
+                    entry.push(entry[0]);
+                    entry
+                },
+                (None, None) => panic!("code {} before any entry", code),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(prev) = prev {
+                if table.len() < MAX_CODE as usize {
+                    let mut added = table[prev as usize].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        // Long enough to fill the code table and force a clear.
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..40_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8 & 1
+        }).collect();
+        let runs: Vec<u8> = (0..5000).map(|idx| (idx / 37 % 2) as u8).collect();
+        let colors: Vec<u8> = (0..5000).map(|idx| (idx / 7 % TABLE_SIZE) as u8).collect();
+
+        for indices in [&[][..], &[1], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0], &runs, &noise, &colors] {
+            assert_eq!(lzw_decode(&lzw_encode(indices)), indices);
+        }
+    }
+
+    #[test]
+    fn header_follows_the_first_frame() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_variant(Variant::HiRes);
+        let mut recorder = GifRecorder::new(Palette::default(), 2, 1);
+        recorder.capture(&chip8_emulator);
+        let gif = recorder.finish();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(u16::from_le_bytes([gif[6], gif[7]]), WIDTH as u16 * 2);
+        assert_eq!(u16::from_le_bytes([gif[8], gif[9]]), 128);
+        assert_eq!(gif.last(), Some(&0x3B));
+
+        let empty = GifRecorder::new(Palette::default(), 1, 1).finish();
+        assert_eq!(u16::from_le_bytes([empty[8], empty[9]]), HEIGHT as u16);
+    }
+
+    #[test]
+    fn identical_frames_are_merged() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x12, 0x00]);
+        let mut recorder = GifRecorder::new(Palette::default(), 1, 1);
+        for _ in 0..6 {
+            recorder.capture(&chip8_emulator);
+        }
+        let gif = recorder.finish();
+        let controls: Vec<usize> = (0..gif.len() - 3).filter(|&idx| gif[idx..idx + 3] == [0x21, 0xF9, 0x04]).collect();
+        assert_eq!(controls.len(), 1);
+        // Six ticks at 60 fps are 10 hundredths of a second.
+        assert_eq!(u16::from_le_bytes([gif[controls[0] + 4], gif[controls[0] + 5]]), 10);
+    }
+
+    #[test]
+    fn chip8x_frames_keep_their_colours() {
+        // V2 = green, B020 colours the top-left zone, draw the font's first row at 0,0.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig::CHIP_8X).unwrap();
+        chip8_emulator.set_variant(Variant::Chip8X);
+        chip8_emulator.init(&[0x62, 0x04, 0xB0, 0x20, 0xA0, 0x00, 0xD0, 0x01]);
+        for _ in 0..4 {
+            chip8_emulator.emulate_cycle();
+        }
+        let mut recorder = GifRecorder::new(Palette::default(), 1, 1);
+        recorder.capture(&chip8_emulator);
+        let gif = recorder.finish();
+
+        let table: Vec<[u8; 3]> = gif[13..13 + TABLE_SIZE * 3].chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect();
+        let image = (0..gif.len() - 3).find(|&idx| gif[idx..idx + 3] == [0x21, 0xF9, 0x04]).unwrap() + 8;
+        assert_eq!((gif[image], gif[image + 10]), (0x2C, MIN_CODE_SIZE));
+        let image = image + 10;
+        let mut data = Vec::new();
+        let mut block = image + 1;
+        while gif[block] != 0 {
+            data.extend_from_slice(&gif[block + 1..block + 1 + gif[block] as usize]);
+            block += 1 + gif[block] as usize;
+        }
+        let indices = lzw_decode(&data);
+        for idx in 0..WIDTH * HEIGHT {
+            assert_eq!(table[indices[idx] as usize], chip8_emulator.pixel_rgb(&Palette::default(), idx), "pixel {}", idx);
+        }
+        assert_eq!(table[indices[0] as usize], CHIP8X_FOREGROUNDS[4]);
+    }
+}
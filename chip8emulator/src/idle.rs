@@ -0,0 +1,138 @@
+// Detection of programs that are spinning without doing anything: a `1NNN`
+// jump to itself, an `FX0A` waiting for a key, or a loop that returns to the
+// same backward jump with identical machine state and no side effects in
+// between (typically polling the delay timer).
+
+use crate::NUM_GP_REGISTERS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CycleState {
+    /// The program is making progress.
+    Running,
+    /// Nothing changes until the next timer tick or key event.
+    Idle,
+    /// Nothing will ever change again; only the timers still run down.
+    Halted,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LoopSignature {
+    pub(crate) gp_registers: [u8; NUM_GP_REGISTERS],
+    pub(crate) i: u16,
+    pub(crate) stack_pointer: u8,
+    pub(crate) delay_timer: u8,
+}
+
+#[derive(Clone)]
+pub(crate) struct IdleDetector {
+    state: CycleState,
+    last_jump: Option<(u16, LoopSignature)>,
+    side_effect: bool,
+}
+
+impl IdleDetector {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: CycleState::Running,
+            last_jump: None,
+            side_effect: false,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn state(&self) -> CycleState {
+        self.state
+    }
+
+    /// A timer tick may end an idle loop. The delay timer is part of the loop
+    /// signature, so a loop that polls it is not mistaken for idle afterwards.
+    pub(crate) fn timer_tick(&mut self) {
+        if self.state == CycleState::Idle {
+            self.state = CycleState::Running;
+        }
+    }
+
+    /// Key state is invisible to the loop signature, so forget the last loop.
+    pub(crate) fn input_changed(&mut self) {
+        self.timer_tick();
+        self.last_jump = None;
+    }
+
+    pub(crate) fn side_effect(&mut self) {
+        self.side_effect = true;
+        if self.state == CycleState::Idle {
+            self.state = CycleState::Running;
+        }
+    }
+
+    pub(crate) fn key_wait(&mut self, waiting: bool) {
+        self.state = if waiting { CycleState::Idle } else { CycleState::Running };
+    }
+
+    pub(crate) fn backward_jump(&mut self, pc: u16, target: u16, signature: LoopSignature) {
+        if pc == target {
+            self.state = CycleState::Halted;
+            return;
+        }
+
+        if !self.side_effect && self.last_jump == Some((pc, signature)) {
+            self.state = CycleState::Idle;
+        }
+        self.last_jump = Some((pc, signature));
+        self.side_effect = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Chip8Emulator, CycleState};
+
+    fn run_until_not_running(chip8_emulator: &mut Chip8Emulator, max_cycles: usize) -> CycleState {
+        for _ in 0..max_cycles {
+            let state = chip8_emulator.emulate_cycle();
+            if state != CycleState::Running {
+                return state;
+            }
+        }
+        CycleState::Running
+    }
+
+    #[test]
+    fn jump_to_self_halts() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(run_until_not_running(&mut chip8_emulator, 10), CycleState::Halted);
+        assert_eq!(chip8_emulator.cycles(), 2);
+    }
+
+    #[test]
+    fn key_wait_is_idle_until_a_key_changes() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0xF0, 0x0A, 0x12, 0x00]);
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Idle);
+        chip8_emulator.set_key(5, true);
+        chip8_emulator.set_key(5, false);
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Running);
+        assert_eq!(chip8_emulator.registers()[0], 5);
+    }
+
+    #[test]
+    fn delay_timer_polling_is_idle_between_ticks() {
+        // V0 = 5, DT = V0; loop: V1 = DT, skip if V1 == 0, jump back; then halt.
+        let rom = [0x60, 0x05, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x12, 0x0A];
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&rom);
+        assert_eq!(run_until_not_running(&mut chip8_emulator, 20), CycleState::Idle);
+
+        for _ in 0..5 {
+            chip8_emulator.advance_timers();
+            assert_eq!(chip8_emulator.cycle_state(), CycleState::Running);
+            run_until_not_running(&mut chip8_emulator, 20);
+        }
+        assert_eq!(chip8_emulator.cycle_state(), CycleState::Halted);
+        assert_eq!(chip8_emulator.program_counter(), 0x20A);
+    }
+}
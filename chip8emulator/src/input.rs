@@ -0,0 +1,133 @@
+// Keypad state plus an edge-triggered queue of press and release events.
+// The queue is what FX0A consumes, so a key that was already held when the
+// wait started cannot satisfy it.
+
+use std::collections::VecDeque;
+
+use crate::NUM_KEYS;
+
+const MAX_QUEUED_EVENTS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct KeyEvent {
+    pub(crate) key: u8,
+    pub(crate) pressed: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct Input {
+    pub(crate) pressed: [bool; NUM_KEYS],
+    /// CHIP-8X second keypad; only EXF2 and EXF5 read it.
+    pub(crate) second: [bool; NUM_KEYS],
+    events: VecDeque<KeyEvent>,
+    pub(crate) waiting: bool,
+    pub(crate) wait_key: Option<u8>,
+}
+
+impl Input {
+    pub(crate) fn new() -> Self {
+        Self {
+            pressed: [false; NUM_KEYS],
+            second: [false; NUM_KEYS],
+            events: VecDeque::new(),
+            waiting: false,
+            wait_key: None,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    /// Records a key change. Returns false if the key was already in that state.
+    pub(crate) fn set_key(&mut self, key: usize, pressed: bool) -> bool {
+        if self.pressed[key] == pressed {
+            return false;
+        }
+        self.pressed[key] = pressed;
+
+        if self.events.len() == MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(KeyEvent { key: key as u8, pressed });
+        true
+    }
+
+    /// One FX0A step. Returns the key once the wait completes: on release of
+    /// the first key pressed during the wait as on the COSMAC VIP, or on the
+    /// press itself with `on_press`.
+    pub(crate) fn wait_for_key(&mut self, on_press: bool) -> Option<u8> {
+        if !self.waiting {
+            self.waiting = true;
+            self.wait_key = None;
+            self.events.clear();
+        }
+
+        while let Some(event) = self.events.pop_front() {
+            let done = match (event.pressed, self.wait_key) {
+                (true, None) if on_press => true,
+                (true, None) => {
+                    self.wait_key = Some(event.key);
+                    false
+                },
+                (false, Some(key)) => key == event.key,
+                _ => false,
+            };
+
+            if done {
+                self.waiting = false;
+                self.wait_key = None;
+                return Some(event.key);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_key_does_not_satisfy_a_new_wait() {
+        let mut input = Input::new();
+        input.set_key(3, true);
+        assert_eq!(input.wait_for_key(false), None);
+        input.set_key(3, false);
+        assert_eq!(input.wait_for_key(false), None);
+        assert!(input.waiting);
+    }
+
+    #[test]
+    fn wait_completes_on_release_of_the_first_key() {
+        let mut input = Input::new();
+        assert_eq!(input.wait_for_key(false), None);
+        input.set_key(7, true);
+        input.set_key(2, true);
+        input.set_key(2, false);
+        assert_eq!(input.wait_for_key(false), None);
+        input.set_key(7, false);
+        assert_eq!(input.wait_for_key(false), Some(7));
+        assert!(!input.waiting);
+    }
+
+    #[test]
+    fn press_quirk_completes_on_the_press() {
+        let mut input = Input::new();
+        assert_eq!(input.wait_for_key(true), None);
+        input.set_key(0xA, true);
+        assert_eq!(input.wait_for_key(true), Some(0xA));
+    }
+
+    #[test]
+    fn repeated_state_is_not_an_event() {
+        let mut input = Input::new();
+        assert!(input.set_key(1, true));
+        assert!(!input.set_key(1, true));
+        assert_eq!(input.events.len(), 1);
+    }
+}
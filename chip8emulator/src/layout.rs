@@ -0,0 +1,198 @@
+// Where the interpreter keeps its own state. With `Separate` the call stack,
+// V registers and display live only in their own structs. With `Vip` they
+// are also mapped into RAM where the COSMAC VIP interpreter kept them, at
+// the top of RAM; with 4 KiB that is:
+//
+//     0xEB8-0xECF  call stack, 12 return addresses growing down from 0xECF
+//     0xEF0-0xEFF  V0-VF
+//     0xF00-0xFFF  display buffer, 8 bytes per row, MSB leftmost
+//
+// so programs that peek or poke them behave as on the hardware, and calls
+// nest at most 12 deep. Return addresses are kept as full 16-bit words, so
+// they reach all of a RAM larger than 4 KiB. With the hi-res variant only
+// the top 32 rows of the display are mapped.
+//
+// The structs stay the working copy. After every instruction and every
+// mutator the two sides are merged byte by byte against the image from the
+// previous merge: a byte that changed in RAM wins, otherwise the struct's
+// value is written back to RAM.
+
+use crate::vip::{vip_address, VIP_DISPLAY, VIP_REGISTERS, VIP_STACK_TOP};
+use crate::{Chip8Emulator, HEIGHT, NUM_GP_REGISTERS, STACK_SIZE, WIDTH};
+
+pub const VIP_STACK_DEPTH: usize = 12;
+
+/// Mapped area as 4 KiB addresses.
+const IMAGE_BASE: u16 = VIP_STACK_TOP + 1 - VIP_STACK_DEPTH as u16 * 2;
+const IMAGE_LEN: usize = 0x1000 - IMAGE_BASE as usize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryLayout {
+    #[default]
+    Separate,
+    Vip,
+}
+
+#[derive(Clone)]
+pub(crate) struct LayoutSync {
+    pub(crate) layout: MemoryLayout,
+    /// Mapped RAM as of the last merge.
+    image: [u8; IMAGE_LEN],
+}
+
+impl Default for LayoutSync {
+    fn default() -> Self {
+        Self { layout: MemoryLayout::Separate, image: [0; IMAGE_LEN] }
+    }
+}
+
+/// Image offsets of the high and low byte of stack entry `idx`.
+fn stack_slot(idx: usize) -> (usize, usize) {
+    let low = (VIP_STACK_TOP - IMAGE_BASE) as usize - idx * 2;
+    (low - 1, low)
+}
+
+impl Chip8Emulator {
+    /// Switches the layout; with `Vip` the current state is written into RAM.
+    pub fn set_memory_layout(&mut self, layout: MemoryLayout) {
+        self.layout.layout = layout;
+        self.reset_layout();
+        self.sync_layout();
+    }
+
+    pub fn memory_layout(&self) -> MemoryLayout {
+        self.layout.layout
+    }
+
+    /// How many calls can be nested before 2NNN overflows.
+    pub fn stack_depth(&self) -> usize {
+        match self.layout.layout {
+            MemoryLayout::Separate => STACK_SIZE,
+            MemoryLayout::Vip => VIP_STACK_DEPTH,
+        }
+    }
+
+    /// Forgets the last merge after RAM and the structs were both reset.
+    pub(crate) fn reset_layout(&mut self) {
+        let start = vip_address(self.memory.ram.len(), IMAGE_BASE);
+        self.layout.image.copy_from_slice(&self.memory.ram[start..]);
+    }
+
+    /// Merges the mapped RAM with the stack, registers and display.
+    pub(crate) fn sync_layout(&mut self) {
+        if self.layout.layout != MemoryLayout::Vip {
+            return;
+        }
+
+        let start = vip_address(self.memory.ram.len(), IMAGE_BASE);
+        let mut mapped = [0u8; IMAGE_LEN];
+        mapped.copy_from_slice(&self.memory.ram[start..]);
+        let depth = (self.stack.stack_pointer as usize).min(VIP_STACK_DEPTH);
+        for idx in 0..depth {
+            let (high, low) = stack_slot(idx);
+            let [hi, lo] = self.stack.stack[idx].wrapping_add(2).to_be_bytes();
+            mapped[high] = hi;
+            mapped[low] = lo;
+        }
+        let v = (VIP_REGISTERS - IMAGE_BASE) as usize;
+        mapped[v..v + NUM_GP_REGISTERS].copy_from_slice(&self.registers.gp_registers);
+        let display = (VIP_DISPLAY - IMAGE_BASE) as usize;
+        for (byte, pixels) in mapped[display..].iter_mut().zip(self.graphic.pixels.chunks(8)) {
+            *byte = pixels.iter().fold(0, |acc, &pixel| acc << 1 | pixel);
+        }
+
+        for (idx, value) in mapped.iter().enumerate() {
+            let ram = &mut self.memory.ram[start + idx];
+            if *ram == self.layout.image[idx] {
+                *ram = *value;
+            }
+            self.layout.image[idx] = *ram;
+        }
+
+        let image = &self.layout.image;
+        for idx in 0..depth {
+            let (high, low) = stack_slot(idx);
+            let address = u16::from_be_bytes([image[high], image[low]]);
+            self.stack.stack[idx] = address.wrapping_sub(2);
+        }
+        self.registers.gp_registers.copy_from_slice(&image[v..v + NUM_GP_REGISTERS]);
+        for idx in 0..WIDTH * HEIGHT {
+            let bit = image[display + idx / 8] >> (7 - idx % 8) & 1;
+            if self.graphic.pixels[idx] != bit {
+                self.graphic.pixels[idx] = bit;
+                self.draw_flag = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CrashReason, CycleState};
+
+    fn vip(rom: &[u8]) -> Chip8Emulator {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_layout(MemoryLayout::Vip);
+        chip8_emulator.init(rom);
+        chip8_emulator
+    }
+
+    #[test]
+    fn registers_and_display_are_mapped_into_ram() {
+        // VA = 0x42, draw the font's first row at 0,0.
+        let mut chip8_emulator = vip(&[0x6A, 0x42, 0xA0, 0x00, 0xD0, 0x01]);
+        for _ in 0..3 {
+            chip8_emulator.emulate_cycle();
+        }
+        assert_eq!(chip8_emulator.memory.ram[0xEFA], 0x42);
+        assert_eq!(chip8_emulator.memory.ram[0xF00], 0xF0);
+    }
+
+    #[test]
+    fn ram_writes_win_over_the_structs() {
+        // I = 0xEF5, V0 = 0x77, store V0 there, so V5 becomes 0x77.
+        let mut chip8_emulator = vip(&[0xAE, 0xF5, 0x60, 0x77, 0xF0, 0x55]);
+        for _ in 0..3 {
+            chip8_emulator.emulate_cycle();
+        }
+        assert_eq!(chip8_emulator.registers()[5], 0x77);
+    }
+
+    #[test]
+    fn return_addresses_can_be_patched_in_ram() {
+        // Call 0x206, which rewrites its return address to 0x20C and returns.
+        let mut chip8_emulator = vip(&[
+            0x22, 0x06, 0x00, 0x00, 0x00, 0x00,
+            0xAE, 0xCE, 0x60, 0x02, 0x61, 0x0C, 0xF1, 0x55, 0x00, 0xEE,
+        ]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.memory.ram[0xECE..0xED0], [0x02, 0x02]);
+        for _ in 0..5 {
+            chip8_emulator.emulate_cycle();
+        }
+        assert_eq!(chip8_emulator.program_counter(), 0x20C);
+    }
+
+    #[test]
+    fn calls_nest_twelve_deep() {
+        // Call itself forever.
+        let mut chip8_emulator = vip(&[0x22, 0x00]);
+        assert_eq!(chip8_emulator.stack_depth(), VIP_STACK_DEPTH);
+        for _ in 0..VIP_STACK_DEPTH {
+            assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Running);
+        }
+        assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Halted);
+        assert_eq!(chip8_emulator.take_crash_dump().unwrap().reason, CrashReason::StackOverflow);
+    }
+
+    #[test]
+    fn separate_layout_leaves_ram_alone() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x6A, 0x42]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.memory.ram[0xEFA], 0);
+        assert_eq!(chip8_emulator.stack_depth(), STACK_SIZE);
+    }
+}
@@ -1,30 +1,134 @@
 use rand::Rng;
 
+use std::collections::VecDeque;
+
+use chip8x::COLOR_COLUMNS;
+use hash::Fnv64;
+use events::EventQueue;
+use layout::LayoutSync;
+use timing::Timing;
+use idle::{IdleDetector, LoopSignature};
+use input::Input;
+use protection::{Access, MemoryProtector};
+
+mod achievements;
+mod cdp1802;
+mod cheats;
+mod chip8x;
+mod coverage;
+mod crash;
+mod debugger;
+mod disasm;
+mod events;
+mod extension;
+mod font;
+mod gif;
+mod hash;
+mod idle;
+mod input;
+mod layout;
+mod memory;
+mod movie;
+mod png;
+mod profiler;
+mod protection;
+mod snapshot;
+mod state;
+mod timing;
+mod trace;
+mod variant;
+mod vip;
+mod wav;
+
+pub use achievements::{Achievement, AchievementEngine, AchievementError, Comparison, Condition, Operand};
+pub use cdp1802::{Cdp1802, Cdp1802Io};
+pub use cheats::{Cheat, CheatError, CheatSearch, CheatTarget, SearchFilter};
+pub use chip8x::{CHIP8X_BACKGROUNDS, CHIP8X_FOREGROUNDS};
+pub use coverage::{Coverage, CoverageSummary};
+pub use crash::{CrashDump, CrashDumpError, CrashReason};
+pub use debugger::{Debugger, StopReason, WriteHit};
+pub use disasm::disassemble;
+pub use events::{EmulatorEvent, EventFilter, Timer};
+pub use extension::{ExtensionClone, MachineRoutines, OpcodeExtension};
+pub use font::{BuiltinFont, Font, FontError};
+pub use gif::GifRecorder;
+pub use idle::CycleState;
+pub use layout::{MemoryLayout, VIP_STACK_DEPTH};
+pub use memory::{MemoryConfig, MemoryConfigError, MAX_MEMORY_SIZE, MIN_MEMORY_SIZE};
+pub use movie::{Movie, MovieError, MoviePlayer, MovieRecord};
+pub use profiler::{Profiler, SubroutineStats};
+pub use protection::{MemoryFault, MemoryFaultKind, MemoryProtection};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use state::{CpuState, StateError};
+pub use timing::{vip_cycles, TimingMode, VIP_CYCLES_PER_FRAME};
+pub use trace::{CallTrace, SymbolMap};
+pub use variant::{Variant, HIRES_HEIGHT};
+pub use vip::VipHybrid;
+pub use wav::WavRecorder;
+
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
 
-const MEMORY_SIZE: usize = 4096;
 const NUM_GP_REGISTERS: usize = 16;
 const STACK_SIZE: usize = 16;
 const NUM_FONTS: usize = 16;
 const FONT_ADDRESS_OFFSET: usize = 5;
 const NUM_KEYS: usize = 16;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            foreground: [0xFF, 0xFF, 0xFF],
+            background: [0x00, 0x00, 0x00],
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Chip8Emulator {
     memory: Memory,
     registers: Registers,
     stack: Stack,
     graphic : Graphic,
-    font_set: FontSet,
+    font: Font,
     draw_flag: bool,
     input: Input,
+    quirks: Quirks,
+    cycles: u64,
+    frames: u64,
+    rng_state: u64,
+    rng_seed: u64,
+    rom: Vec<u8>,
+    movie: Option<Movie>,
+    cheats: Vec<Cheat>,
+    coverage: Option<Coverage>,
+    profiler: Option<Profiler>,
+    call_trace: Option<CallTrace>,
+    history: VecDeque<(u16, u16)>,
+    crash_reason: Option<CrashReason>,
+    crash_dump: Option<CrashDump>,
+    protector: MemoryProtector,
+    idle: IdleDetector,
+    events: EventQueue,
+    extensions: Vec<Box<dyn OpcodeExtension>>,
+    timing: Timing,
+    layout: LayoutSync,
+    variant: Variant,
 }
 
+#[derive(Clone)]
 struct Memory {
-    ram: [u8; MEMORY_SIZE],
-    program_start_address: u16,
+    ram: Vec<u8>,
+    config: MemoryConfig,
 }
 
+#[derive(Clone)]
 struct Registers {
     gp_registers: [u8; NUM_GP_REGISTERS],
     i: u16,
@@ -33,29 +137,42 @@ struct Registers {
     sound_timer: u8,
 }
 
+#[derive(Clone)]
 struct Stack {
     stack: [u16; STACK_SIZE],
     stack_pointer: u8,
 }
 
+#[derive(Clone)]
 struct Graphic {
-    pixels: [u8; WIDTH * HEIGHT],
+    /// Room for the tallest display; rows past `display_height` stay unused.
+    pixels: [u8; WIDTH * HIRES_HEIGHT],
+    /// CHIP-8X foreground colour per 8x1 cell.
+    colors: [u8; COLOR_COLUMNS * HEIGHT],
+    /// CHIP-8X background colour.
+    background: u8,
 }
 
-struct FontSet {
-    font_set: [u8; NUM_FONTS * FONT_ADDRESS_OFFSET],
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// FX0A completes as soon as a key goes down instead of on its release.
+    pub key_wait_on_press: bool,
 }
 
-struct Input {
-    pressed: [bool; NUM_KEYS],
+impl Default for Chip8Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8Emulator {
     pub fn new() -> Self {
+        let rng_seed = rand::thread_rng().gen();
         Self {
             memory: Memory {
-                ram: [0; MEMORY_SIZE],
-                program_start_address: 0x200,
+                ram: vec![0; MemoryConfig::default().ram_size],
+                config: MemoryConfig::default(),
             },
             registers: Registers {
                 gp_registers: [0; NUM_GP_REGISTERS],
@@ -69,101 +186,160 @@ impl Chip8Emulator {
                 stack_pointer: 0,
             },
             graphic: Graphic {
-                pixels: [0; WIDTH * HEIGHT],
-            },
-            font_set: FontSet {
-                font_set: [
-                    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-                    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-                    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-                    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-                    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-                    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-                    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-                    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-                    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-                    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-                    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-                    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-                    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-                    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-                    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-                    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-                ],
+                pixels: [0; WIDTH * HIRES_HEIGHT],
+                colors: [chip8x::DEFAULT_FOREGROUND; COLOR_COLUMNS * HEIGHT],
+                background: 0,
             },
+            font: Font::default(),
             draw_flag: false,
-            input: Input {
-                pressed: [false; NUM_KEYS],
-            },
+            input: Input::new(),
+            quirks: Quirks::default(),
+            cycles: 0,
+            frames: 0,
+            rng_state: rng_seed,
+            rng_seed,
+            rom: Vec::new(),
+            movie: None,
+            cheats: Vec::new(),
+            coverage: None,
+            profiler: None,
+            call_trace: None,
+            history: VecDeque::with_capacity(crash::CRASH_HISTORY_LEN),
+            crash_reason: None,
+            crash_dump: None,
+            protector: MemoryProtector::new(MemoryConfig::default().ram_size),
+            idle: IdleDetector::new(),
+            events: EventQueue::default(),
+            extensions: Vec::new(),
+            timing: Timing::default(),
+            layout: LayoutSync::default(),
+            variant: Variant::default(),
         }
     }
 
+    /// Loads the font and `buffer` as the program. Panics if the ROM does not
+    /// fit; see `try_init`.
     pub fn init(&mut self, buffer: &[u8]) {
-        self.load_font_set();
-        self.load_program(buffer);
+        if let Err(err) = self.try_init(buffer) {
+            panic!("{}", err);
+        }
     }
 
     fn load_font_set(&mut self) {
-        self.memory.ram[..(NUM_FONTS * FONT_ADDRESS_OFFSET)].copy_from_slice(&self.font_set.font_set);
+        let font_address = self.memory.config.font_address as usize;
+        self.memory.ram[font_address..font_address + self.font.len()].copy_from_slice(&self.font.as_bytes());
     }
 
     fn load_program(&mut self, buffer: &[u8]) {
-        let program_start_memory_address = self.memory.program_start_address as usize;
-        let program_end_memory_adderess = program_start_memory_address as usize + buffer.len();
+        let program_start_memory_address = self.memory.config.program_start as usize;
+        let program_end_memory_adderess = program_start_memory_address + buffer.len();
         self.memory.ram[program_start_memory_address..program_end_memory_adderess].copy_from_slice(buffer);
+        self.rom = buffer.to_vec();
+        self.protector.set_program(program_start_memory_address..program_end_memory_adderess);
     }
 
     pub fn reset(&mut self) {
-        self.memory.ram = [0; MEMORY_SIZE];
+        self.memory.ram = vec![0; self.memory.config.ram_size];
         self.registers.gp_registers = [0; NUM_GP_REGISTERS];
         self.registers.i = 0;
-        self.registers.program_counter = 0x200;
+        self.registers.program_counter = self.memory.config.program_start;
         self.registers.delay_timer = 0;
         self.registers.sound_timer = 0;
         self.stack.stack = [0; STACK_SIZE];
         self.stack.stack_pointer = 0;
-        self.graphic.pixels = [0; WIDTH * HEIGHT];
-        self.input.pressed = [false; NUM_KEYS];
+        self.graphic.pixels = [0; WIDTH * HIRES_HEIGHT];
+        self.reset_colors();
+        self.input.reset();
+        self.cycles = 0;
+        self.frames = 0;
+        self.rng_state = self.rng_seed;
+        self.protector.reset();
+        self.idle.reset();
+        self.history.clear();
+        self.crash_reason = None;
+        self.timing.reset();
+        self.reset_layout();
     }
 
-    pub fn emulate_cycle(&mut self) {
-        let opcode = self.fetch_opcode();
+    /// Executes one instruction and reports whether the program is still making progress.
+    /// Frontends can stop calling this on `Idle` until the next timer tick or key event,
+    /// and on `Halted` altogether.
+    pub fn emulate_cycle(&mut self) -> CycleState {
+        if self.is_trapped() {
+            return CycleState::Halted;
+        }
+
+        let opcode = self.fetch_opcode_checked();
         let (op1, op2, op3, op4, x, y, n, nnn, kk) = self.decode_opcode(opcode);
 
-        self.registers.program_counter += 2;
+        if !self.check_memory_access(opcode) {
+            return CycleState::Halted;
+        }
+        self.record_coverage(opcode);
+
+        let pc = self.registers.program_counter;
+        let sound_was_playing = self.is_sound_playing();
+        self.events.push(EmulatorEvent::BeforeInstruction { pc, opcode });
+        // Wraps at the top of a 64 KiB RAM rather than overflowing.
+        self.registers.program_counter = pc.wrapping_add(2);
+        let (vx, v0, i) = (self.registers.gp_registers[x], self.registers.gp_registers[0], self.registers.i);
+        let chip8x = self.variant == Variant::Chip8X;
+        let hires = self.variant == Variant::HiRes;
 
         match (op1, op2, op3, op4) {
+            (0x0, 0x2, 0xA, 0x0) if chip8x => self.cycle_background(),
+            (0x0, 0x2, 0x3, 0x0) if hires => {
+                self.graphic.pixels = [0; WIDTH * HIRES_HEIGHT];
+                self.set_draw_flag(true);
+                self.events.push(EmulatorEvent::ClearScreen);
+                self.idle.side_effect();
+            },
             (0x0, 0x0, 0xE, 0x0) => {
-                self.graphic.pixels = [0; WIDTH * HEIGHT];
+                self.graphic.pixels = [0; WIDTH * HIRES_HEIGHT];
                 self.set_draw_flag(true);
+                self.events.push(EmulatorEvent::ClearScreen);
             },
             (0x0, 0x0, 0xE, 0xE) => {
+                if self.stack.stack_pointer == 0 {
+                    return self.crash(CrashReason::StackUnderflow, pc, opcode);
+                }
                 self.stack.stack_pointer -= 1;
-                self.registers.program_counter = self.stack.stack[self.stack.stack_pointer as usize] + 2;
+                self.registers.program_counter = self.stack.stack[self.stack.stack_pointer as usize].wrapping_add(2);
+                self.events.push(EmulatorEvent::SubroutineReturn { from: pc, to: self.registers.program_counter });
+            },
+            (0x1, 0x2, 0x6, 0x0) if hires && pc == self.memory.config.program_start => {
+                self.registers.program_counter = variant::HIRES_ENTRY;
             },
             (0x1, _, _, _) => {
                 self.registers.program_counter = nnn;
             },
             (0x2, _, _, _) => {
-                self.stack.stack[self.stack.stack_pointer as usize] = self.registers.program_counter - 2;
+                if self.stack.stack_pointer as usize >= self.stack_depth() {
+                    return self.crash(CrashReason::StackOverflow, pc, opcode);
+                }
+                self.stack.stack[self.stack.stack_pointer as usize] = pc;
                 self.stack.stack_pointer += 1;
                 self.registers.program_counter = nnn;
+                self.events.push(EmulatorEvent::SubroutineCall { from: pc, to: nnn });
             },
             (0x3, _, _, _) => {
                 if self.registers.gp_registers[x] == kk as u8 {
-                    self.registers.program_counter += 2;
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
                 }
             }, 
             (0x4, _, _, _) => {
                 if self.registers.gp_registers[x] != kk as u8 {
-                    self.registers.program_counter += 2;
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
                 }
             },
             (0x5, _, _, 0x0) => {
                 if self.registers.gp_registers[x] == self.registers.gp_registers[y] {
-                    self.registers.program_counter += 2;
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
                 }
             },
+            (0x5, _, _, 0x1) if chip8x => {
+                self.registers.gp_registers[x] = chip8x::add_octal(vx, self.registers.gp_registers[y]);
+            },
             (0x6, _, _, _) => {
                 self.registers.gp_registers[x] = kk as u8;
             },
@@ -209,22 +385,25 @@ impl Chip8Emulator {
             },
             (0x9, _, _, 0x0) => {
                 if self.registers.gp_registers[x] != self.registers.gp_registers[y] {
-                    self.registers.program_counter += 2;
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
                 }
             },
             (0xA, _, _, _) => {
                 self.registers.i = nnn;
             },
+            (0xB, _, _, 0x0) if chip8x => self.color_zones(x, y),
+            (0xB, _, _, _) if chip8x => self.color_rows(x, y, n),
             (0xB, _, _, _) => {
-                self.registers.program_counter = self.registers.gp_registers[0 as usize] as u16 + nnn;
+                self.registers.program_counter = self.registers.gp_registers[0] as u16 + nnn;
             },
             (0xC, _, _, _) => {
-                let rand: u8 = rand::thread_rng().gen();
+                let rand = self.next_random();
                 self.registers.gp_registers[x] = rand & kk as u8;
             },
             (0xD, _, _, _) => {
                 let x_val = self.registers.gp_registers[x];
                 let y_val = self.registers.gp_registers[y];
+                let height = self.display_height();
 
                 self.registers.gp_registers[NUM_GP_REGISTERS-1] = 0;
                 
@@ -232,58 +411,79 @@ impl Chip8Emulator {
                     let pixel = self.memory.ram[(self.registers.i + row) as usize];
                     for col in 0..8 {
                         if pixel & (0x80 >> col) != 0 {
-                            let r = (y_val as u16 + row) as usize % 32;
-                            let c = (x_val as u16 + col) as usize % 64;
-                            if self.graphic.pixels[(c + 64 * r)] == 1 {
+                            let r = (y_val as u16 + row) as usize % height;
+                            let c = (x_val as u16 + col) as usize % WIDTH;
+                            if self.graphic.pixels[c + WIDTH * r] == 1 {
                                 self.registers.gp_registers[NUM_GP_REGISTERS-1] = 1;
                             }
-                            self.graphic.pixels[(c + 64 * r)] ^= 1; 
+                            self.graphic.pixels[c + WIDTH * r] ^= 1; 
                         }
                     }
                 } 
 
                 self.set_draw_flag(true);
+                let collision = self.registers.gp_registers[NUM_GP_REGISTERS-1] == 1;
+                self.events.push(EmulatorEvent::SpriteDraw { x: x_val, y: y_val, height: n as u8, collision });
+            },
+            // There is no key to test past F.
+            (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) if vx as usize >= NUM_KEYS => {
+                return self.crash(CrashReason::InvalidOpcode, pc, opcode);
             },
             (0xE, _, 0x9, 0xE) => {
                 let val = self.registers.gp_registers[x];
                 if self.input.pressed[val as usize] {
-                    self.registers.program_counter += 2;
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
                 }
             },
             (0xE, _, 0xA, 0x1) => {
                 let val = self.registers.gp_registers[x];
                 if !self.input.pressed[val as usize] {
-                    self.registers.program_counter += 2;
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
+                }
+            },
+            (0xE, _, 0xF, 0x2) if chip8x => {
+                if self.second_key_pressed(vx) {
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
+                }
+            },
+            (0xE, _, 0xF, 0x5) if chip8x => {
+                if !self.second_key_pressed(vx) {
+                    self.registers.program_counter = self.registers.program_counter.wrapping_add(2);
                 }
             },
             (0xF, _, 0x0, 0x7) => {
                 self.registers.gp_registers[x] = self.registers.delay_timer;
             },
             (0xF, _, 0x0, 0xA) => {
-                let mut pressed = false;
-                for (idx, val) in self.input.pressed.iter().enumerate() {
-                    if *val {
-                        self.registers.gp_registers[x] = idx as u8;
-                        pressed = true;
-                        break;
-                    }
+                if !self.input.waiting {
+                    self.events.push(EmulatorEvent::KeyWaitStart);
                 }
-
-                if !pressed {
-                    self.registers.program_counter -= 2;
+                match self.input.wait_for_key(self.quirks.key_wait_on_press) {
+                    Some(key) => {
+                        self.registers.gp_registers[x] = key;
+                        self.events.push(EmulatorEvent::KeyWaitEnd { key });
+                    },
+                    None => self.registers.program_counter = pc,
                 }
             },
             (0xF, _, 0x1, 0x5) => {
                 self.registers.delay_timer = self.registers.gp_registers[x];
+                self.events.push(EmulatorEvent::TimerWrite { timer: Timer::Delay, value: self.registers.delay_timer });
             },
             (0xF, _, 0x1, 0x8) => {
                 self.registers.sound_timer = self.registers.gp_registers[x];
+                self.events.push(EmulatorEvent::TimerWrite { timer: Timer::Sound, value: self.registers.sound_timer });
             },
             (0xF, _, 0x1, 0xE) => {
                 self.registers.i = self.registers.i.wrapping_add(self.registers.gp_registers[x].into());
             },
             (0xF, _, 0x2, 0x9) => {
-                self.registers.i = (self.registers.gp_registers[x] * FONT_ADDRESS_OFFSET as u8) as u16;
+                let digit = (self.registers.gp_registers[x] & 0x0F) as u16;
+                self.registers.i = self.memory.config.font_address + digit * FONT_ADDRESS_OFFSET as u16;
+            },
+            (0xF, _, 0x3, 0x0) if self.font.big_glyphs() > 0 => {
+                let digit = (self.registers.gp_registers[x] & 0x0F) as u16;
+                self.registers.i = self.memory.config.font_address + (font::SMALL_FONT_LEN + digit as usize * font::BIG_GLYPH_LEN) as u16;
             },
             (0xF, _, 0x3, 0x3) => {
                 let val = self.registers.gp_registers[x] as f32;
@@ -293,15 +493,127 @@ impl Chip8Emulator {
             },
             (0xF, _, 0x5, 0x5) => {
                 for idx in 0..=x {
-                    self.memory.ram[self.registers.i as usize + idx as usize] = self.registers.gp_registers[idx as usize];
+                    self.memory.ram[self.registers.i as usize + idx] = self.registers.gp_registers[idx];
                 }
             },
             (0xF, _, 0x6, 0x5) => {
                 for idx in 0..=x {
-                    self.registers.gp_registers[idx as usize] = self.memory.ram[self.registers.i as usize + idx as usize];
+                    self.registers.gp_registers[idx] = self.memory.ram[self.registers.i as usize + idx];
+                }
+            },
+            (_, _, _, _) => {
+                if !self.run_extension(opcode) {
+                    return self.crash(CrashReason::InvalidOpcode, pc, opcode);
                 }
+                // Anything could have changed.
+                self.idle.side_effect();
             },
-            (_, _, _, _) => unimplemented!("Opcode is not implemented: {:#04x}", opcode),
+        }
+
+        self.cycles += 1;
+        let skipped = self.registers.program_counter == pc.wrapping_add(4);
+        self.timing.charge(timing::vip_cycles(opcode, vx, v0, i, skipped), op1 == 0xD);
+        crash::push_history(&mut self.history, pc, opcode);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_instruction(pc, opcode);
+        }
+        if let Some(call_trace) = self.call_trace.as_mut() {
+            call_trace.record_instruction(pc, opcode, self.cycles);
+        }
+        self.apply_cheats();
+        self.sync_layout();
+        self.update_cycle_state(pc, op1, op3, op4, nnn);
+        self.events.push(EmulatorEvent::AfterInstruction { pc, opcode });
+        self.push_sound_event(sound_was_playing);
+        self.idle.state()
+    }
+
+    fn update_cycle_state(&mut self, pc: u16, op1: u8, op3: u8, op4: u8, nnn: u16) {
+        match (op1, op3, op4) {
+            (0x1, _, _) if nnn <= pc => {
+                let signature = LoopSignature {
+                    gp_registers: self.registers.gp_registers,
+                    i: self.registers.i,
+                    stack_pointer: self.stack.stack_pointer,
+                    delay_timer: self.registers.delay_timer,
+                };
+                self.idle.backward_jump(pc, nnn, signature);
+            },
+            (0xF, 0x0, 0xA) => self.idle.key_wait(self.registers.program_counter == pc),
+            (0x0, 0xE, 0x0) | (0xC, _, _) | (0xD, _, _) |
+            (0xF, 0x1, 0x5) | (0xF, 0x1, 0x8) | (0xF, 0x3, 0x3) | (0xF, 0x5, 0x5) => self.idle.side_effect(),
+            _ => (),
+        }
+    }
+
+    fn push_sound_event(&mut self, was_playing: bool) {
+        match (was_playing, self.is_sound_playing()) {
+            (false, true) => self.events.push(EmulatorEvent::SoundStart),
+            (true, false) => self.events.push(EmulatorEvent::SoundStop),
+            _ => (),
+        }
+    }
+
+    pub fn cycle_state(&self) -> CycleState {
+        if self.is_trapped() {
+            CycleState::Halted
+        } else {
+            self.idle.state()
+        }
+    }
+
+    // splitmix64: tiny state, so snapshots and movies can carry it exactly.
+    fn next_random(&mut self) -> u8 {
+        self.rng_state = self.rng_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+
+    fn fetch_opcode_checked(&self) -> u16 {
+        let pc = self.registers.program_counter as usize;
+        if pc + 1 >= self.memory.ram.len() {
+            return 0;
+        }
+        self.fetch_opcode()
+    }
+
+    /// Runs the memory protection checks, crashing the machine on a trap.
+    fn check_memory_access(&mut self, opcode: u16) -> bool {
+        let pc = self.registers.program_counter;
+        let accesses = self.memory_accesses(opcode);
+        if self.protector.check(pc, opcode, &accesses) {
+            return true;
+        }
+
+        if let Some(fault) = self.protector.trap() {
+            self.crash(CrashReason::Memory { kind: fault.kind, address: fault.address }, pc, opcode);
+        }
+        false
+    }
+
+    /// Memory touched by `opcode` if it were executed at the current PC.
+    pub(crate) fn memory_accesses(&self, opcode: u16) -> Vec<Access> {
+        let (op1, _, op3, op4, x, _, n, _, _) = self.decode_opcode(opcode);
+        let i = self.registers.i as usize;
+        let mut accesses = vec![Access::Execute(self.registers.program_counter as usize)];
+        match (op1, op3, op4) {
+            (0xD, _, _) => accesses.push(Access::Read(i..i + n as usize)),
+            (0xF, 0x3, 0x3) => accesses.push(Access::Write(i..i + 3)),
+            (0xF, 0x5, 0x5) => accesses.push(Access::Write(i..i + x + 1)),
+            (0xF, 0x6, 0x5) => accesses.push(Access::Read(i..i + x + 1)),
+            _ => (),
+        }
+        accesses
+    }
+
+    /// The opcode at PC, or None if PC points past the end of RAM.
+    pub(crate) fn peek_opcode(&self) -> Option<u16> {
+        if self.registers.program_counter as usize + 1 < self.memory.ram.len() {
+            Some(self.fetch_opcode())
+        } else {
+            None
         }
     }
 
@@ -324,6 +636,9 @@ impl Chip8Emulator {
     }
 
     pub fn advance_timers(&mut self) {
+        self.idle.timer_tick();
+        let sound_was_playing = self.is_sound_playing();
+
         if self.registers.delay_timer > 0 {
             self.registers.delay_timer -= 1;
         }
@@ -334,6 +649,121 @@ impl Chip8Emulator {
             }
             self.registers.sound_timer -= 1;
         }
+        self.push_sound_event(sound_was_playing);
+
+        self.frames += 1;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_frame();
+        }
+        if let Some(call_trace) = self.call_trace.as_mut() {
+            call_trace.record_frame(self.cycles);
+        }
+        if let Some(mut movie) = self.movie.take() {
+            movie.records.push(MovieRecord::Frame { cycles: self.cycles, state_hash: self.state_hash() });
+            self.movie = Some(movie);
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Number of instructions executed since the last reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.registers.program_counter
+    }
+
+    /// Number of timer ticks since the last reset.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Seeds the CXNN random number generator; `reset` restarts the sequence.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng_seed = seed;
+        self.rng_state = seed;
+    }
+
+    pub fn rng_seed(&self) -> u64 {
+        self.rng_seed
+    }
+
+    pub fn rom_sha1(&self) -> String {
+        hash::to_hex(&hash::sha1(&self.rom))
+    }
+
+    /// Resets the machine and reloads the current ROM.
+    pub fn restart(&mut self) {
+        let rom = std::mem::take(&mut self.rom);
+        self.reset();
+        self.init(&rom);
+    }
+
+    /// Hash of everything that determines future execution and output.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = Fnv64::new();
+        hasher.write(&self.memory.ram);
+        hasher.write(&self.registers.gp_registers);
+        hasher.write(&self.registers.i.to_le_bytes());
+        hasher.write(&self.registers.program_counter.to_le_bytes());
+        hasher.write(&[self.registers.delay_timer, self.registers.sound_timer, self.stack.stack_pointer]);
+        for address in self.stack.stack {
+            hasher.write(&address.to_le_bytes());
+        }
+        hasher.write(&self.graphic.pixels);
+        hasher.write(&self.input.pressed.map(u8::from));
+        hasher.write(&self.input.second.map(u8::from));
+        hasher.finish()
+    }
+
+    /// Restarts the ROM and records every key change from power-on until
+    /// `stop_movie_recording`.
+    pub fn start_movie_recording(&mut self, instructions_per_second: u32) {
+        self.restart();
+        self.movie = Some(Movie::new(self, instructions_per_second));
+    }
+
+    pub fn stop_movie_recording(&mut self) -> Option<Movie> {
+        self.movie.take()
+    }
+
+    pub fn is_recording_movie(&self) -> bool {
+        self.movie.is_some()
+    }
+
+    pub fn set_memory_protection(&mut self, mode: MemoryProtection) {
+        self.protector.set_mode(mode);
+    }
+
+    pub fn memory_protection(&self) -> MemoryProtection {
+        self.protector.mode()
+    }
+
+    /// Drains the memory faults raised since the last call.
+    pub fn take_memory_faults(&mut self) -> Vec<MemoryFault> {
+        self.protector.take_faults()
+    }
+
+    /// True after a memory trap or a crash; the machine stays halted until `clear_trap`.
+    pub fn is_trapped(&self) -> bool {
+        self.protector.is_trapped() || self.is_crashed()
+    }
+
+    pub fn clear_trap(&mut self) {
+        self.protector.clear_trap();
+        self.crash_reason = None;
+    }
+
+    pub fn is_sound_playing(&self) -> bool {
+        self.registers.sound_timer > 0
     }
 
     pub fn set_draw_flag(&mut self, should_draw: bool) {
@@ -345,10 +775,114 @@ impl Chip8Emulator {
     }
 
     pub fn set_key(&mut self, index: usize, pressed: bool) {
-        self.input.pressed[index] = pressed;
+        if self.input.set_key(index, pressed) {
+            self.idle.input_changed();
+            if let Some(movie) = self.movie.as_mut() {
+                movie.records.push(MovieRecord::Key { frame: self.frames, cycle: self.cycles, key: index as u8, pressed });
+            }
+        }
     }
 
+    /// Pixels of the display, row by row; see `display_size`.
     pub fn get_color_array(&self) -> &[u8] {
-        &self.graphic.pixels
+        &self.graphic.pixels[..WIDTH * self.display_height()]
+    }
+
+    /// Color of pixel `idx`: from the palette, or from the color map for
+    /// CHIP-8X.
+    pub fn pixel_rgb(&self, palette: &Palette, idx: usize) -> [u8; 3] {
+        let lit = self.graphic.pixels[idx] == 0x01;
+        match self.variant {
+            Variant::Chip8X if lit => {
+                let color = self.graphic.colors[idx / WIDTH * COLOR_COLUMNS + idx % WIDTH / 8];
+                CHIP8X_FOREGROUNDS[color as usize % CHIP8X_FOREGROUNDS.len()]
+            },
+            Variant::Chip8X => CHIP8X_BACKGROUNDS[self.graphic.background as usize % CHIP8X_BACKGROUNDS.len()],
+            _ if lit => palette.foreground,
+            _ => palette.background,
+        }
+    }
+
+    /// Expands the framebuffer into packed RGB bytes, each pixel drawn as a
+    /// `scale` x `scale` block in the palette's colors.
+    pub fn render_rgb(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        let height = self.display_height();
+        let mut rgb = Vec::with_capacity(WIDTH * height * scale * scale * 3);
+        for row in 0..height {
+            for _ in 0..scale {
+                for idx in row * WIDTH..(row + 1) * WIDTH {
+                    let color = self.pixel_rgb(palette, idx);
+                    for _ in 0..scale {
+                        rgb.extend_from_slice(&color);
+                    }
+                }
+            }
+        }
+        rgb
+    }
+
+    /// Encodes the current screen as a PNG image.
+    pub fn screenshot_png(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        png::encode_rgb(WIDTH * scale, self.display_height() * scale, &self.render_rgb(palette, scale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_skip_with_register_past_f_crashes() {
+        for skip in [0x9E, 0xA1] {
+            let mut chip8_emulator = Chip8Emulator::new();
+            chip8_emulator.init(&[0x60, 0x20, 0xE0, skip]);
+            chip8_emulator.emulate_cycle();
+            assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Halted);
+
+            let dump = chip8_emulator.take_crash_dump().unwrap();
+            assert_eq!(dump.reason, CrashReason::InvalidOpcode);
+            assert_eq!((dump.pc, dump.opcode), (0x202, 0xE000 | skip as u16));
+            assert_eq!(chip8_emulator.program_counter(), 0x202);
+        }
+    }
+
+    #[test]
+    fn program_counter_wraps_at_the_top_of_64k() {
+        let config = MemoryConfig { ram_size: MAX_MEMORY_SIZE, ..MemoryConfig::default() };
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(config).unwrap();
+        chip8_emulator.init(&[0x60, 0x00].repeat(config.capacity() / 2));
+        for _ in 0..config.capacity() / 2 {
+            assert_eq!(chip8_emulator.emulate_cycle(), CycleState::Running);
+        }
+        assert_eq!(chip8_emulator.program_counter(), 0x0000);
+
+        // A skip from the last instruction wraps as well.
+        chip8_emulator.set_program_counter(0xFFFE).unwrap();
+        chip8_emulator.memory.ram[0xFFFE..].copy_from_slice(&[0x30, 0x00]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.program_counter(), 0x0002);
+
+        // So does a key wait there, which stays put instead of rewinding.
+        chip8_emulator.set_program_counter(0xFFFE).unwrap();
+        chip8_emulator.memory.ram[0xFFFE..].copy_from_slice(&[0xF0, 0x0A]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.program_counter(), 0xFFFE);
+    }
+
+    #[test]
+    fn vip_layout_keeps_return_addresses_past_4k() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig { ram_size: 0x2000, ..MemoryConfig::default() }).unwrap();
+        chip8_emulator.init(&[0x00, 0xEE]);
+        chip8_emulator.set_memory_layout(MemoryLayout::Vip);
+        chip8_emulator.memory.ram[0x1200..0x1202].copy_from_slice(&[0x22, 0x00]);
+        chip8_emulator.set_program_counter(0x1200).unwrap();
+
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.stack(), &[0x1200]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.program_counter(), 0x1202);
     }
 }
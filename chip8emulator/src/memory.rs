@@ -0,0 +1,172 @@
+// Memory map configuration: RAM size, where programs are loaded and where the
+// hex font lives. Everything below the program start is the interpreter area,
+// which is where the font has to go. Configurations are validated when set,
+// and ROMs are checked against them before loading.
+
+use std::fmt;
+
+use crate::protection::MemoryProtector;
+use crate::font::SMALL_FONT_LEN;
+use crate::Chip8Emulator;
+
+pub const MIN_MEMORY_SIZE: usize = 0x800;
+pub const MAX_MEMORY_SIZE: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryConfig {
+    /// Bytes of RAM, a multiple of 256 from 2 KiB to 64 KiB.
+    pub ram_size: usize,
+    pub program_start: u16,
+    pub font_address: u16,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self { ram_size: 0x1000, program_start: 0x200, font_address: 0x000 }
+    }
+}
+
+impl MemoryConfig {
+    /// Original COSMAC VIP with its base 2 KiB.
+    pub const VIP_2K: Self = Self { ram_size: 0x800, program_start: 0x200, font_address: 0x000 };
+    /// ETI-660, which loads programs at 0x600.
+    pub const ETI_660: Self = Self { ram_size: 0x1000, program_start: 0x600, font_address: 0x000 };
+    /// CHIP-8X, whose larger interpreter moves programs to 0x300.
+    pub const CHIP_8X: Self = Self { ram_size: 0x1000, program_start: 0x300, font_address: 0x000 };
+
+    pub fn validate(&self) -> Result<(), MemoryConfigError> {
+        if !(MIN_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&self.ram_size) || !self.ram_size.is_multiple_of(0x100) {
+            return Err(MemoryConfigError::RamSize(self.ram_size));
+        }
+        if self.program_start as usize >= self.ram_size {
+            return Err(MemoryConfigError::ProgramStart(self.program_start));
+        }
+        self.check_font(SMALL_FONT_LEN)
+    }
+
+    /// Whether a font of `len` bytes fits between the font address and the
+    /// program start.
+    pub(crate) fn check_font(&self, len: usize) -> Result<(), MemoryConfigError> {
+        if self.font_address as usize + len > self.program_start as usize {
+            return Err(MemoryConfigError::FontAddress(self.font_address));
+        }
+        Ok(())
+    }
+
+    /// Bytes available for a ROM.
+    pub fn capacity(&self) -> usize {
+        self.ram_size - self.program_start as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryConfigError {
+    RamSize(usize),
+    ProgramStart(u16),
+    FontAddress(u16),
+    RomTooLarge { len: usize, capacity: usize },
+}
+
+impl fmt::Display for MemoryConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryConfigError::RamSize(size) => write!(f, "RAM size {} is not a multiple of 256 between 2 KiB and 64 KiB", size),
+            MemoryConfigError::ProgramStart(address) => write!(f, "program start {:#05x} is outside RAM", address),
+            MemoryConfigError::FontAddress(address) => write!(f, "font at {:#05x} does not fit below the program start", address),
+            MemoryConfigError::RomTooLarge { len, capacity } => write!(f, "ROM of {} bytes does not fit in the {} bytes after the program start", len, capacity),
+        }
+    }
+}
+
+impl std::error::Error for MemoryConfigError {}
+
+impl Chip8Emulator {
+    /// Changes the memory map and resets the machine; call before `init`. The
+    /// current font, big glyphs included, has to fit below the program start.
+    pub fn set_memory_config(&mut self, config: MemoryConfig) -> Result<(), MemoryConfigError> {
+        config.validate()?;
+        config.check_font(self.font.len())?;
+        let mode = self.protector.mode();
+        self.protector = MemoryProtector::new(config.ram_size);
+        self.protector.set_mode(mode);
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.resize(config.ram_size);
+        }
+        self.memory.config = config;
+        self.reset();
+        Ok(())
+    }
+
+    pub fn memory_config(&self) -> MemoryConfig {
+        self.memory.config
+    }
+
+    /// Like `init`, but rejects a ROM that does not fit instead of panicking.
+    pub fn try_init(&mut self, buffer: &[u8]) -> Result<(), MemoryConfigError> {
+        let capacity = self.memory.config.capacity();
+        if buffer.len() > capacity {
+            return Err(MemoryConfigError::RomTooLarge { len: buffer.len(), capacity });
+        }
+        self.detect_variant(buffer);
+        self.load_font_set();
+        self.load_program(buffer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuiltinFont, Font};
+
+    #[test]
+    fn validates_configurations() {
+        for config in [MemoryConfig::default(), MemoryConfig::VIP_2K, MemoryConfig::ETI_660, MemoryConfig::CHIP_8X] {
+            assert_eq!(config.validate(), Ok(()));
+        }
+        let config = MemoryConfig::default();
+        assert_eq!(MemoryConfig { ram_size: 0x700, ..config }.validate(), Err(MemoryConfigError::RamSize(0x700)));
+        assert_eq!(MemoryConfig { ram_size: 0x1080, ..config }.validate(), Err(MemoryConfigError::RamSize(0x1080)));
+        assert_eq!(MemoryConfig { ram_size: 0x20000, ..config }.validate(), Err(MemoryConfigError::RamSize(0x20000)));
+        assert_eq!(MemoryConfig { program_start: 0x1000, ..config }.validate(), Err(MemoryConfigError::ProgramStart(0x1000)));
+        assert_eq!(MemoryConfig { font_address: 0x1C0, ..config }.validate(), Err(MemoryConfigError::FontAddress(0x1C0)));
+        assert_eq!(MemoryConfig { ram_size: MAX_MEMORY_SIZE, ..config }.capacity(), 0xFE00);
+    }
+
+    #[test]
+    fn programs_load_at_the_configured_start() {
+        // ETI-660: V0 = 7, then FX29 for digit 7 with the font moved to 0x100.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig { font_address: 0x100, ..MemoryConfig::ETI_660 }).unwrap();
+        chip8_emulator.init(&[0x60, 0x07, 0xF0, 0x29]);
+        assert_eq!(chip8_emulator.program_counter(), 0x600);
+        assert_eq!(chip8_emulator.memory.ram[0x600..0x602], [0x60, 0x07]);
+        chip8_emulator.emulate_cycle();
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.registers.i, 0x100 + 7 * 5);
+        assert_eq!(chip8_emulator.memory.ram[0x100], 0xF0);
+    }
+
+    #[test]
+    fn rejects_roms_that_do_not_fit() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig::VIP_2K).unwrap();
+        assert_eq!(chip8_emulator.memory.ram.len(), 0x800);
+        assert_eq!(chip8_emulator.try_init(&[0; 0x601]), Err(MemoryConfigError::RomTooLarge { len: 0x601, capacity: 0x600 }));
+        assert_eq!(chip8_emulator.try_init(&[0; 0x600]), Ok(()));
+    }
+
+    #[test]
+    fn big_glyphs_have_to_fit_below_the_program_start() {
+        let small = MemoryConfig { program_start: 0x60, ..MemoryConfig::default() };
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(small).unwrap();
+        assert!(chip8_emulator.set_font(Font::builtin(BuiltinFont::Schip)).is_err());
+
+        chip8_emulator.set_memory_config(MemoryConfig::default()).unwrap();
+        chip8_emulator.set_font(Font::builtin(BuiltinFont::Schip)).unwrap();
+        assert_eq!(chip8_emulator.set_memory_config(small), Err(MemoryConfigError::FontAddress(0)));
+        assert_eq!(chip8_emulator.memory_config(), MemoryConfig::default());
+    }
+}
@@ -0,0 +1,405 @@
+// Input movies: every key change with the frame and cycle it happened on,
+// plus enough metadata to rebuild the exact machine and a state hash per
+// frame to catch desyncs during playback.
+//
+// The file format is plain text, one record per line:
+//
+//     chip8-movie 1
+//     rom <sha1>
+//     quirks <key_wait_on_press>
+//     ips <instructions per second>
+//     seed <rng seed>
+//     timing <Flat|Vip>
+//     protection <Off|Report|Trap>
+//     layout <Separate|Vip>
+//     variant <Chip8|Chip8X|HiRes>
+//     memory <ram size> <program start> <font address>
+//     font <hex font bytes>
+//     cheat <code>                            (one per active cheat)
+//     key <frame> <cycle> <key> <0|1>
+//     key2 <frame> <cycle> <key> <0|1>        (CHIP-8X second keypad)
+//     frame <cycles at end of frame> <state hash>
+
+use std::fmt;
+
+use crate::crash::{from_hex, parse_layout, parse_protection, parse_variant};
+use crate::hash::to_hex;
+use crate::{Cheat, Chip8Emulator, Font, MemoryConfig, MemoryConfigError, MemoryLayout, MemoryProtection, Quirks, TimingMode, Variant};
+
+const MAGIC: &str = "chip8-movie 1";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_sha1: String,
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    pub rng_seed: u64,
+    pub timing_mode: TimingMode,
+    pub memory_protection: MemoryProtection,
+    pub memory_layout: MemoryLayout,
+    pub variant: Variant,
+    pub memory_config: MemoryConfig,
+    pub font: Font,
+    /// Cheats active at power-on; they stay frozen for the whole movie.
+    pub cheats: Vec<Cheat>,
+    pub records: Vec<MovieRecord>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieRecord {
+    Key { frame: u64, cycle: u64, key: u8, pressed: bool },
+    SecondKey { frame: u64, cycle: u64, key: u8, pressed: bool },
+    Frame { cycles: u64, state_hash: u64 },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    RomMismatch { expected: String, actual: String },
+    Config(MemoryConfigError),
+    Desync { frame: u64, expected: u64, actual: u64 },
+    Stalled { frame: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::RomMismatch { expected, actual } => {
+                write!(f, "movie was recorded with ROM {} but {} is loaded", expected, actual)
+            },
+            MovieError::Config(err) => write!(f, "movie configuration cannot be applied: {}", err),
+            MovieError::Desync { frame, expected, actual } => {
+                write!(f, "desync at frame {}: expected state {:016x}, got {:016x}", frame, expected, actual)
+            },
+            MovieError::Stalled { frame } => write!(f, "emulator stopped executing at frame {}", frame),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    pub(crate) fn new(chip8_emulator: &Chip8Emulator, instructions_per_second: u32) -> Self {
+        Self {
+            rom_sha1: chip8_emulator.rom_sha1(),
+            quirks: chip8_emulator.quirks(),
+            instructions_per_second,
+            rng_seed: chip8_emulator.rng_seed(),
+            timing_mode: chip8_emulator.timing_mode(),
+            memory_protection: chip8_emulator.memory_protection(),
+            memory_layout: chip8_emulator.memory_layout(),
+            variant: chip8_emulator.variant(),
+            memory_config: chip8_emulator.memory_config(),
+            font: chip8_emulator.font.clone(),
+            cheats: chip8_emulator.cheats.clone(),
+            records: Vec::new(),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.records.iter().filter(|record| matches!(record, MovieRecord::Frame { .. })).count()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", MAGIC);
+        out += &format!("rom {}\n", self.rom_sha1);
+        out += &format!("quirks {}\n", self.quirks.key_wait_on_press as u8);
+        out += &format!("ips {}\n", self.instructions_per_second);
+        out += &format!("seed {}\n", self.rng_seed);
+        out += &format!("timing {:?}\n", self.timing_mode);
+        out += &format!("protection {:?}\n", self.memory_protection);
+        out += &format!("layout {:?}\n", self.memory_layout);
+        out += &format!("variant {:?}\n", self.variant);
+        let config = &self.memory_config;
+        out += &format!("memory {:x} {:03x} {:03x}\n", config.ram_size, config.program_start, config.font_address);
+        out += &format!("font {}\n", to_hex(&self.font.as_bytes()));
+        for cheat in &self.cheats {
+            out += &format!("cheat {}\n", cheat);
+        }
+        for record in &self.records {
+            match record {
+                MovieRecord::Key { frame, cycle, key, pressed } => {
+                    out += &format!("key {} {} {:x} {}\n", frame, cycle, key, *pressed as u8);
+                },
+                MovieRecord::SecondKey { frame, cycle, key, pressed } => {
+                    out += &format!("key2 {} {} {:x} {}\n", frame, cycle, key, *pressed as u8);
+                },
+                MovieRecord::Frame { cycles, state_hash } => {
+                    out += &format!("frame {} {:016x}\n", cycles, state_hash);
+                },
+            }
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line.trim()));
+        match lines.next() {
+            Some((_, MAGIC)) => (),
+            _ => return Err(MovieError::Parse { line: 1, message: "not a movie file".to_string() }),
+        }
+
+        let mut movie = Movie {
+            rom_sha1: String::new(),
+            quirks: Quirks::default(),
+            instructions_per_second: 0,
+            rng_seed: 0,
+            timing_mode: TimingMode::Flat,
+            memory_protection: MemoryProtection::Off,
+            memory_layout: MemoryLayout::Separate,
+            variant: Variant::Chip8,
+            memory_config: MemoryConfig::default(),
+            font: Font::default(),
+            cheats: Vec::new(),
+            records: Vec::new(),
+        };
+
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let error = |message: &str| MovieError::Parse { line, message: message.to_string() };
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let dec = |idx: usize| fields.get(idx).and_then(|s| s.parse::<u64>().ok()).ok_or_else(|| error("bad number"));
+            let hex = |idx: usize| fields.get(idx).and_then(|s| u64::from_str_radix(s, 16).ok()).ok_or_else(|| error("bad number"));
+
+            match fields[0] {
+                "rom" => movie.rom_sha1 = fields.get(1).ok_or_else(|| error("missing hash"))?.to_string(),
+                "quirks" => movie.quirks.key_wait_on_press = dec(1)? != 0,
+                "ips" => movie.instructions_per_second = dec(1)? as u32,
+                "seed" => movie.rng_seed = dec(1)?,
+                "timing" => {
+                    movie.timing_mode = match fields.get(1) {
+                        Some(&"Flat") => TimingMode::Flat,
+                        Some(&"Vip") => TimingMode::Vip,
+                        _ => return Err(error("unknown timing mode")),
+                    }
+                },
+                "protection" => movie.memory_protection = fields.get(1).copied().and_then(parse_protection).ok_or_else(|| error("unknown protection mode"))?,
+                "layout" => movie.memory_layout = fields.get(1).copied().and_then(parse_layout).ok_or_else(|| error("unknown memory layout"))?,
+                "variant" => movie.variant = fields.get(1).copied().and_then(parse_variant).ok_or_else(|| error("unknown variant"))?,
+                "memory" => {
+                    let ram_size = hex(1)? as usize;
+                    let program_start = u16::try_from(hex(2)?).map_err(|_| error("bad number"))?;
+                    let font_address = u16::try_from(hex(3)?).map_err(|_| error("bad number"))?;
+                    movie.memory_config = MemoryConfig { ram_size, program_start, font_address };
+                    movie.memory_config.validate().map_err(|err| error(&err.to_string()))?;
+                },
+                "font" => {
+                    let bytes = fields.get(1).and_then(|s| from_hex(s)).ok_or_else(|| error("bad hex data"))?;
+                    movie.font = Font::from_bytes(&bytes).map_err(|err| error(&err.to_string()))?;
+                },
+                "cheat" => {
+                    let code = fields.get(1).ok_or_else(|| error("missing cheat code"))?;
+                    let cheat = Cheat::parse_codes(code).map_err(|err| error(&err.message))?;
+                    movie.cheats.extend(cheat);
+                },
+                "key" | "key2" => {
+                    let key = hex(3)?;
+                    if key > 0xF {
+                        return Err(error("key out of range"));
+                    }
+                    let (frame, cycle, key, pressed) = (dec(1)?, dec(2)?, key as u8, dec(4)? != 0);
+                    movie.records.push(match fields[0] {
+                        "key" => MovieRecord::Key { frame, cycle, key, pressed },
+                        _ => MovieRecord::SecondKey { frame, cycle, key, pressed },
+                    });
+                },
+                "frame" => movie.records.push(MovieRecord::Frame { cycles: dec(1)?, state_hash: hex(2)? }),
+                _ => return Err(error("unknown record")),
+            }
+        }
+        movie.memory_config.check_font(movie.font.len()).map_err(|err| MovieError::Parse { line: 1, message: err.to_string() })?;
+        Ok(movie)
+    }
+}
+
+/// Drives an emulator through a recorded movie one frame at a time.
+pub struct MoviePlayer {
+    movie: Movie,
+    position: usize,
+    frame: u64,
+}
+
+impl MoviePlayer {
+    /// Resets the emulator into the movie's starting configuration and loads
+    /// `rom`. Timing, memory map, layout, variant, font and cheats all come
+    /// from the movie, replacing whatever the emulator was set up with.
+    pub fn start(movie: Movie, chip8_emulator: &mut Chip8Emulator, rom: &[u8]) -> Result<Self, MovieError> {
+        chip8_emulator.set_quirks(movie.quirks);
+        chip8_emulator.set_rng_seed(movie.rng_seed);
+        chip8_emulator.set_timing_mode(movie.timing_mode);
+        chip8_emulator.set_memory_protection(movie.memory_protection);
+        movie.memory_config.check_font(movie.font.len()).map_err(MovieError::Config)?;
+        chip8_emulator.font = movie.font.clone();
+        chip8_emulator.set_variant(movie.variant);
+        chip8_emulator.set_memory_config(movie.memory_config).map_err(MovieError::Config)?;
+        chip8_emulator.try_init(rom).map_err(MovieError::Config)?;
+        chip8_emulator.set_memory_layout(movie.memory_layout);
+        // Set without applying: during recording they were first written
+        // back after the first instruction as well.
+        chip8_emulator.cheats = movie.cheats.clone();
+
+        let actual = chip8_emulator.rom_sha1();
+        if actual != movie.rom_sha1 {
+            return Err(MovieError::RomMismatch { expected: movie.rom_sha1.clone(), actual });
+        }
+
+        Ok(Self { movie, position: 0, frame: 0 })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.movie.records.len()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Runs one frame: instructions up to the recorded cycle count with key
+    /// changes applied on their cycle, then a timer tick and a state check.
+    pub fn play_frame(&mut self, chip8_emulator: &mut Chip8Emulator) -> Result<(), MovieError> {
+        loop {
+            let done = self.frame_done(chip8_emulator);
+            match self.movie.records.get(self.position).copied() {
+                None => return Ok(()),
+                Some(MovieRecord::Key { cycle, key, pressed, .. }) if cycle <= chip8_emulator.cycles() || done => {
+                    chip8_emulator.set_key(key as usize, pressed);
+                    self.position += 1;
+                },
+                Some(MovieRecord::SecondKey { cycle, key, pressed, .. }) if cycle <= chip8_emulator.cycles() || done => {
+                    chip8_emulator.set_second_key(key as usize, pressed);
+                    self.position += 1;
+                },
+                Some(MovieRecord::Frame { state_hash, .. }) if done => {
+                    chip8_emulator.advance_timers();
+                    self.position += 1;
+                    let frame = self.frame;
+                    self.frame += 1;
+
+                    let actual = chip8_emulator.state_hash();
+                    if actual != state_hash {
+                        return Err(MovieError::Desync { frame, expected: state_hash, actual });
+                    }
+                    return Ok(());
+                },
+                Some(_) => {
+                    let before = chip8_emulator.cycles();
+                    chip8_emulator.emulate_cycle();
+                    if chip8_emulator.cycles() == before {
+                        return Err(MovieError::Stalled { frame: self.frame });
+                    }
+                },
+            }
+        }
+    }
+
+    fn frame_done(&self, chip8_emulator: &Chip8Emulator) -> bool {
+        let end = self.movie.records[self.position..].iter().find_map(|record| match record {
+            MovieRecord::Frame { cycles, .. } => Some(*cycles),
+            _ => None,
+        });
+        end.is_none_or(|cycles| chip8_emulator.cycles() >= cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuiltinFont, CheatTarget};
+
+    // V0 = random, draw the digit in V1, V1 += 1, loop.
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xF1, 0x29, 0xD0, 0x05, 0x71, 0x01, 0x12, 0x00];
+
+    fn record(frames: usize) -> Movie {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+        chip8_emulator.set_memory_config(MemoryConfig::ETI_660).unwrap();
+        chip8_emulator.set_font(Font::builtin(BuiltinFont::Schip)).unwrap();
+        chip8_emulator.add_cheat(Cheat { target: CheatTarget::Register(5), value: 9 });
+        chip8_emulator.init(&ROM);
+        chip8_emulator.start_movie_recording(600);
+        for frame in 0..frames {
+            chip8_emulator.set_key(frame % 16, frame % 3 == 0);
+            chip8_emulator.run_frame(10);
+        }
+        chip8_emulator.stop_movie_recording().unwrap()
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let movie = record(20);
+        assert_eq!(movie.frame_count(), 20);
+        assert_eq!(Movie::parse(&movie.to_text()), Ok(movie));
+    }
+
+    #[test]
+    fn parse_reports_bad_lines() {
+        assert_eq!(Movie::parse("chip8-crash 1\n"), Err(MovieError::Parse { line: 1, message: "not a movie file".to_string() }));
+        let error = |text: &str| match Movie::parse(text) {
+            Err(MovieError::Parse { line, message }) => (line, message),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(error("chip8-movie 1\nkey 0 0 10 1\n"), (2, "key out of range".to_string()));
+        assert_eq!(error("chip8-movie 1\nkey2 0 0 10 1\n"), (2, "key out of range".to_string()));
+        assert_eq!(error("chip8-movie 1\n\nbogus\n"), (3, "unknown record".to_string()));
+        assert_eq!(error("chip8-movie 1\ntiming Fast\n"), (2, "unknown timing mode".to_string()));
+        assert_eq!(error("chip8-movie 1\nmemory 10 200 000\n").1, MemoryConfigError::RamSize(0x10).to_string());
+    }
+
+    #[test]
+    fn playback_restores_the_recorded_machine() {
+        let movie = record(30);
+        let mut chip8_emulator = Chip8Emulator::new();
+        let mut player = MoviePlayer::start(movie.clone(), &mut chip8_emulator, &ROM).unwrap();
+        assert_eq!(chip8_emulator.timing_mode(), TimingMode::Vip);
+        assert_eq!(chip8_emulator.memory_config(), MemoryConfig::ETI_660);
+        assert_eq!(chip8_emulator.cheats(), movie.cheats.as_slice());
+        while !player.is_finished() {
+            player.play_frame(&mut chip8_emulator).unwrap();
+        }
+        assert_eq!(player.frame(), 30);
+    }
+
+    #[test]
+    fn playback_detects_mismatches() {
+        let movie = record(5);
+        let mut chip8_emulator = Chip8Emulator::new();
+        assert!(matches!(
+            MoviePlayer::start(movie.clone(), &mut chip8_emulator, &[0x12, 0x00]),
+            Err(MovieError::RomMismatch { .. })
+        ));
+
+        let mut tampered = movie;
+        let frame = tampered.records.iter_mut().find_map(|record| match record {
+            MovieRecord::Frame { state_hash, .. } => Some(state_hash),
+            _ => None,
+        });
+        *frame.unwrap() ^= 1;
+        let mut player = MoviePlayer::start(tampered, &mut chip8_emulator, &ROM).unwrap();
+        assert!(matches!(player.play_frame(&mut chip8_emulator), Err(MovieError::Desync { frame: 0, .. })));
+    }
+
+    #[test]
+    fn second_keypad_changes_are_replayed() {
+        // V6 = 5, V7 += 1 while second-keypad key 5 is down, loop.
+        let rom = [0x66, 0x05, 0xE6, 0xF5, 0x77, 0x01, 0x12, 0x02];
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig::CHIP_8X).unwrap();
+        chip8_emulator.set_variant(Variant::Chip8X);
+        chip8_emulator.init(&rom);
+        chip8_emulator.start_movie_recording(600);
+        for frame in 0..6 {
+            chip8_emulator.set_second_key(5, frame % 2 == 0);
+            chip8_emulator.run_frame(10);
+        }
+        let movie = chip8_emulator.stop_movie_recording().unwrap();
+        assert!(movie.records.contains(&MovieRecord::SecondKey { frame: 0, cycle: 0, key: 5, pressed: true }));
+        assert_eq!(Movie::parse(&movie.to_text()).as_ref(), Ok(&movie));
+
+        let mut replayed = Chip8Emulator::new();
+        let mut player = MoviePlayer::start(movie, &mut replayed, &rom).unwrap();
+        while !player.is_finished() {
+            player.play_frame(&mut replayed).unwrap();
+        }
+        assert_eq!(replayed.registers()[7], chip8_emulator.registers()[7]);
+    }
+}
@@ -0,0 +1,286 @@
+// Profiler: counts executed instructions per opcode class and per address,
+// instructions spent in each subroutine (2NNN until the matching 00EE, callees
+// included), DXYN calls per frame and how many frames drew at all. Time is
+// measured in instructions, since that is what a ROM author can budget
+// against the interpreter's instruction rate.
+//
+// Reports come as text, JSON, or collapsed stacks (`main;sub_2A0 123` lines)
+// that flamegraph tools take as input.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::Chip8Emulator;
+
+const MAX_LISTED_ADDRESSES: usize = 20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    /// Instructions executed between the call and its return, callees included.
+    pub instructions: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    instructions: u64,
+    opcode_counts: BTreeMap<&'static str, u64>,
+    address_counts: BTreeMap<u16, u64>,
+    subroutines: BTreeMap<u16, SubroutineStats>,
+    call_stack: Vec<(u16, u64)>,
+    collapsed: HashMap<Vec<u16>, u64>,
+    frames: u64,
+    frames_drawn: u64,
+    draws_this_frame: u64,
+    drawn_this_frame: bool,
+    draws_per_frame: BTreeMap<u64, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_instruction(&mut self, pc: u16, opcode: u16) {
+        self.instructions += 1;
+        *self.opcode_counts.entry(opcode_class(opcode)).or_insert(0) += 1;
+        *self.address_counts.entry(pc).or_insert(0) += 1;
+
+        let path: Vec<u16> = self.call_stack.iter().map(|(address, _)| *address).collect();
+        *self.collapsed.entry(path).or_insert(0) += 1;
+
+        match opcode & 0xF000 {
+            0x2000 => self.call_stack.push((opcode & 0x0FFF, self.instructions)),
+            0xD000 => {
+                self.draws_this_frame += 1;
+                self.drawn_this_frame = true;
+            },
+            _ if opcode == 0x00E0 => self.drawn_this_frame = true,
+            _ if opcode == 0x00EE => {
+                if let Some((address, entered)) = self.call_stack.pop() {
+                    let stats = self.subroutines.entry(address).or_default();
+                    stats.calls += 1;
+                    stats.instructions += self.instructions - entered;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    pub(crate) fn record_frame(&mut self) {
+        self.frames += 1;
+        self.frames_drawn += self.drawn_this_frame as u64;
+        self.drawn_this_frame = false;
+        *self.draws_per_frame.entry(self.draws_this_frame).or_insert(0) += 1;
+        self.draws_this_frame = 0;
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Executed instructions per opcode class such as `8XY4` or `DXYN`.
+    pub fn opcode_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.opcode_counts
+    }
+
+    pub fn address_counts(&self) -> &BTreeMap<u16, u64> {
+        &self.address_counts
+    }
+
+    /// Completed calls per subroutine address; calls still on the stack are not counted.
+    pub fn subroutines(&self) -> &BTreeMap<u16, SubroutineStats> {
+        &self.subroutines
+    }
+
+    /// Number of frames per DXYN count, e.g. `{0: 10, 2: 50}`.
+    pub fn draws_per_frame(&self) -> &BTreeMap<u64, u64> {
+        &self.draws_per_frame
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Frames in which the draw flag was raised by 00E0 or DXYN.
+    pub fn frames_drawn(&self) -> u64 {
+        self.frames_drawn
+    }
+
+    fn hottest_addresses(&self) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self.address_counts.iter().map(|(address, count)| (*address, *count)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(MAX_LISTED_ADDRESSES);
+        addresses
+    }
+
+    fn total_draws(&self) -> u64 {
+        self.draws_per_frame.iter().map(|(draws, frames)| draws * frames).sum()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+
+        writeln!(text, "{} instructions over {} frames", self.instructions, self.frames).unwrap();
+        if self.frames > 0 {
+            writeln!(text, "{:.1} instructions per frame", self.instructions as f64 / self.frames as f64).unwrap();
+            writeln!(text, "{} of {} frames drawn, {:.2} DXYN per frame, at most {}", self.frames_drawn, self.frames,
+                self.total_draws() as f64 / self.frames as f64, self.draws_per_frame.keys().last().unwrap_or(&0)).unwrap();
+        }
+
+        writeln!(text, "\nOpcodes:").unwrap();
+        let mut opcodes: Vec<_> = self.opcode_counts.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1));
+        for (class, count) in opcodes {
+            writeln!(text, "  {:<6} {:>10} {:>6.2}%", class, count, percent(*count)).unwrap();
+        }
+
+        writeln!(text, "\nHot addresses:").unwrap();
+        for (address, count) in self.hottest_addresses() {
+            writeln!(text, "  {:#05x} {:>10} {:>6.2}%", address, count, percent(count)).unwrap();
+        }
+
+        writeln!(text, "\nSubroutines:").unwrap();
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, stats)| Reverse(stats.instructions));
+        for (address, stats) in subroutines {
+            writeln!(text, "  {:#05x} {:>8} calls {:>10} instructions {:>6.2}% {:>8.1} per call", address, stats.calls,
+                stats.instructions, percent(stats.instructions), stats.instructions as f64 / stats.calls as f64).unwrap();
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let object = |entries: Vec<String>| format!("{{{}}}", entries.join(","));
+        let opcodes = object(self.opcode_counts.iter().map(|(class, count)| format!("\"{}\":{}", class, count)).collect());
+        let addresses = object(self.address_counts.iter().map(|(address, count)| format!("\"{:#05x}\":{}", address, count)).collect());
+        let subroutines = object(self.subroutines.iter()
+            .map(|(address, stats)| format!("\"{:#05x}\":{{\"calls\":{},\"instructions\":{}}}", address, stats.calls, stats.instructions))
+            .collect());
+        let draws = object(self.draws_per_frame.iter().map(|(draws, frames)| format!("\"{}\":{}", draws, frames)).collect());
+        format!(
+            "{{\"instructions\":{},\"frames\":{},\"frames_drawn\":{},\"opcodes\":{},\"addresses\":{},\"subroutines\":{},\"draws_per_frame\":{}}}\n",
+            self.instructions, self.frames, self.frames_drawn, opcodes, addresses, subroutines, draws,
+        )
+    }
+
+    /// One `main;sub_XXX;... count` line per call stack, counting the
+    /// instructions executed directly in its innermost frame.
+    pub fn to_collapsed(&self) -> String {
+        let mut lines: Vec<String> = self.collapsed
+            .iter()
+            .map(|(path, count)| {
+                let frames: String = path.iter().map(|address| format!(";sub_{:03X}", address)).collect();
+                format!("main{} {}\n", frames, count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+/// Opcode mnemonic pattern, e.g. `8XY4` for 0x8AB4.
+pub(crate) fn opcode_class(opcode: u16) -> &'static str {
+    match (opcode >> 12, opcode & 0x000F, opcode & 0x00FF) {
+        (0x0, _, _) if opcode == 0x00E0 => "00E0",
+        (0x0, _, _) if opcode == 0x00EE => "00EE",
+        (0x0, _, _) => "0NNN",
+        (0x1, _, _) => "1NNN",
+        (0x2, _, _) => "2NNN",
+        (0x3, _, _) => "3XNN",
+        (0x4, _, _) => "4XNN",
+        (0x5, 0x1, _) => "5XY1",
+        (0x5, _, _) => "5XY0",
+        (0x6, _, _) => "6XNN",
+        (0x7, _, _) => "7XNN",
+        (0x8, 0x0, _) => "8XY0",
+        (0x8, 0x1, _) => "8XY1",
+        (0x8, 0x2, _) => "8XY2",
+        (0x8, 0x3, _) => "8XY3",
+        (0x8, 0x4, _) => "8XY4",
+        (0x8, 0x5, _) => "8XY5",
+        (0x8, 0x6, _) => "8XY6",
+        (0x8, 0x7, _) => "8XY7",
+        (0x8, 0xE, _) => "8XYE",
+        (0x9, _, _) => "9XY0",
+        (0xA, _, _) => "ANNN",
+        (0xB, _, _) => "BNNN",
+        (0xC, _, _) => "CXNN",
+        (0xD, _, _) => "DXYN",
+        (0xE, _, 0x9E) => "EX9E",
+        (0xE, _, 0xA1) => "EXA1",
+        (0xE, _, 0xF2) => "EXF2",
+        (0xE, _, 0xF5) => "EXF5",
+        (0xF, _, 0x07) => "FX07",
+        (0xF, _, 0x0A) => "FX0A",
+        (0xF, _, 0x15) => "FX15",
+        (0xF, _, 0x18) => "FX18",
+        (0xF, _, 0x1E) => "FX1E",
+        (0xF, _, 0x29) => "FX29",
+        (0xF, _, 0x30) => "FX30",
+        (0xF, _, 0x33) => "FX33",
+        (0xF, _, 0x55) => "FX55",
+        (0xF, _, 0x65) => "FX65",
+        _ => "????",
+    }
+}
+
+impl Chip8Emulator {
+    /// Starts collecting a profile, discarding any profile in progress.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Profiler {
+        // Call 0x206 (V0 = 1, return), clear the screen, spin.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x22, 0x06, 0x00, 0xE0, 0x12, 0x04, 0x60, 0x01, 0x00, 0xEE]);
+        chip8_emulator.start_profiling();
+        for _ in 0..6 {
+            chip8_emulator.emulate_cycle();
+        }
+        chip8_emulator.advance_timers();
+        chip8_emulator.advance_timers();
+        chip8_emulator.stop_profiling().unwrap()
+    }
+
+    #[test]
+    fn counts_opcodes_subroutines_and_frames() {
+        let profiler = profile();
+        assert_eq!(profiler.instructions(), 6);
+        assert_eq!(profiler.opcode_counts()["1NNN"], 2);
+        assert_eq!(profiler.opcode_counts()["00EE"], 1);
+        assert_eq!(profiler.address_counts()[&0x204], 2);
+        assert_eq!(profiler.subroutines()[&0x206], SubroutineStats { calls: 1, instructions: 2 });
+        assert_eq!((profiler.frames(), profiler.frames_drawn()), (2, 1));
+        assert_eq!(profiler.draws_per_frame()[&0], 2);
+    }
+
+    #[test]
+    fn collapsed_stacks_attribute_to_the_innermost_frame() {
+        assert_eq!(profile().to_collapsed(), "main 4\nmain;sub_206 2\n");
+    }
+
+    #[test]
+    fn classifies_opcodes() {
+        let cases = [(0x00E0, "00E0"), (0x0123, "0NNN"), (0x5AB1, "5XY1"), (0x8AB6, "8XY6"), (0x8AB9, "????"), (0xF330, "FX30")];
+        for (opcode, class) in cases {
+            assert_eq!(opcode_class(opcode), class);
+        }
+    }
+}
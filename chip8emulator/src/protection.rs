@@ -0,0 +1,243 @@
+// Memory protection layer over `Memory::ram`. Every instruction's memory
+// accesses are checked before it runs; violations are queued as faults for
+// the frontend and, depending on the mode, stop the machine.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryProtection {
+    /// Only out-of-range accesses are caught, since they cannot run at all.
+    Off,
+    /// Faults are reported and execution continues. Out-of-range accesses
+    /// still stop the machine since there is nothing sensible to read.
+    Report,
+    /// Any fault stops the machine before the offending instruction runs.
+    Trap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryFaultKind {
+    /// Write into the interpreter and font area below the program start.
+    ReservedWrite,
+    /// Access or fetch past the end of RAM.
+    OutOfBounds,
+    /// Fetch of a byte that was previously used as sprite or register data.
+    DataExecution,
+    /// Fetch outside the loaded ROM image.
+    RunawayPc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MemoryFault {
+    pub kind: MemoryFaultKind,
+    pub pc: u16,
+    pub opcode: u16,
+    pub address: usize,
+}
+
+impl fmt::Display for MemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at PC {:#05x} (opcode {:#06x}), address {:#05x}", self.kind, self.pc, self.opcode, self.address)
+    }
+}
+
+pub(crate) enum Access {
+    Execute(usize),
+    Read(Range<usize>),
+    Write(Range<usize>),
+}
+
+#[derive(Clone)]
+pub(crate) struct MemoryProtector {
+    mode: MemoryProtection,
+    data: Vec<bool>,
+    program: Range<usize>,
+    faults: Vec<MemoryFault>,
+    reported: HashSet<MemoryFault>,
+    trap: Option<MemoryFault>,
+}
+
+impl MemoryProtector {
+    pub(crate) fn new(memory_size: usize) -> Self {
+        Self {
+            mode: MemoryProtection::Off,
+            data: vec![false; memory_size],
+            program: 0..0,
+            faults: Vec::new(),
+            reported: HashSet::new(),
+            trap: None,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.data.iter_mut().for_each(|byte| *byte = false);
+        self.program = 0..0;
+        self.faults.clear();
+        self.reported.clear();
+        self.trap = None;
+    }
+
+    pub(crate) fn mode(&self) -> MemoryProtection {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: MemoryProtection) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn set_program(&mut self, program: Range<usize>) {
+        self.program = program;
+    }
+
+    pub(crate) fn is_trapped(&self) -> bool {
+        self.trap.is_some()
+    }
+
+    /// The fault that stopped the machine.
+    pub(crate) fn trap(&self) -> Option<MemoryFault> {
+        self.trap
+    }
+
+    pub(crate) fn clear_trap(&mut self) {
+        self.trap = None;
+    }
+
+    /// Which bytes were used as data, one bit per byte, for snapshots.
+    pub(crate) fn data_bits(&self) -> Vec<u8> {
+        self.data.chunks(8).map(|bits| bits.iter().rev().fold(0, |acc, &bit| acc << 1 | bit as u8)).collect()
+    }
+
+    pub(crate) fn set_data_bits(&mut self, bits: &[u8]) {
+        for (idx, byte) in self.data.iter_mut().enumerate() {
+            *byte = bits[idx / 8] >> (idx % 8) & 1 != 0;
+        }
+    }
+
+    pub(crate) fn take_faults(&mut self) -> Vec<MemoryFault> {
+        std::mem::take(&mut self.faults)
+    }
+
+    /// Checks the accesses of the instruction at `pc`. Returns false if the
+    /// instruction must not run.
+    pub(crate) fn check(&mut self, pc: u16, opcode: u16, accesses: &[Access]) -> bool {
+        let memory_size = self.data.len();
+        let mut found = Vec::new();
+        for access in accesses {
+            match access {
+                Access::Execute(address) => {
+                    if address + 1 >= memory_size {
+                        found.push((MemoryFaultKind::OutOfBounds, *address));
+                    } else if !self.program.contains(address) {
+                        found.push((MemoryFaultKind::RunawayPc, *address));
+                    } else if self.data[*address] {
+                        found.push((MemoryFaultKind::DataExecution, *address));
+                    }
+                },
+                Access::Read(range) => {
+                    if range.end > memory_size {
+                        found.push((MemoryFaultKind::OutOfBounds, range.start.max(memory_size)));
+                    }
+                },
+                Access::Write(range) => {
+                    if range.end > memory_size {
+                        found.push((MemoryFaultKind::OutOfBounds, range.start.max(memory_size)));
+                    } else if range.start < self.program.start {
+                        found.push((MemoryFaultKind::ReservedWrite, range.start));
+                    }
+                },
+            }
+        }
+
+        if self.mode == MemoryProtection::Off {
+            found.retain(|(kind, _)| *kind == MemoryFaultKind::OutOfBounds);
+        }
+
+        for (kind, address) in found {
+            let fault = MemoryFault { kind, pc, opcode, address };
+            if self.trap.is_none() && (self.mode == MemoryProtection::Trap || kind == MemoryFaultKind::OutOfBounds) {
+                self.trap = Some(fault);
+            }
+            // In report mode a fault inside a loop is only queued the first time.
+            if self.trap.is_some() || self.reported.insert(fault) {
+                self.faults.push(fault);
+            }
+        }
+
+        if self.trap.is_some() {
+            return false;
+        }
+        if self.mode == MemoryProtection::Off {
+            return true;
+        }
+
+        for access in accesses {
+            if let Access::Read(range) | Access::Write(range) = access {
+                self.data[range.clone()].iter_mut().for_each(|byte| *byte = true);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Chip8Emulator, CrashReason, CycleState, MemoryFaultKind, MemoryProtection};
+
+    // A000 F055: store V0 at 0x000, inside the interpreter area.
+    const RESERVED_WRITE: [u8; 6] = [0xA0, 0x00, 0xF0, 0x55, 0x12, 0x00];
+
+    fn run(mode: MemoryProtection, rom: &[u8], cycles: usize) -> Chip8Emulator {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_protection(mode);
+        chip8_emulator.init(rom);
+        for _ in 0..cycles {
+            chip8_emulator.emulate_cycle();
+        }
+        chip8_emulator
+    }
+
+    #[test]
+    fn trap_stops_before_the_write() {
+        let mut chip8_emulator = run(MemoryProtection::Trap, &RESERVED_WRITE, 2);
+        assert_eq!(chip8_emulator.cycle_state(), CycleState::Halted);
+        assert_eq!(chip8_emulator.program_counter(), 0x202);
+        let faults = chip8_emulator.take_memory_faults();
+        assert_eq!(faults.len(), 1);
+        assert_eq!((faults[0].kind, faults[0].address), (MemoryFaultKind::ReservedWrite, 0));
+        let dump = chip8_emulator.take_crash_dump().unwrap();
+        assert_eq!(dump.reason, CrashReason::Memory { kind: MemoryFaultKind::ReservedWrite, address: 0 });
+    }
+
+    #[test]
+    fn report_queues_each_fault_once() {
+        // The write sits in a loop: A000 F055 1202.
+        let rom = [0xA0, 0x00, 0xF0, 0x55, 0x12, 0x02];
+        let mut chip8_emulator = run(MemoryProtection::Report, &rom, 9);
+        assert!(!chip8_emulator.is_trapped());
+        assert_eq!(chip8_emulator.take_memory_faults().len(), 1);
+    }
+
+    #[test]
+    fn off_only_catches_out_of_bounds() {
+        let mut chip8_emulator = run(MemoryProtection::Off, &RESERVED_WRITE, 2);
+        assert!(chip8_emulator.take_memory_faults().is_empty());
+
+        // AFFF F155: two bytes from 0xFFF run past the end of RAM.
+        let mut chip8_emulator = run(MemoryProtection::Off, &[0xAF, 0xFF, 0xF1, 0x55], 2);
+        let faults = chip8_emulator.take_memory_faults();
+        assert_eq!((faults[0].kind, faults[0].address), (MemoryFaultKind::OutOfBounds, 0x1000));
+        assert!(chip8_emulator.is_trapped());
+    }
+
+    #[test]
+    fn executing_data_is_reported() {
+        // A206 F065 1206, with the byte at 0x206 read as data before the jump.
+        let rom = [0xA2, 0x06, 0xF0, 0x65, 0x12, 0x06, 0x12, 0x06];
+        let mut chip8_emulator = run(MemoryProtection::Report, &rom, 4);
+        let kinds: Vec<_> = chip8_emulator.take_memory_faults().iter().map(|fault| fault.kind).collect();
+        assert_eq!(kinds, [MemoryFaultKind::DataExecution]);
+    }
+}
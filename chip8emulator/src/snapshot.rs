@@ -0,0 +1,291 @@
+// In-memory snapshots of the machine state as a flat byte layout. Fixed size
+// for a given RAM size and set of extensions and mostly unchanged between
+// frames, so callers can cheaply delta-compress consecutive snapshots.
+//
+// Layout: version, RAM size, RAM, the memory protector's data map (one bit
+// per byte), the fixed-size machine state, then each extension's state with
+// its length.
+
+use std::fmt;
+
+use crate::chip8x::COLOR_COLUMNS;
+use crate::{Chip8Emulator, HEIGHT, HIRES_HEIGHT, MAX_MEMORY_SIZE, MIN_MEMORY_SIZE, MemoryConfigError, NUM_GP_REGISTERS, NUM_KEYS, STACK_SIZE, WIDTH};
+
+const SNAPSHOT_VERSION: u8 = 4;
+/// Machine state after RAM and the data map, up to the extensions.
+const STATE_SIZE: usize = NUM_GP_REGISTERS + 2 + 2 + 1 + 1
+    + STACK_SIZE * 2 + 1 + WIDTH * HIRES_HEIGHT + COLOR_COLUMNS * HEIGHT + 1 + 1
+    + NUM_KEYS * 2 + 1 + 1 + 8 + 8 + 8
+    + 8 + 8 + 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    bytes: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Taken with a different RAM size.
+    MemorySize { expected: usize, actual: usize },
+    /// Extension state does not match the registered extensions.
+    Extensions,
+    /// The memory configuration it was taken with is invalid or does not fit
+    /// its ROM.
+    Config(MemoryConfigError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::MemorySize { expected, actual } => {
+                write!(f, "snapshot has {} bytes of RAM but the machine has {}", actual, expected)
+            },
+            SnapshotError::Extensions => write!(f, "snapshot was taken with different extensions"),
+            SnapshotError::Config(err) => write!(f, "invalid memory configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Snapshot {
+    /// Wraps bytes previously taken from `as_bytes`; None if they are not a valid snapshot.
+    pub fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        if bytes.first() != Some(&SNAPSHOT_VERSION) {
+            return None;
+        }
+        let snapshot = Self { bytes };
+        let memory_size = u32::from_le_bytes(snapshot.bytes.get(1..5)?.try_into().unwrap()) as usize;
+        if !(MIN_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&memory_size) || !memory_size.is_multiple_of(0x100) {
+            return None;
+        }
+        let mut reader = Reader { bytes: snapshot.bytes.get(5 + memory_size + memory_size / 8 + STATE_SIZE..)? };
+        for _ in 0..reader.try_u8()? {
+            let len = u32::from_le_bytes(reader.try_take(4)?.try_into().unwrap());
+            reader.try_take(len as usize)?;
+        }
+        reader.bytes.is_empty().then_some(snapshot)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// RAM size of the machine the snapshot was taken from.
+    pub fn memory_size(&self) -> usize {
+        u32::from_le_bytes(self.bytes[1..5].try_into().unwrap()) as usize
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn try_take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn try_u8(&mut self) -> Option<u8> {
+        Some(self.try_take(1)?[0])
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        self.try_take(len).unwrap()
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take(2).try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take(8).try_into().unwrap())
+    }
+}
+
+impl Chip8Emulator {
+    pub fn snapshot(&self) -> Snapshot {
+        let extensions: Vec<Vec<u8>> = self.extensions.iter().map(|extension| extension.save_state()).collect();
+        let memory_size = self.memory.ram.len();
+        let size = 5 + memory_size + memory_size / 8 + STATE_SIZE + 1 + extensions.iter().map(|state| 4 + state.len()).sum::<usize>();
+        let mut bytes = Vec::with_capacity(size);
+        bytes.push(SNAPSHOT_VERSION);
+        bytes.extend_from_slice(&(memory_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.memory.ram);
+        bytes.extend(self.protector.data_bits());
+        bytes.extend_from_slice(&self.registers.gp_registers);
+        bytes.extend_from_slice(&self.registers.i.to_le_bytes());
+        bytes.extend_from_slice(&self.registers.program_counter.to_le_bytes());
+        bytes.push(self.registers.delay_timer);
+        bytes.push(self.registers.sound_timer);
+        for address in self.stack.stack {
+            bytes.extend_from_slice(&address.to_le_bytes());
+        }
+        bytes.push(self.stack.stack_pointer);
+        bytes.extend_from_slice(&self.graphic.pixels);
+        bytes.extend_from_slice(&self.graphic.colors);
+        bytes.push(self.graphic.background);
+        bytes.push(self.draw_flag as u8);
+        bytes.extend(self.input.pressed.map(u8::from));
+        bytes.extend(self.input.second.map(u8::from));
+        bytes.push(self.input.waiting as u8);
+        bytes.push(self.input.wait_key.unwrap_or(0xFF));
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.frames.to_le_bytes());
+        bytes.extend_from_slice(&self.rng_state.to_le_bytes());
+        bytes.extend_from_slice(&self.timing.machine_cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.timing.budget.to_le_bytes());
+        bytes.push(self.timing.display_wait as u8);
+        bytes.push(extensions.len() as u8);
+        for state in extensions {
+            bytes.extend_from_slice(&(state.len() as u32).to_le_bytes());
+            bytes.extend(state);
+        }
+        debug_assert_eq!(bytes.len(), size);
+        Snapshot { bytes }
+    }
+
+    /// Puts the machine back into the snapshotted state. Configuration such as
+    /// quirks, the loaded ROM and memory protection mode is left alone; a
+    /// pending memory trap or crash is cleared. Fails without changing
+    /// anything if the snapshot was taken with a different RAM size or
+    /// different extensions.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let memory_size = snapshot.memory_size();
+        if memory_size != self.memory.ram.len() {
+            return Err(SnapshotError::MemorySize { expected: self.memory.ram.len(), actual: memory_size });
+        }
+
+        // Extensions first, into copies, so a mismatch leaves the machine alone.
+        let mut reader = Reader { bytes: &snapshot.bytes[5 + memory_size + memory_size / 8 + STATE_SIZE..] };
+        if reader.u8() as usize != self.extensions.len() {
+            return Err(SnapshotError::Extensions);
+        }
+        let mut extensions = self.extensions.clone();
+        for extension in extensions.iter_mut() {
+            let len = reader.u32() as usize;
+            if !extension.load_state(reader.take(len)) {
+                return Err(SnapshotError::Extensions);
+            }
+        }
+        self.extensions = extensions;
+
+        let mut reader = Reader { bytes: &snapshot.bytes[5..] };
+        self.memory.ram.copy_from_slice(reader.take(memory_size));
+        self.protector.set_data_bits(reader.take(memory_size / 8));
+        self.registers.gp_registers.copy_from_slice(reader.take(NUM_GP_REGISTERS));
+        self.registers.i = reader.u16();
+        self.registers.program_counter = reader.u16();
+        self.registers.delay_timer = reader.u8();
+        self.registers.sound_timer = reader.u8();
+        for address in self.stack.stack.iter_mut() {
+            *address = reader.u16();
+        }
+        self.stack.stack_pointer = reader.u8();
+        self.graphic.pixels.copy_from_slice(reader.take(WIDTH * HIRES_HEIGHT));
+        self.graphic.colors.copy_from_slice(reader.take(COLOR_COLUMNS * HEIGHT));
+        self.graphic.background = reader.u8();
+        self.draw_flag = reader.u8() != 0;
+        for (pressed, byte) in self.input.pressed.iter_mut().zip(reader.take(NUM_KEYS)) {
+            *pressed = *byte != 0;
+        }
+        for (pressed, byte) in self.input.second.iter_mut().zip(reader.take(NUM_KEYS)) {
+            *pressed = *byte != 0;
+        }
+        self.input.waiting = reader.u8() != 0;
+        self.input.wait_key = Some(reader.u8()).filter(|key| *key != 0xFF);
+        self.input.clear_events();
+        self.cycles = reader.u64();
+        self.frames = reader.u64();
+        self.rng_state = reader.u64();
+        self.timing.machine_cycles = reader.u64();
+        self.timing.budget = reader.u64() as i64;
+        self.timing.display_wait = reader.u8() != 0;
+
+        self.reset_layout();
+        self.idle.reset();
+        self.clear_trap();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryConfig, MemoryProtection, TimingMode, VipHybrid};
+
+    // Random values, a draw, a read of the drawn sprite as data, and a VIP
+    // routine at 0x20C (INC RF, SEP R4) called every time round.
+    const ROM: [u8; 14] = [0xC0, 0xFF, 0xA2, 0x0C, 0xD0, 0x12, 0xF1, 0x65, 0x02, 0x0C, 0x12, 0x00, 0x1F, 0xD4];
+
+    fn machine() -> Chip8Emulator {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+        chip8_emulator.set_memory_protection(MemoryProtection::Report);
+        chip8_emulator.add_extension(Box::new(VipHybrid::new()));
+        chip8_emulator.init(&ROM);
+        chip8_emulator
+    }
+
+    #[test]
+    fn restore_replays_exactly() {
+        let mut chip8_emulator = machine();
+        for _ in 0..3 {
+            chip8_emulator.run_frame(0);
+        }
+        let snapshot = Snapshot::from_bytes(chip8_emulator.snapshot().as_bytes().to_vec()).unwrap();
+        let run = |chip8_emulator: &mut Chip8Emulator| {
+            for _ in 0..5 {
+                chip8_emulator.run_frame(0);
+            }
+            (chip8_emulator.state_hash(), chip8_emulator.machine_cycles(), chip8_emulator.snapshot())
+        };
+
+        let expected = run(&mut chip8_emulator);
+        chip8_emulator.restore(&snapshot).unwrap();
+        assert_eq!(run(&mut chip8_emulator), expected);
+    }
+
+    #[test]
+    fn restore_rejects_a_different_machine() {
+        let snapshot = machine().snapshot();
+
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&ROM);
+        let before = chip8_emulator.snapshot();
+        assert_eq!(chip8_emulator.restore(&snapshot), Err(SnapshotError::Extensions));
+        assert_eq!(chip8_emulator.snapshot(), before);
+
+        chip8_emulator.set_memory_config(MemoryConfig::VIP_2K).unwrap();
+        assert_eq!(chip8_emulator.restore(&snapshot), Err(SnapshotError::MemorySize { expected: 0x800, actual: 0x1000 }));
+    }
+
+    #[test]
+    fn from_bytes_checks_the_layout() {
+        let bytes = machine().snapshot().as_bytes().to_vec();
+        assert_eq!(Snapshot::from_bytes(bytes.clone()).unwrap().memory_size(), 0x1000);
+        assert!(Snapshot::from_bytes(bytes[..bytes.len() - 1].to_vec()).is_none());
+        assert!(Snapshot::from_bytes([&bytes[..], &[0]].concat()).is_none());
+
+        let mut old_version = bytes.clone();
+        old_version[0] = SNAPSHOT_VERSION - 1;
+        assert!(Snapshot::from_bytes(old_version).is_none());
+
+        let mut odd_size = bytes;
+        odd_size[1] = 0x10;
+        assert!(Snapshot::from_bytes(odd_size).is_none());
+    }
+}
@@ -0,0 +1,445 @@
+// Public view of the machine: a `CpuState` copy of the registers, borrowed
+// views of RAM and the stack, and validated mutators for debuggers and tests.
+// Mutators leave the machine in a consistent state but bypass memory
+// protection, movies and the rest of the instrumentation.
+
+use std::fmt;
+
+use crate::{Chip8Emulator, NUM_GP_REGISTERS, STACK_SIZE};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub registers: [u8; NUM_GP_REGISTERS],
+    pub i: u16,
+    pub program_counter: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: [u16; STACK_SIZE],
+    pub stack_pointer: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    RegisterOutOfRange(usize),
+    AddressOutOfRange(usize),
+    StackPointerOutOfRange(u8),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::RegisterOutOfRange(idx) => write!(f, "register V{:X} does not exist", idx),
+            StateError::AddressOutOfRange(address) => write!(f, "address {:#05x} is outside RAM", address),
+            StateError::StackPointerOutOfRange(sp) => write!(f, "stack pointer {} exceeds the stack", sp),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl Chip8Emulator {
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers.gp_registers,
+            i: self.registers.i,
+            program_counter: self.registers.program_counter,
+            delay_timer: self.registers.delay_timer,
+            sound_timer: self.registers.sound_timer,
+            stack: self.stack.stack,
+            stack_pointer: self.stack.stack_pointer,
+        }
+    }
+
+    /// V0 to VF.
+    pub fn registers(&self) -> &[u8; NUM_GP_REGISTERS] {
+        &self.registers.gp_registers
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory.ram
+    }
+
+    /// Return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack.stack[..self.stack.stack_pointer as usize]
+    }
+
+    pub fn i(&self) -> u16 {
+        self.registers.i
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.registers.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.registers.sound_timer
+    }
+
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack.stack_pointer
+    }
+
+    pub fn set_cpu_state(&mut self, state: &CpuState) -> Result<(), StateError> {
+        if state.stack_pointer as usize > self.stack_depth() {
+            return Err(StateError::StackPointerOutOfRange(state.stack_pointer));
+        }
+        if state.program_counter as usize >= self.memory.ram.len() {
+            return Err(StateError::AddressOutOfRange(state.program_counter as usize));
+        }
+        self.registers.gp_registers = state.registers;
+        self.registers.i = state.i;
+        self.registers.program_counter = state.program_counter;
+        self.registers.delay_timer = state.delay_timer;
+        self.registers.sound_timer = state.sound_timer;
+        self.stack.stack = state.stack;
+        self.stack.stack_pointer = state.stack_pointer;
+        self.sync_layout();
+        self.idle.reset();
+        Ok(())
+    }
+
+    pub fn set_register(&mut self, idx: usize, value: u8) -> Result<(), StateError> {
+        let register = self.registers.gp_registers.get_mut(idx).ok_or(StateError::RegisterOutOfRange(idx))?;
+        *register = value;
+        self.sync_layout();
+        self.idle.reset();
+        Ok(())
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.registers.i = i;
+        self.idle.reset();
+    }
+
+    pub fn set_program_counter(&mut self, address: u16) -> Result<(), StateError> {
+        if address as usize >= self.memory.ram.len() {
+            return Err(StateError::AddressOutOfRange(address as usize));
+        }
+        self.registers.program_counter = address;
+        self.idle.reset();
+        Ok(())
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.registers.delay_timer = value;
+        self.idle.reset();
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.registers.sound_timer = value;
+    }
+
+    /// Writes `bytes` starting at `address`; nothing is written if any of it falls outside RAM.
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) -> Result<(), StateError> {
+        let end = address + bytes.len();
+        if end > self.memory.ram.len() {
+            return Err(StateError::AddressOutOfRange(end - 1));
+        }
+        self.memory.ram[address..end].copy_from_slice(bytes);
+        self.sync_layout();
+        self.idle.reset();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Chip8Emulator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Chip8Emulator")
+            .field("cpu", &self.cpu_state())
+            .field("cycles", &self.cycles)
+            .field("frames", &self.frames)
+            .field("quirks", &self.quirks)
+            .field("rom_sha1", &self.rom_sha1())
+            .field("cycle_state", &self.cycle_state())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two emulators are equal when they hold the same ROM and configuration
+/// and their machine states match; recorders and instrumentation are ignored.
+impl PartialEq for Chip8Emulator {
+    fn eq(&self, other: &Self) -> bool {
+        self.rom == other.rom
+            && self.quirks == other.quirks
+            && self.rng_seed == other.rng_seed
+            && self.snapshot() == other.snapshot()
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::CpuState;
+    use crate::chip8x::COLOR_COLUMNS;
+    use crate::{Chip8Emulator, Font, MemoryConfig, MemoryLayout, MemoryProtection, Quirks, TimingMode, Variant, HEIGHT, NUM_KEYS};
+
+    /// Serialized form of the emulator: the machine state plus what is needed
+    /// to rebuild it. Instrumentation and recorders are not included.
+    #[derive(Serialize, Deserialize)]
+    struct SerializedEmulator {
+        cpu: CpuState,
+        memory: Vec<u8>,
+        display: Vec<u8>,
+        draw_flag: bool,
+        keys: [bool; NUM_KEYS],
+        key_wait: bool,
+        wait_key: Option<u8>,
+        cycles: u64,
+        frames: u64,
+        rng_state: u64,
+        rng_seed: u64,
+        quirks: Quirks,
+        memory_protection: MemoryProtection,
+        #[serde(default)]
+        memory_layout: MemoryLayout,
+        #[serde(default)]
+        memory_config: MemoryConfig,
+        #[serde(default)]
+        variant: Variant,
+        /// CHIP-8X colour map; empty for the power-on colours.
+        #[serde(default)]
+        colors: Vec<u8>,
+        #[serde(default)]
+        background: u8,
+        #[serde(default)]
+        second_keys: [bool; NUM_KEYS],
+        /// Raw font bytes; empty for the default font.
+        #[serde(default)]
+        font: Vec<u8>,
+        #[serde(default)]
+        timing_mode: TimingMode,
+        #[serde(default)]
+        machine_cycles: u64,
+        /// VIP cycles left in the current frame.
+        #[serde(default)]
+        cycle_budget: i64,
+        #[serde(default)]
+        display_wait: bool,
+        rom: Vec<u8>,
+    }
+
+    impl Serialize for Chip8Emulator {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            SerializedEmulator {
+                cpu: self.cpu_state(),
+                memory: self.memory.ram.to_vec(),
+                display: self.get_color_array().to_vec(),
+                draw_flag: self.draw_flag,
+                keys: self.input.pressed,
+                key_wait: self.input.waiting,
+                wait_key: self.input.wait_key,
+                cycles: self.cycles,
+                frames: self.frames,
+                rng_state: self.rng_state,
+                rng_seed: self.rng_seed,
+                quirks: self.quirks,
+                memory_protection: self.memory_protection(),
+                memory_layout: self.memory_layout(),
+                memory_config: self.memory_config(),
+                variant: self.variant,
+                colors: self.graphic.colors.to_vec(),
+                background: self.graphic.background,
+                second_keys: self.input.second,
+                font: self.font.as_bytes(),
+                timing_mode: self.timing.mode,
+                machine_cycles: self.timing.machine_cycles,
+                cycle_budget: self.timing.budget,
+                display_wait: self.timing.display_wait,
+                rom: self.rom.clone(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Chip8Emulator {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let state = SerializedEmulator::deserialize(deserializer)?;
+            if state.memory.len() != state.memory_config.ram_size {
+                return Err(de::Error::custom("memory has the wrong size"));
+            }
+            if !state.colors.is_empty() && state.colors.len() != COLOR_COLUMNS * HEIGHT {
+                return Err(de::Error::custom("colour map has the wrong size"));
+            }
+
+            let mut chip8_emulator = Chip8Emulator::new();
+            chip8_emulator.set_memory_config(state.memory_config).map_err(de::Error::custom)?;
+            if !state.font.is_empty() {
+                let font = Font::from_bytes(&state.font).map_err(de::Error::custom)?;
+                chip8_emulator.set_font(font).map_err(de::Error::custom)?;
+            }
+            chip8_emulator.set_variant(state.variant);
+            chip8_emulator.set_quirks(state.quirks);
+            chip8_emulator.set_memory_protection(state.memory_protection);
+            chip8_emulator.set_rng_seed(state.rng_seed);
+            chip8_emulator.try_init(&state.rom).map_err(de::Error::custom)?;
+            // The variant, and with it the display size, may come from the ROM.
+            if state.display.len() != chip8_emulator.get_color_array().len() {
+                return Err(de::Error::custom("display has the wrong size"));
+            }
+            // The layout decides how deep the stack can go.
+            chip8_emulator.set_memory_layout(state.memory_layout);
+            chip8_emulator.set_cpu_state(&state.cpu).map_err(de::Error::custom)?;
+            chip8_emulator.memory.ram.copy_from_slice(&state.memory);
+            chip8_emulator.graphic.pixels[..state.display.len()].copy_from_slice(&state.display);
+            if !state.colors.is_empty() {
+                chip8_emulator.graphic.colors.copy_from_slice(&state.colors);
+            }
+            chip8_emulator.graphic.background = state.background;
+            chip8_emulator.draw_flag = state.draw_flag;
+            chip8_emulator.input.pressed = state.keys;
+            chip8_emulator.input.second = state.second_keys;
+            chip8_emulator.input.waiting = state.key_wait;
+            chip8_emulator.input.wait_key = state.wait_key;
+            chip8_emulator.cycles = state.cycles;
+            chip8_emulator.frames = state.frames;
+            chip8_emulator.rng_state = state.rng_state;
+            chip8_emulator.set_timing_mode(state.timing_mode);
+            chip8_emulator.timing.machine_cycles = state.machine_cycles;
+            chip8_emulator.timing.budget = state.cycle_budget;
+            chip8_emulator.timing.display_wait = state.display_wait;
+            // Maps the restored state into RAM again.
+            chip8_emulator.set_memory_layout(state.memory_layout);
+            Ok(chip8_emulator)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Chip8Emulator, MemoryConfig, MemoryLayout, StateError, STACK_SIZE, VIP_STACK_DEPTH};
+    #[cfg(feature = "serde")]
+    use crate::TimingMode;
+
+    /// Mid-run: V0 counting up in a loop, with a call on the stack.
+    fn running() -> Chip8Emulator {
+        // Call 0x204, then V0 += 1 forever.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_rng_seed(7);
+        chip8_emulator.init(&[0x22, 0x04, 0x00, 0x00, 0x70, 0x01, 0x12, 0x04]);
+        chip8_emulator.run_frame(9);
+        chip8_emulator
+    }
+
+    #[test]
+    fn clones_are_equal_until_one_changes() {
+        let chip8_emulator = running();
+        let mut clone = chip8_emulator.clone();
+        assert_eq!(clone, chip8_emulator);
+
+        clone.set_register(3, 1).unwrap();
+        assert_ne!(clone, chip8_emulator);
+        clone.set_register(3, 0).unwrap();
+        assert_eq!(clone, chip8_emulator);
+
+        clone.emulate_cycle();
+        assert_ne!(clone, chip8_emulator);
+    }
+
+    #[test]
+    fn set_cpu_state_rejects_invalid_state() {
+        let mut chip8_emulator = running();
+        chip8_emulator.set_memory_config(MemoryConfig::VIP_2K).unwrap();
+        let before = chip8_emulator.cpu_state();
+
+        let mut state = before;
+        state.program_counter = 0x800;
+        assert_eq!(chip8_emulator.set_cpu_state(&state), Err(StateError::AddressOutOfRange(0x800)));
+        let mut state = before;
+        state.stack_pointer = STACK_SIZE as u8 + 1;
+        assert_eq!(chip8_emulator.set_cpu_state(&state), Err(StateError::StackPointerOutOfRange(STACK_SIZE as u8 + 1)));
+        assert_eq!(chip8_emulator.cpu_state(), before);
+
+        let mut state = before;
+        state.program_counter = 0x7FE;
+        state.registers[0xF] = 1;
+        assert_eq!(chip8_emulator.set_cpu_state(&state), Ok(()));
+        assert_eq!((chip8_emulator.program_counter(), chip8_emulator.registers()[0xF]), (0x7FE, 1));
+    }
+
+    #[test]
+    fn mutators_reject_out_of_range_targets() {
+        let mut chip8_emulator = running();
+        let ram = chip8_emulator.memory().to_vec();
+        assert_eq!(chip8_emulator.write_memory(0xFFE, &[1, 2, 3]), Err(StateError::AddressOutOfRange(0x1000)));
+        assert_eq!(chip8_emulator.memory(), &ram[..]);
+        assert_eq!(chip8_emulator.write_memory(0xFFD, &[1, 2, 3]), Ok(()));
+        assert_eq!(chip8_emulator.memory()[0xFFD..], [1, 2, 3]);
+
+        assert_eq!(chip8_emulator.set_register(16, 1), Err(StateError::RegisterOutOfRange(16)));
+        assert_eq!(chip8_emulator.set_program_counter(0x1000), Err(StateError::AddressOutOfRange(0x1000)));
+        assert_eq!(StateError::RegisterOutOfRange(16).to_string(), "register V10 does not exist");
+    }
+
+    #[test]
+    fn debug_shows_the_cpu_and_rom() {
+        let chip8_emulator = running();
+        let debug = format!("{:?}", chip8_emulator);
+        assert!(debug.starts_with("Chip8Emulator { cpu: CpuState { registers: [4, 0,"));
+        assert!(debug.contains("stack_pointer: 1 }"));
+        assert!(debug.contains(&format!("rom_sha1: \"{}\"", chip8_emulator.rom_sha1())));
+        assert!(debug.ends_with("cycle_state: Running, .. }"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_the_machine() {
+        let mut chip8_emulator = running();
+        chip8_emulator.set_key(5, true);
+        chip8_emulator.set_memory_layout(MemoryLayout::Vip);
+        let json = serde_json::to_string(&chip8_emulator).unwrap();
+        let mut restored: Chip8Emulator = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, chip8_emulator);
+        assert_eq!(restored.memory_layout(), MemoryLayout::Vip);
+
+        // And both carry on the same way.
+        chip8_emulator.run_frame(5);
+        restored.run_frame(5);
+        assert_eq!(restored, chip8_emulator);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializing_rejects_inconsistent_state() {
+        let mut value = serde_json::to_value(running()).unwrap();
+        value["memory"].as_array_mut().unwrap().pop();
+        let error = serde_json::from_value::<Chip8Emulator>(value).unwrap_err();
+        assert!(error.to_string().contains("memory has the wrong size"));
+    }
+
+    #[test]
+    fn stack_pointer_is_checked_against_the_layout() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x12, 0x00]);
+        chip8_emulator.set_memory_layout(MemoryLayout::Vip);
+
+        let mut state = chip8_emulator.cpu_state();
+        state.stack_pointer = VIP_STACK_DEPTH as u8;
+        assert_eq!(chip8_emulator.set_cpu_state(&state), Ok(()));
+        state.stack_pointer += 1;
+        assert_eq!(chip8_emulator.set_cpu_state(&state), Err(StateError::StackPointerOutOfRange(state.stack_pointer)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_vip_timing() {
+        // V0 = 1, then draw forever; a frame ends on the draw, over budget.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+        chip8_emulator.init(&[0x60, 0x01, 0xD0, 0x01, 0x12, 0x02]);
+        chip8_emulator.run_frame(10);
+
+        let json = serde_json::to_string(&chip8_emulator).unwrap();
+        let mut restored: Chip8Emulator = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.timing_mode(), TimingMode::Vip);
+        assert_eq!(restored.machine_cycles(), chip8_emulator.machine_cycles());
+        assert_eq!(restored, chip8_emulator);
+
+        chip8_emulator.run_frame(10);
+        restored.run_frame(10);
+        assert_eq!(restored.cycles(), chip8_emulator.cycles());
+        assert_eq!(restored, chip8_emulator);
+    }
+}
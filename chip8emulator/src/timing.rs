@@ -0,0 +1,220 @@
+// Instruction timing. `Flat` runs a fixed number of instructions per frame,
+// as the emulator always has. `Vip` charges every instruction the machine
+// cycles it took in the original COSMAC VIP interpreter and runs as many as
+// fit in a 60 Hz frame, so programs run at the speed they were written for.
+//
+// A VIP frame is 3668 machine cycles of the 1.76 MHz 1802; the 1861 display
+// DMA and the interrupt routine take 1070 of them, the interpreter gets the
+// rest. Costs include the 40 cycles spent fetching and decoding. DXYN waits
+// for the display interrupt before drawing, so it ends the frame and the
+// sprite is charged to the next one. Cycles run over the budget are carried
+// into the next frame as well.
+//
+// Machine-code calls are charged as a plain instruction; time spent in the
+// 1802 routine is not counted.
+
+use crate::{Chip8Emulator, CycleState};
+
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+const VIP_DISPLAY_CYCLES: u32 = 1070;
+const VIP_FETCH_CYCLES: u32 = 40;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimingMode {
+    /// Every instruction takes one slot of a fixed number per frame.
+    #[default]
+    Flat,
+    /// Machine-cycle budget per frame with the VIP interpreter's costs.
+    Vip,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Timing {
+    pub(crate) mode: TimingMode,
+    pub(crate) machine_cycles: u64,
+    /// Cycles left in the current frame; negative when the last instruction
+    /// ran over.
+    pub(crate) budget: i64,
+    pub(crate) display_wait: bool,
+}
+
+impl Timing {
+    /// Back to power-on; the mode is kept.
+    pub(crate) fn reset(&mut self) {
+        *self = Self { mode: self.mode, ..Self::default() };
+    }
+
+    /// Charges an executed instruction. `draw` marks a DXYN, which waits
+    /// for the display interrupt and so uses up the frame.
+    pub(crate) fn charge(&mut self, cycles: u32, draw: bool) {
+        self.machine_cycles += cycles as u64;
+        if self.mode == TimingMode::Flat {
+            return;
+        }
+        if draw {
+            // The rest of the frame goes by waiting; the drawing itself
+            // happens in the next one.
+            self.budget = self.budget.min(0);
+            self.display_wait = true;
+        }
+        self.budget -= cycles as i64;
+    }
+}
+
+/// Machine cycles the VIP interpreter spends on `opcode`, given VX, V0 and I
+/// as they were before it ran and whether it skipped.
+pub fn vip_cycles(opcode: u16, vx: u8, v0: u8, i: u16, skipped: bool) -> u32 {
+    let skip = if skipped { 4 } else { 0 };
+    let n = (opcode & 0x000F) as u32;
+    let cost = match opcode >> 12 {
+        0x0 if opcode == 0x00E0 => 24 + 3078,
+        0x0 if opcode == 0x00EE => 10,
+        0x0 => 10,
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10 + skip,
+        0x5 | 0x9 => 14 + skip,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 if n == 0 => 12,
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22 + if (opcode & 0xFF) + v0 as u16 > 0xFF { 2 } else { 0 },
+        0xC => 36,
+        // Each row is shifted into place one bit at a time unless aligned.
+        0xD => {
+            let shift = (vx & 7) as u32;
+            let row = if shift == 0 { 34 } else { 44 + 8 * shift };
+            26 + n * row
+        },
+        0xE => 14 + skip,
+        _ => match opcode & 0xFF {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 18,
+            0x1E => 16 + if (i & 0xFF) + vx as u16 > 0xFF { 4 } else { 0 },
+            0x29 => 16,
+            // Digits are found by repeated subtraction.
+            0x33 => 84 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u32,
+            0x55 | 0x65 => 14 + 14 * ((opcode >> 8 & 0xF) as u32 + 1),
+            _ => 10,
+        },
+    };
+    VIP_FETCH_CYCLES + cost
+}
+
+impl Chip8Emulator {
+    pub fn set_timing_mode(&mut self, mode: TimingMode) {
+        self.timing.mode = mode;
+        self.timing.budget = 0;
+        self.timing.display_wait = false;
+    }
+
+    pub fn timing_mode(&self) -> TimingMode {
+        self.timing.mode
+    }
+
+    /// VIP machine cycles spent since power-on, counted in either mode.
+    pub fn machine_cycles(&self) -> u64 {
+        self.timing.machine_cycles
+    }
+
+    /// Runs one 60 Hz frame and ticks the timers: `instructions_per_frame`
+    /// instructions in `Flat` mode, the frame's machine-cycle budget in `Vip`
+    /// mode. Stops early once the program idles or halts.
+    pub fn run_frame(&mut self, instructions_per_frame: usize) -> CycleState {
+        let mut state = self.cycle_state();
+        match self.timing.mode {
+            TimingMode::Flat => {
+                for _ in 0..instructions_per_frame {
+                    state = self.emulate_cycle();
+                    if state != CycleState::Running {
+                        break;
+                    }
+                }
+            },
+            TimingMode::Vip => {
+                self.timing.budget += (VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES) as i64;
+                self.timing.display_wait = false;
+                while self.timing.budget > 0 && !self.timing.display_wait {
+                    state = self.emulate_cycle();
+                    if state != CycleState::Running {
+                        break;
+                    }
+                }
+                // An idle or halted program burns the rest of the frame.
+                self.timing.budget = self.timing.budget.min(0);
+            },
+        }
+        self.advance_timers();
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_the_vip_interpreter_costs() {
+        assert_eq!(vip_cycles(0x6012, 0, 0, 0, false), 46);
+        assert_eq!(vip_cycles(0x00E0, 0, 0, 0, false), 3142);
+        assert_eq!(vip_cycles(0x3012, 0, 0, 0, true), vip_cycles(0x3012, 0, 0, 0, false) + 4);
+        // Aligned sprites skip the bit shifting.
+        assert_eq!(vip_cycles(0xD015, 8, 0, 0, false), 40 + 26 + 5 * 34);
+        assert_eq!(vip_cycles(0xD015, 3, 0, 0, false), 40 + 26 + 5 * 68);
+        assert_eq!(vip_cycles(0xF033, 255, 0, 0, false), 40 + 84 + 16 * 12);
+        assert_eq!(vip_cycles(0xF21E, 0x80, 0, 0x280, false), 40 + 20);
+        assert_eq!(vip_cycles(0xB0FF, 0, 1, 0, false), 40 + 24);
+    }
+
+    #[test]
+    fn flat_frames_run_a_fixed_instruction_count() {
+        // V0 += 1 in a loop.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&[0x70, 0x01, 0x12, 0x00]);
+        chip8_emulator.run_frame(10);
+        assert_eq!(chip8_emulator.cycles(), 10);
+        assert_eq!(chip8_emulator.machine_cycles(), 5 * 50 + 5 * 52);
+    }
+
+    #[test]
+    fn vip_frames_spend_a_cycle_budget() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+        chip8_emulator.init(&[0x70, 0x01, 0x12, 0x00]);
+        chip8_emulator.run_frame(10);
+        // 25 loops of 102 cycles, then one more 7001 runs over by 2.
+        assert_eq!(chip8_emulator.cycles(), 51);
+        assert_eq!(chip8_emulator.timing.budget, -2);
+        // The overrun comes out of the next frame, which ends 6 over.
+        chip8_emulator.run_frame(10);
+        assert_eq!(chip8_emulator.timing.budget, -6);
+        assert_eq!(chip8_emulator.machine_cycles(), 2 * (VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES) as u64 + 6);
+    }
+
+    #[test]
+    fn a_draw_ends_the_vip_frame() {
+        // V0 = 1, then draw forever.
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+        chip8_emulator.init(&[0x60, 0x01, 0xD0, 0x01, 0x12, 0x02]);
+        chip8_emulator.run_frame(10);
+        assert_eq!(chip8_emulator.cycles(), 2);
+        // The sprite is paid for out of the next frame.
+        assert_eq!(chip8_emulator.timing.budget, -118);
+        chip8_emulator.run_frame(10);
+        assert_eq!(chip8_emulator.cycles(), 4);
+    }
+
+    #[test]
+    fn switching_modes_clears_the_budget() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+        chip8_emulator.init(&[0x60, 0x01, 0xD0, 0x01, 0x12, 0x02]);
+        chip8_emulator.run_frame(10);
+        chip8_emulator.set_timing_mode(TimingMode::Flat);
+        assert_eq!((chip8_emulator.timing.budget, chip8_emulator.timing.display_wait), (0, false));
+        assert_eq!(chip8_emulator.timing_mode(), TimingMode::Flat);
+    }
+}
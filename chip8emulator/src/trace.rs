@@ -0,0 +1,218 @@
+// Call tracing: every 2NNN/00EE pair becomes a begin/end span, with 60 Hz
+// ticks and DXYN draws as instant events, exported as Chrome trace JSON
+// (chrome://tracing, Perfetto). Timestamps are instruction counts rather than
+// microseconds, so the timeline reads in cycles.
+//
+// Symbol maps name the spans. One symbol per line in any of these forms,
+// addresses in hex, `#` or `;` starts a comment:
+//
+//     draw_player 0x2A0
+//     0x2A0 draw_player
+//     draw_player = 0x2A0
+//     draw_player: $2A0
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::Chip8Emulator;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TraceEvent {
+    Call { address: u16, cycle: u64 },
+    Return { cycle: u64 },
+    Frame { cycle: u64 },
+    Draw { pc: u16, cycle: u64 },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    symbols: HashMap<u16, String>,
+}
+
+impl SymbolMap {
+    /// Lines that do not look like a symbol definition are skipped.
+    pub fn parse(text: &str) -> Self {
+        let address = |s: &str| {
+            let s = s.trim();
+            let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')).unwrap_or(s);
+            u16::from_str_radix(digits, 16).ok()
+        };
+
+        let mut symbols = HashMap::new();
+        for line in text.lines() {
+            let line = line.split(['#', ';']).next().unwrap().replace(['=', ':'], " ");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let [first, second] = fields[..] {
+                // Names like `add` are valid hex too, so a prefixed address
+                // decides the column, then the name-first form wins.
+                let prefixed = first.starts_with("0x") || first.starts_with('$');
+                match (address(first), address(second)) {
+                    (Some(address), _) if prefixed => symbols.insert(address, second.to_string()),
+                    (_, Some(address)) => symbols.insert(address, first.to_string()),
+                    (Some(address), None) => symbols.insert(address, second.to_string()),
+                    _ => None,
+                };
+            }
+        }
+        Self { symbols }
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.symbols.get(&address).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallTrace {
+    events: Vec<TraceEvent>,
+    depth: usize,
+    last_cycle: u64,
+}
+
+impl CallTrace {
+    pub(crate) fn record_instruction(&mut self, pc: u16, opcode: u16, cycle: u64) {
+        self.last_cycle = cycle;
+        match opcode & 0xF000 {
+            0x2000 => {
+                self.depth += 1;
+                self.events.push(TraceEvent::Call { address: opcode & 0x0FFF, cycle });
+            },
+            0xD000 => self.events.push(TraceEvent::Draw { pc, cycle }),
+            // A return with nothing open was called before tracing started.
+            _ if opcode == 0x00EE && self.depth > 0 => {
+                self.depth -= 1;
+                self.events.push(TraceEvent::Return { cycle });
+            },
+            _ => (),
+        }
+    }
+
+    pub(crate) fn record_frame(&mut self, cycle: u64) {
+        self.events.push(TraceEvent::Frame { cycle });
+    }
+
+    /// Number of completed or open subroutine calls.
+    pub fn calls(&self) -> usize {
+        self.events.iter().filter(|event| matches!(event, TraceEvent::Call { .. })).count()
+    }
+
+    /// Chrome trace event JSON. Spans still open are closed at the last
+    /// traced cycle.
+    pub fn to_chrome_json(&self, symbols: Option<&SymbolMap>) -> String {
+        let mut events = Vec::with_capacity(self.events.len() + self.depth);
+        let mut open = Vec::new();
+        for event in &self.events {
+            let entry = match *event {
+                TraceEvent::Call { address, cycle } => {
+                    let name = symbols
+                        .and_then(|symbols| symbols.name(address))
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("sub_{:03X}", address));
+                    let entry = format!("{{\"name\":{},\"cat\":\"call\",\"ph\":\"B\",\"ts\":{},\"pid\":1,\"tid\":1,\"args\":{{\"address\":\"{:#05x}\"}}}}",
+                        json_string(&name), cycle, address);
+                    open.push(name);
+                    entry
+                },
+                TraceEvent::Return { cycle } => {
+                    let name = open.pop().unwrap_or_default();
+                    format!("{{\"name\":{},\"cat\":\"call\",\"ph\":\"E\",\"ts\":{},\"pid\":1,\"tid\":1}}", json_string(&name), cycle)
+                },
+                TraceEvent::Frame { cycle } => {
+                    format!("{{\"name\":\"frame\",\"cat\":\"frame\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{},\"pid\":1,\"tid\":1}}", cycle)
+                },
+                TraceEvent::Draw { pc, cycle } => {
+                    format!("{{\"name\":\"DXYN\",\"cat\":\"draw\",\"ph\":\"i\",\"s\":\"t\",\"ts\":{},\"pid\":1,\"tid\":1,\"args\":{{\"pc\":\"{:#05x}\"}}}}", cycle, pc)
+                },
+            };
+            events.push(entry);
+        }
+        while let Some(name) = open.pop() {
+            events.push(format!("{{\"name\":{},\"cat\":\"call\",\"ph\":\"E\",\"ts\":{},\"pid\":1,\"tid\":1}}", json_string(&name), self.last_cycle));
+        }
+
+        let mut json = String::from("{\"displayTimeUnit\":\"ns\",\"otherData\":{\"timestamps\":\"instructions\"},\"traceEvents\":[\n");
+        for (idx, event) in events.iter().enumerate() {
+            let separator = if idx + 1 < events.len() { "," } else { "" };
+            writeln!(json, "{}{}", event, separator).unwrap();
+        }
+        json.push_str("]}\n");
+        json
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Chip8Emulator {
+    /// Starts recording subroutine calls, frames and draws.
+    pub fn start_call_trace(&mut self) {
+        self.call_trace = Some(CallTrace::default());
+    }
+
+    pub fn stop_call_trace(&mut self) -> Option<CallTrace> {
+        self.call_trace.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_maps_accept_every_form() {
+        let symbols = SymbolMap::parse("draw 0x2A0\n0x2B0 move  # player\nadd = 2C0\nscore: $2D0\n; comment\nnot a symbol\n");
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.name(0x2A0), Some("draw"));
+        assert_eq!(symbols.name(0x2B0), Some("move"));
+        assert_eq!(symbols.name(0x2C0), Some("add"));
+        assert_eq!(symbols.name(0x2D0), Some("score"));
+    }
+
+    #[test]
+    fn calls_become_named_spans_closed_at_the_end() {
+        let mut trace = CallTrace::default();
+        // A stray return before any call is ignored.
+        trace.record_instruction(0x200, 0x00EE, 1);
+        trace.record_instruction(0x202, 0x22A0, 2);
+        trace.record_instruction(0x2A0, 0xD015, 3);
+        trace.record_instruction(0x2A2, 0x00EE, 4);
+        trace.record_frame(5);
+        trace.record_instruction(0x204, 0x22B0, 6);
+        assert_eq!(trace.calls(), 2);
+
+        let json = trace.to_chrome_json(Some(&SymbolMap::parse("draw 0x2A0")));
+        let events: Vec<&str> = json.lines().filter(|line| line.starts_with("{\"name\"")).collect();
+        assert_eq!(events.len(), 6);
+        assert!(events[0].starts_with("{\"name\":\"draw\",\"cat\":\"call\",\"ph\":\"B\",\"ts\":2,"));
+        assert!(events[1].contains("\"ph\":\"i\",\"s\":\"t\",\"ts\":3,") && events[1].contains("\"pc\":\"0x2a0\""));
+        assert!(events[2].starts_with("{\"name\":\"draw\",\"cat\":\"call\",\"ph\":\"E\",\"ts\":4,"));
+        assert!(events[3].starts_with("{\"name\":\"frame\""));
+        assert!(events[4].starts_with("{\"name\":\"sub_2B0\",\"cat\":\"call\",\"ph\":\"B\""));
+        assert!(events[5].starts_with("{\"name\":\"sub_2B0\",\"cat\":\"call\",\"ph\":\"E\",\"ts\":6,"));
+        assert!(json.ends_with("]}\n"));
+    }
+
+    #[test]
+    fn escapes_symbol_names() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
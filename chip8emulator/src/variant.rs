@@ -0,0 +1,127 @@
+// Interpreter variants. Most programs are plain CHIP-8; the others give some
+// of its opcodes a meaning of their own, so the variant has to be chosen
+// before the program runs.
+//
+// Hi-res programs are recognised by their first instruction: they were run
+// with the two-page VIP interpreter, which the program itself patches in by
+// starting with a jump to 0x260. Instead of running the patch, 1260 at the
+// program start enters the program proper at 0x2C0, and 0230 clears the
+// 64x64 screen.
+
+use crate::{Chip8Emulator, HEIGHT, WIDTH};
+
+pub const HIRES_HEIGHT: usize = 64;
+const HIRES_SIGNATURE: [u8; 2] = [0x12, 0x60];
+const HIRES_PROGRAM_START: u16 = 0x200;
+pub(crate) const HIRES_ENTRY: u16 = 0x2C0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Variant {
+    #[default]
+    Chip8,
+    /// VIP interpreter for the VP-590 colour board; programs load at 0x300,
+    /// see `MemoryConfig::CHIP_8X`.
+    Chip8X,
+    /// Two-page hi-res CHIP-8 with a 64x64 display; picked by `init` for
+    /// programs that start with 1260.
+    HiRes,
+}
+
+impl Chip8Emulator {
+    /// Switches the instruction set; call before `init`. The colour map is
+    /// reset. `init` picks between `Chip8` and `HiRes` by itself, so only
+    /// `Chip8X` has to be chosen this way.
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.reset_colors();
+        self.set_draw_flag(true);
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Width and height of the display in pixels.
+    pub fn display_size(&self) -> (usize, usize) {
+        (WIDTH, self.display_height())
+    }
+
+    pub(crate) fn display_height(&self) -> usize {
+        match self.variant {
+            Variant::HiRes => HIRES_HEIGHT,
+            _ => HEIGHT,
+        }
+    }
+
+    /// Switches between plain and hi-res CHIP-8 depending on whether
+    /// `program` carries the hi-res signature; CHIP-8X is left alone.
+    pub(crate) fn detect_variant(&mut self, program: &[u8]) {
+        if self.variant == Variant::Chip8X {
+            return;
+        }
+        let hires = self.memory.config.program_start == HIRES_PROGRAM_START && program.starts_with(&HIRES_SIGNATURE);
+        self.variant = if hires { Variant::HiRes } else { Variant::Chip8 };
+        self.set_draw_flag(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryConfig;
+
+    /// The hi-res signature, then at the entry point: V1 = 40, draw the
+    /// font's first row at 0,40, clear the 64x64 screen.
+    fn hires_rom() -> Vec<u8> {
+        let mut rom = vec![0; (HIRES_ENTRY - HIRES_PROGRAM_START) as usize];
+        rom[..2].copy_from_slice(&HIRES_SIGNATURE);
+        rom.extend_from_slice(&[0x61, 0x28, 0xA0, 0x00, 0xD0, 0x11, 0x02, 0x30]);
+        rom
+    }
+
+    #[test]
+    fn detects_hires_programs_by_their_first_instruction() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&hires_rom());
+        assert_eq!(chip8_emulator.variant(), Variant::HiRes);
+        assert_eq!(chip8_emulator.display_size(), (WIDTH, HIRES_HEIGHT));
+        assert_eq!(chip8_emulator.get_color_array().len(), WIDTH * HIRES_HEIGHT);
+
+        // Loading a plain program switches back.
+        chip8_emulator.init(&[0x12, 0x00]);
+        assert_eq!(chip8_emulator.variant(), Variant::Chip8);
+        assert_eq!(chip8_emulator.display_size(), (WIDTH, HEIGHT));
+    }
+
+    #[test]
+    fn hires_programs_draw_on_64_rows() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.init(&hires_rom());
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.program_counter(), HIRES_ENTRY);
+        for _ in 0..3 {
+            chip8_emulator.emulate_cycle();
+        }
+        assert_eq!(chip8_emulator.get_color_array()[40 * WIDTH..40 * WIDTH + 5], [1, 1, 1, 1, 0]);
+        chip8_emulator.emulate_cycle();
+        assert!(chip8_emulator.get_color_array().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn the_signature_only_counts_at_0x200() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_memory_config(MemoryConfig::ETI_660).unwrap();
+        chip8_emulator.init(&hires_rom());
+        assert_eq!(chip8_emulator.variant(), Variant::Chip8);
+    }
+
+    #[test]
+    fn chip8x_is_left_alone() {
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.set_variant(Variant::Chip8X);
+        chip8_emulator.init(&hires_rom());
+        assert_eq!(chip8_emulator.variant(), Variant::Chip8X);
+        assert_eq!(chip8_emulator.display_size(), (WIDTH, HEIGHT));
+    }
+}
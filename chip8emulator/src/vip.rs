@@ -0,0 +1,271 @@
+// COSMAC VIP hybrid programs. `VipHybrid` is an opcode extension that runs
+// 0NNN targets as real 1802 machine code on the interpreter's RAM, entered
+// the way the original VIP interpreter calls them: P = 3 with R3 = NNN,
+// X = 2, and the interpreter registers set up. The routine returns with
+// SEP R4 (D4).
+//
+// The CHIP-8 state is mirrored into its VIP locations around every run, so
+// the routine sees V0-VF at 0xEF0 and the display buffer at 0xF00 (with
+// 4 KiB; like the VIP interpreter, they sit at the top of RAM), and changes
+// made there come back. The 1802 I/O is wired like the VIP: OUT 2
+// latches the keypad key that EF3 reports, INP 1 and OUT 1 turn the 1861
+// display on and off. The VIP interpreter's own
+// routines below 0x200 are not present, so only calls into program memory
+// are claimed.
+//
+// Routines run in slices of `SLICE_INSTRUCTIONS`, and IDL ends a slice the
+// way it waits for the next display interrupt. While a routine is running
+// the CHIP-8 PC stays on the 0NNN, so timers tick and frames are presented
+// in between.
+
+use crate::cdp1802::{Cdp1802, Cdp1802Io};
+use crate::{Chip8Emulator, OpcodeExtension, HEIGHT, NUM_KEYS, WIDTH};
+
+pub(crate) const VIP_STACK_TOP: u16 = 0x0ECF;
+pub(crate) const VIP_REGISTERS: u16 = 0x0EF0;
+pub(crate) const VIP_DISPLAY: u16 = 0x0F00;
+
+/// Where the interpreter area at `address` of a 4 KiB machine is with
+/// `memory_size` bytes of RAM.
+pub(crate) fn vip_address(memory_size: usize, address: u16) -> usize {
+    memory_size - (0x1000 - address as usize)
+}
+
+const SLICE_INSTRUCTIONS: usize = 1000;
+const PROGRAM_START: u16 = 0x200;
+/// R0-RF, the other CPU registers, keys, key latch, display and the running call.
+const VIP_STATE_SIZE: usize = 32 + 8 + NUM_KEYS + 2 + 3;
+
+/// VIP peripherals as seen by the 1802.
+#[derive(Clone, Debug, Default)]
+struct VipIo {
+    keys: [bool; NUM_KEYS],
+    key_latch: u8,
+    display_enabled: bool,
+}
+
+impl Cdp1802Io for VipIo {
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_enabled = false,
+            2 => self.key_latch = value & 0x0F,
+            _ => (),
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_enabled = true;
+        }
+        0
+    }
+
+    fn flag(&mut self, line: u8) -> bool {
+        line == 3 && self.keys[self.key_latch as usize]
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VipHybrid {
+    cpu: Cdp1802,
+    io: VipIo,
+    /// Address of the 0NNN whose routine is still running.
+    running: Option<u16>,
+}
+
+impl VipHybrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cpu(&self) -> &Cdp1802 {
+        &self.cpu
+    }
+
+    /// Whether a routine was interrupted mid-way and resumes on the next cycle.
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Whether the routine left the 1861 display on (INP 1) or off (OUT 1).
+    pub fn display_enabled(&self) -> bool {
+        self.io.display_enabled
+    }
+
+    /// Copies the CHIP-8 state into VIP RAM and the interpreter registers.
+    fn enter(&mut self, chip8_emulator: &mut Chip8Emulator) {
+        let registers = &chip8_emulator.registers;
+        let ram = &mut chip8_emulator.memory.ram;
+        let v = vip_address(ram.len(), VIP_REGISTERS);
+        ram[v..v + 16].copy_from_slice(&registers.gp_registers);
+        let display = vip_address(ram.len(), VIP_DISPLAY);
+        for (byte, pixels) in ram[display..display + WIDTH * HEIGHT / 8].iter_mut().zip(chip8_emulator.graphic.pixels.chunks(8)) {
+            *byte = pixels.iter().fold(0, |acc, &pixel| acc << 1 | pixel);
+        }
+
+        self.cpu.r[0x8] = (registers.delay_timer as u16) << 8 | registers.sound_timer as u16;
+        self.cpu.r[0xA] = registers.i;
+        self.cpu.r[0xB] = display as u16;
+        self.io.keys = chip8_emulator.input.pressed;
+    }
+
+    /// Copies VIP RAM and the interpreter registers back into the CHIP-8 state.
+    fn leave(&mut self, chip8_emulator: &mut Chip8Emulator) {
+        let ram = &chip8_emulator.memory.ram;
+        let registers = &mut chip8_emulator.registers;
+        let v = vip_address(ram.len(), VIP_REGISTERS);
+        registers.gp_registers.copy_from_slice(&ram[v..v + 16]);
+        registers.delay_timer = (self.cpu.r[0x8] >> 8) as u8;
+        registers.sound_timer = self.cpu.r[0x8] as u8;
+        registers.i = self.cpu.r[0xA];
+
+        let display = vip_address(ram.len(), VIP_DISPLAY);
+        for (idx, pixel) in chip8_emulator.graphic.pixels.iter_mut().take(WIDTH * HEIGHT).enumerate() {
+            let bit = ram[display + idx / 8] >> (7 - idx % 8) & 1;
+            if *pixel != bit {
+                *pixel = bit;
+                chip8_emulator.draw_flag = true;
+            }
+        }
+    }
+}
+
+impl OpcodeExtension for VipHybrid {
+    fn claims(&self, opcode: u16) -> bool {
+        opcode & 0xF000 == 0 && opcode >= PROGRAM_START
+    }
+
+    fn execute(&mut self, chip8_emulator: &mut Chip8Emulator, opcode: u16) {
+        let next_pc = chip8_emulator.registers.program_counter;
+        let caller = next_pc.wrapping_sub(2);
+        // A different call site means the machine was reset or rewound
+        // since the last slice; start over.
+        if self.running != Some(caller) {
+            self.cpu.p = 3;
+            self.cpu.x = 2;
+            self.cpu.idle = false;
+            let memory_size = chip8_emulator.memory.ram.len();
+            let registers = vip_address(memory_size, VIP_REGISTERS) as u16;
+            self.cpu.r[0x2] = vip_address(memory_size, VIP_STACK_TOP) as u16 - 2 * chip8_emulator.stack.stack_pointer as u16;
+            self.cpu.r[0x3] = opcode;
+            self.cpu.r[0x5] = next_pc;
+            self.cpu.r[0x6] = registers + (opcode >> 8 & 0xF);
+            self.cpu.r[0x7] = registers + (opcode >> 4 & 0xF);
+            self.running = Some(caller);
+        }
+        self.enter(chip8_emulator);
+
+        for _ in 0..SLICE_INSTRUCTIONS {
+            self.cpu.step(&mut chip8_emulator.memory.ram, &mut self.io);
+            if self.cpu.p == 4 {
+                self.running = None;
+                break;
+            }
+            if self.cpu.idle {
+                self.cpu.idle = false;
+                break;
+            }
+        }
+
+        self.leave(chip8_emulator);
+        chip8_emulator.registers.program_counter = if self.running.is_some() {
+            caller
+        } else {
+            (self.cpu.r[0x5] as usize % chip8_emulator.memory.ram.len()) as u16
+        };
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let cpu = &self.cpu;
+        let mut state: Vec<u8> = cpu.r.iter().flat_map(|r| r.to_le_bytes()).collect();
+        state.extend([cpu.p, cpu.x, cpu.d, cpu.df as u8, cpu.q as u8, cpu.t, cpu.ie as u8, cpu.idle as u8]);
+        state.extend(self.io.keys.map(u8::from));
+        state.extend([self.io.key_latch, self.io.display_enabled as u8, self.running.is_some() as u8]);
+        state.extend(self.running.unwrap_or(0).to_le_bytes());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> bool {
+        if state.len() != VIP_STATE_SIZE {
+            return false;
+        }
+        let (registers, rest) = state.split_at(32);
+        for (r, bytes) in self.cpu.r.iter_mut().zip(registers.chunks(2)) {
+            *r = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        let cpu = &mut self.cpu;
+        [cpu.p, cpu.x, cpu.d, cpu.t] = [rest[0], rest[1], rest[2], rest[5]];
+        [cpu.df, cpu.q, cpu.ie, cpu.idle] = [rest[3] != 0, rest[4] != 0, rest[6] != 0, rest[7] != 0];
+        let rest = &rest[8..];
+        for (key, byte) in self.io.keys.iter_mut().zip(rest) {
+            *key = *byte != 0;
+        }
+        let rest = &rest[NUM_KEYS..];
+        self.io.key_latch = rest[0] & 0x0F;
+        self.io.display_enabled = rest[1] != 0;
+        self.running = Some(u16::from_le_bytes([rest[3], rest[4]])).filter(|_| rest[2] != 0);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls the machine code at 0x204 with 0204, then spins at 0x202.
+    fn hybrid(routine: &[u8]) -> Chip8Emulator {
+        let mut rom = vec![0x02, 0x04, 0x12, 0x02];
+        rom.extend_from_slice(routine);
+        let mut chip8_emulator = Chip8Emulator::new();
+        chip8_emulator.add_extension(Box::new(VipHybrid::new()));
+        chip8_emulator.init(&rom);
+        chip8_emulator
+    }
+
+    #[test]
+    fn routines_see_the_interpreter_registers() {
+        // LDI 2A, STR R6 (VX of the call, V2), SEP R4
+        let mut chip8_emulator = hybrid(&[0xF8, 0x2A, 0x56, 0xD4]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.registers()[2], 0x2A);
+        assert_eq!(chip8_emulator.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn display_changes_come_back() {
+        // LDI 80, STR RB (first display byte), SEP R4
+        let mut chip8_emulator = hybrid(&[0xF8, 0x80, 0x5B, 0xD4]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.get_color_array()[..2], [1, 0]);
+        assert!(chip8_emulator.draw_flag);
+    }
+
+    #[test]
+    fn idle_routines_resume_after_a_snapshot() {
+        // IDL, then as above.
+        let mut chip8_emulator = hybrid(&[0x00, 0xF8, 0x2A, 0x56, 0xD4]);
+        chip8_emulator.emulate_cycle();
+        assert_eq!(chip8_emulator.program_counter(), 0x200);
+        let snapshot = chip8_emulator.snapshot();
+
+        let mut restored = hybrid(&[0x00, 0xF8, 0x2A, 0x56, 0xD4]);
+        restored.restore(&snapshot).unwrap();
+        restored.emulate_cycle();
+        assert_eq!(restored.registers()[2], 0x2A);
+        assert_eq!(restored.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn state_must_have_the_exact_size() {
+        let mut vip_hybrid = VipHybrid::new();
+        vip_hybrid.running = Some(0x300);
+        vip_hybrid.cpu.r[3] = 0x1234;
+        let state = vip_hybrid.save_state();
+        assert_eq!(state.len(), VIP_STATE_SIZE);
+
+        let mut loaded = VipHybrid::new();
+        assert!(!loaded.load_state(&state[1..]));
+        assert!(loaded.load_state(&state));
+        assert_eq!((loaded.running, loaded.cpu.r[3]), (Some(0x300), 0x1234));
+    }
+}
@@ -0,0 +1,139 @@
+// Audio capture: renders the sound-timer beeper as a square wave and writes
+// the result out as a mono 16-bit PCM WAV file.
+
+use std::time::Duration;
+
+use crate::Chip8Emulator;
+
+const TICKS_PER_SECOND: u32 = 60;
+const BEEP_FREQUENCY: f64 = 440.0;
+const BEEP_AMPLITUDE: i16 = i16::MAX / 4;
+
+pub struct WavRecorder {
+    sample_rate: u32,
+    phase: f64,
+    fractional_samples: f64,
+    samples: Vec<i16>,
+}
+
+impl WavRecorder {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            phase: 0.0,
+            fractional_samples: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Renders one 60 Hz timer tick; meant for offline runs that step frame by frame.
+    pub fn capture_tick(&mut self, chip8_emulator: &Chip8Emulator) {
+        let samples = self.sample_rate as f64 / TICKS_PER_SECOND as f64;
+        self.capture_samples(chip8_emulator, samples);
+    }
+
+    /// Renders `duration` of wall-clock time; meant for real-time frontends.
+    pub fn capture_duration(&mut self, chip8_emulator: &Chip8Emulator, duration: Duration) {
+        let samples = duration.as_secs_f64() * self.sample_rate as f64;
+        self.capture_samples(chip8_emulator, samples);
+    }
+
+    fn capture_samples(&mut self, chip8_emulator: &Chip8Emulator, samples: f64) {
+        let exact = samples + self.fractional_samples;
+        let count = exact.floor();
+        self.fractional_samples = exact - count;
+        self.render(chip8_emulator.is_sound_playing(), count as usize);
+    }
+
+    fn render(&mut self, sound_on: bool, count: usize) {
+        let step = BEEP_FREQUENCY / self.sample_rate as f64;
+        for _ in 0..count {
+            let sample = if !sound_on {
+                0
+            } else if self.phase < 0.5 {
+                BEEP_AMPLITUDE
+            } else {
+                -BEEP_AMPLITUDE
+            };
+            self.samples.push(sample);
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let data_len = (self.samples.len() * 2) as u32;
+        let byte_rate = self.sample_rate * 2;
+
+        let mut out = Vec::with_capacity(44 + data_len as usize);
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        out.extend_from_slice(&1u16.to_le_bytes()); // mono
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend_from_slice(&byte_rate.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes()); // block align
+        out.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for sample in self.samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_a_pcm_header() {
+        let mut recorder = WavRecorder::new(8000);
+        recorder.render(true, 10);
+        let wav = recorder.finish();
+
+        assert_eq!(wav.len(), 44 + 20);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4), wav.len() as u32 - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 16), 16);
+        assert_eq!(&wav[20..24], &[1, 0, 1, 0]);
+        assert_eq!(u32_at(&wav, 24), 8000);
+        assert_eq!(u32_at(&wav, 28), 16000);
+        assert_eq!(&wav[32..36], &[2, 0, 16, 0]);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 20);
+    }
+
+    #[test]
+    fn renders_a_square_wave_only_while_sounding() {
+        let mut recorder = WavRecorder::new(4400);
+        recorder.render(true, 10);
+        recorder.render(false, 2);
+        let mut expected = vec![BEEP_AMPLITUDE; 5];
+        expected.extend([-BEEP_AMPLITUDE; 5]);
+        expected.extend([0, 0]);
+        assert_eq!(recorder.samples, expected);
+    }
+
+    #[test]
+    fn ticks_carry_fractional_samples() {
+        let chip8_emulator = Chip8Emulator::new();
+        let mut recorder = WavRecorder::new(8000);
+        for _ in 0..60 {
+            recorder.capture_tick(&chip8_emulator);
+        }
+        // 133 1/3 samples a tick; the fractions add up to a second, give or
+        // take floating-point rounding.
+        assert!((7999..=8000).contains(&recorder.samples.len()));
+    }
+}
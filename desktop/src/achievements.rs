@@ -0,0 +1,191 @@
+// Achievements for the desktop app. Definitions live in achievements.json as
+// a map from ROM SHA-1 to a list of achievements; unlocks are appended to
+// achievements/history.txt as `<rom sha1> <id> <unix seconds>` lines and are
+// not offered again.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+
+use chip8emulator::{Achievement, AchievementEngine, Chip8Emulator};
+
+const DEFINITIONS_PATH: &str = "achievements.json";
+const HISTORY_PATH: &str = "achievements/history.txt";
+const BANNER_DURATION: Duration = Duration::from_secs(3);
+const GLYPH_SCALE: u32 = 4;
+const BANNER_PADDING: u32 = 8;
+
+pub struct AchievementTracker {
+    engine: Option<AchievementEngine>,
+    rom_sha1: String,
+    banners: Vec<(String, Instant)>,
+}
+
+impl AchievementTracker {
+    pub fn load(chip8_emulator: &Chip8Emulator) -> Self {
+        let rom_sha1 = chip8_emulator.rom_sha1();
+        let definitions = match fs::read_to_string(DEFINITIONS_PATH) {
+            Ok(text) => match serde_json::from_str::<HashMap<String, Vec<Achievement>>>(&text) {
+                Ok(mut definitions) => definitions.remove(&rom_sha1).unwrap_or_default(),
+                Err(err) => {
+                    eprintln!("Ignoring {}: {}", DEFINITIONS_PATH, err);
+                    Vec::new()
+                },
+            },
+            Err(_) => Vec::new(),
+        };
+
+        let unlocked: Vec<String> = fs::read_to_string(HISTORY_PATH)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next()) {
+                    (Some(sha1), Some(id)) if sha1 == rom_sha1 => Some(id.to_string()),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let engine = if definitions.is_empty() {
+            None
+        } else {
+            match AchievementEngine::new(definitions, &unlocked) {
+                Ok(engine) => {
+                    let total = engine.achievements().len();
+                    let done = engine.achievements().iter().filter(|achievement| engine.is_unlocked(&achievement.id)).count();
+                    println!("Achievements: {}/{} unlocked", done, total);
+                    Some(engine)
+                },
+                Err(err) => {
+                    eprintln!("Ignoring {}: {}", DEFINITIONS_PATH, err);
+                    None
+                },
+            }
+        };
+
+        Self { engine, rom_sha1, banners: Vec::new() }
+    }
+
+    /// Evaluates one frame; new unlocks are recorded and queued as banners.
+    pub fn update(&mut self, chip8_emulator: &Chip8Emulator) {
+        let Some(engine) = self.engine.as_mut() else {
+            return;
+        };
+        let mut unlocked = Vec::new();
+        engine.evaluate(chip8_emulator, |achievement| unlocked.push(achievement.clone()));
+
+        for achievement in unlocked {
+            println!("Achievement unlocked: {} - {}", achievement.title, achievement.description);
+            self.record(&achievement);
+            self.banners.push((achievement.title, Instant::now()));
+        }
+    }
+
+    fn record(&self, achievement: &Achievement) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let result = fs::create_dir_all(Path::new(HISTORY_PATH).parent().unwrap())
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(HISTORY_PATH))
+            .and_then(|mut file| writeln!(file, "{} {} {}", self.rom_sha1, achievement.id, timestamp));
+        if let Err(err) = result {
+            eprintln!("Failed to save {}: {}", HISTORY_PATH, err);
+        }
+    }
+
+    /// True while a banner is on screen or has just expired, so the frame
+    /// has to be redrawn.
+    pub fn needs_redraw(&mut self) -> bool {
+        let before = self.banners.len();
+        self.banners.retain(|(_, shown)| shown.elapsed() < BANNER_DURATION);
+        !self.banners.is_empty() || self.banners.len() != before
+    }
+
+    /// Draws the oldest pending banner across the top of the window.
+    pub fn draw_overlay(&self, canvas: &mut WindowCanvas, foreground: Color, background: Color) {
+        let Some((title, _)) = self.banners.first() else {
+            return;
+        };
+        let lines = ["ACHIEVEMENT UNLOCKED", title.as_str()];
+        let line_height = 6 * GLYPH_SCALE;
+        let width = canvas.output_size().map(|(width, _)| width).unwrap_or(0);
+        let height = BANNER_PADDING * 2 + line_height * lines.len() as u32;
+
+        canvas.set_draw_color(foreground);
+        canvas.fill_rect(Rect::new(0, 0, width, height)).unwrap();
+        canvas.set_draw_color(background);
+        for (row, line) in lines.iter().enumerate() {
+            let y = BANNER_PADDING + row as u32 * line_height;
+            for (column, c) in line.chars().enumerate() {
+                let x = BANNER_PADDING + column as u32 * 4 * GLYPH_SCALE;
+                draw_glyph(canvas, c, x as i32, y as i32);
+            }
+        }
+    }
+}
+
+fn draw_glyph(canvas: &mut WindowCanvas, c: char, x: i32, y: i32) {
+    for (row, bits) in glyph(c).iter().enumerate() {
+        for column in 0..3 {
+            if bits & (0b100 >> column) != 0 {
+                let rect = Rect::new(x + (column * GLYPH_SCALE) as i32, y + (row as u32 * GLYPH_SCALE) as i32, GLYPH_SCALE, GLYPH_SCALE);
+                canvas.fill_rect(rect).unwrap();
+            }
+        }
+    }
+}
+
+/// 3x5 glyphs, one row per byte, most significant of the low three bits on the left.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b110, 0b001, 0b010, 0b000, 0b010],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        _ => [0; 5],
+    }
+}
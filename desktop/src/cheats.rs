@@ -0,0 +1,109 @@
+// Cheat console on stdin. Cheats for a ROM live in cheats/<rom sha1>.txt and
+// are loaded on start.
+
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use chip8emulator::{Cheat, CheatSearch, Chip8Emulator, SearchFilter};
+
+const HELP: &str = "search | eq XX | changed | same | inc | dec | list | cheat CODE | uncheat CODE | cheats | save";
+const MAX_LISTED: usize = 20;
+
+pub struct CheatConsole {
+    commands: Receiver<String>,
+    search: Option<CheatSearch>,
+    path: PathBuf,
+}
+
+impl CheatConsole {
+    pub fn start(chip8_emulator: &mut Chip8Emulator) -> Self {
+        let path = PathBuf::from("cheats").join(format!("{}.txt", chip8_emulator.rom_sha1()));
+        if let Ok(text) = fs::read_to_string(&path) {
+            match Cheat::parse_codes(&text) {
+                Ok(cheats) => {
+                    println!("Loaded {} cheats from {}", cheats.len(), path.display());
+                    cheats.into_iter().for_each(|cheat| chip8_emulator.add_cheat(cheat));
+                },
+                Err(err) => eprintln!("Ignoring {}: {}", path.display(), err),
+            }
+        }
+
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { commands, search: None, path }
+    }
+
+    pub fn poll(&mut self, chip8_emulator: &mut Chip8Emulator) {
+        while let Ok(line) = self.commands.try_recv() {
+            self.run(chip8_emulator, line.trim());
+        }
+    }
+
+    fn run(&mut self, chip8_emulator: &mut Chip8Emulator, line: &str) {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let filter = match command {
+            "search" => {
+                self.search = Some(CheatSearch::new(chip8_emulator));
+                println!("Search started");
+                return;
+            },
+            "eq" => match u8::from_str_radix(arg.trim(), 16) {
+                Ok(value) => SearchFilter::Equal(value),
+                Err(_) => return println!("eq takes a hex byte"),
+            },
+            "changed" => SearchFilter::Changed,
+            "same" => SearchFilter::Unchanged,
+            "inc" => SearchFilter::Increased,
+            "dec" => SearchFilter::Decreased,
+            "list" => return self.list(),
+            "cheat" | "uncheat" => {
+                match Cheat::parse_codes(arg) {
+                    Ok(cheats) if command == "cheat" => cheats.into_iter().for_each(|cheat| chip8_emulator.add_cheat(cheat)),
+                    Ok(cheats) => cheats.into_iter().for_each(|cheat| chip8_emulator.remove_cheat(cheat.target)),
+                    Err(err) => println!("{}", err),
+                }
+                return;
+            },
+            "cheats" => return print!("{}", Cheat::codes_to_text(chip8_emulator.cheats())),
+            "save" => return self.save(chip8_emulator),
+            _ => return println!("{}", HELP),
+        };
+
+        match self.search.as_mut() {
+            Some(search) => {
+                search.filter(chip8_emulator, filter);
+                println!("{} candidates", search.candidates().len());
+            },
+            None => println!("Start with: search"),
+        }
+    }
+
+    fn list(&self) {
+        let candidates = self.search.as_ref().map_or(&[][..], |search| search.candidates());
+        for (target, value) in candidates.iter().take(MAX_LISTED) {
+            println!("{}", Cheat { target: *target, value: *value });
+        }
+        if candidates.len() > MAX_LISTED {
+            println!("... {} more", candidates.len() - MAX_LISTED);
+        }
+    }
+
+    fn save(&self, chip8_emulator: &Chip8Emulator) {
+        let result = fs::create_dir_all("cheats")
+            .and_then(|_| fs::write(&self.path, Cheat::codes_to_text(chip8_emulator.cheats())));
+        match result {
+            Ok(()) => println!("Saved {}", self.path.display()),
+            Err(err) => eprintln!("Failed to save {}: {}", self.path.display(), err),
+        }
+    }
+}
@@ -1,4 +1,3 @@
-use sdl2;
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Scancode;
@@ -10,11 +9,26 @@ use std::env;
 use std::path::Path;
 use std::{thread, time};
 
-use chip8emulator::Chip8Emulator;
+mod achievements;
+mod cheats;
+mod rewind;
+
+use chip8emulator::{BuiltinFont, Chip8Emulator, CycleState, Font, GifRecorder, MemoryConfig, MemoryProtection, Palette, SymbolMap, TimingMode, Variant, VipHybrid, WavRecorder};
+use achievements::AchievementTracker;
+use cheats::CheatConsole;
+use rewind::Rewinder;
 
 const CELL_SIZE:u32 = 18;
 const HEIGHT: u32 = 32;
 const WIDTH: u32 = 64;
+const SAMPLE_RATE: u32 = 44100;
+const CYCLE_INTERVAL_MS: u32 = 1;
+const HALTED_WAIT_MS: u32 = 100;
+const INSTRUCTIONS_PER_SECOND: u32 = 1000 / CYCLE_INTERVAL_MS;
+const FRAME_INTERVAL: time::Duration = time::Duration::from_micros(1_000_000 / 60);
+const REWIND_BUDGET_BYTES: usize = 32 * 1024 * 1024;
+const REWIND_INTERVAL_TICKS: usize = 4;
+const MAX_REWIND_SPEED: usize = 8;
 
 fn main () {
     let args: Vec<_> = env::args().collect();
@@ -23,8 +37,8 @@ fn main () {
     }
     match Path::new(&args[1]).extension().and_then(OsStr::to_str) {
         Some(ext) => {
-            if ext != "ch8" {
-                panic!("Provide a .ch file");
+            if ext != "ch8" && ext != "c8x" {
+                panic!("Provide a .ch8 or .c8x file");
             }
         },
         _ => {
@@ -37,55 +51,302 @@ fn main () {
 
     let window = video_subsystem.window("Chip-8 Emulator", WIDTH * CELL_SIZE, HEIGHT * CELL_SIZE).position_centered().build().unwrap();
 
+    let palette = Palette::default();
+    let foreground = Color::RGB(palette.foreground[0], palette.foreground[1], palette.foreground[2]);
+    let background = Color::RGB(palette.background[0], palette.background[1], palette.background[2]);
+
     let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::WHITE);
+    canvas.set_draw_color(foreground);
     canvas.clear();
     canvas.present();
 
     let buffer = fs::read(&args[1]).unwrap();
     let mut chip8_emulator = Chip8Emulator::new();
+    chip8_emulator.set_memory_protection(MemoryProtection::Report);
+    chip8_emulator.add_extension(Box::new(VipHybrid::new()));
+    if Path::new(&args[1]).extension().and_then(OsStr::to_str) == Some("c8x") {
+        chip8_emulator.set_memory_config(MemoryConfig::CHIP_8X).unwrap();
+        chip8_emulator.set_variant(Variant::Chip8X);
+    }
+    // A hand-drawn font next to the ROM replaces the built-in one.
+    let font_path = Path::new(&args[1]).with_extension("font");
+    if let Ok(text) = fs::read_to_string(&font_path) {
+        match Font::parse(&text).map_err(|err| err.to_string()).and_then(|font| chip8_emulator.set_font(font).map_err(|err| err.to_string())) {
+            Ok(()) => println!("Loaded font {}", font_path.display()),
+            Err(err) => println!("Ignoring font {}: {}", font_path.display(), err),
+        }
+    }
     chip8_emulator.init(&buffer);
+    let mut builtin_font = 0;
+    // Hi-res programs are detected by `init` and need a taller window.
+    let (display_width, display_height) = chip8_emulator.display_size();
+    if display_height as u32 != HEIGHT {
+        canvas.window_mut().set_size(display_width as u32 * CELL_SIZE, display_height as u32 * CELL_SIZE).unwrap();
+    }
+
+    let mut cheat_console = CheatConsole::start(&mut chip8_emulator);
+    let mut achievements = AchievementTracker::load(&chip8_emulator);
+
+    let mut recorder: Option<GifRecorder> = None;
+    let mut wav_recorder: Option<WavRecorder> = None;
+    let mut last_tick = time::Instant::now();
+    let mut next_frame = last_tick;
+    // 60 Hz wall-clock ticks for outputs that count frames, whatever the
+    // loop is doing.
+    let mut next_tick = last_tick;
+
+    let mut state = CycleState::Running;
+
+    let mut rewinder = Rewinder::new(REWIND_BUDGET_BYTES, REWIND_INTERVAL_TICKS);
+    let mut rewinding = false;
+    let mut rewind_speed = 1;
 
     let mut event_pump = sdl_context.event_pump().unwrap();
     'main_loop: loop {
-        for event in event_pump.poll_iter() {
+        // When the program is spinning without effect, block on input instead of busy looping.
+        let wait_ms = match state {
+            CycleState::Running => None,
+            CycleState::Halted if !chip8_emulator.is_sound_playing() => Some(HALTED_WAIT_MS),
+            CycleState::Idle | CycleState::Halted => Some(CYCLE_INTERVAL_MS),
+        };
+        let mut events: Vec<Event> = wait_ms.and_then(|ms| event_pump.wait_event_timeout(ms)).into_iter().collect();
+        events.extend(event_pump.poll_iter());
+
+        for event in events {
             match event {
                 Event::Quit { .. } => break 'main_loop,
+                Event::KeyDown { scancode: Some(Scancode::F12), .. } => {
+                    save_file("screenshot", "png", &chip8_emulator.screenshot_png(&palette, CELL_SIZE as usize));
+                }
+                Event::KeyDown { scancode: Some(Scancode::F10), repeat: false, .. } => {
+                    match recorder.take() {
+                        Some(recorder) => save_file("recording", "gif", &recorder.finish()),
+                        None => {
+                            println!("Recording started");
+                            recorder = Some(GifRecorder::new(palette, CELL_SIZE as usize / 3, 1));
+                        }
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::F8), repeat: false, .. } => {
+                    match chip8_emulator.stop_movie_recording() {
+                        Some(movie) => save_file("movie", "c8m", movie.to_text().as_bytes()),
+                        None => {
+                            println!("Movie recording started from power-on");
+                            chip8_emulator.start_movie_recording(INSTRUCTIONS_PER_SECOND);
+                            rewinder.clear();
+                            state = CycleState::Running;
+                        }
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::F9), repeat: false, .. } => {
+                    match wav_recorder.take() {
+                        Some(wav_recorder) => save_file("audio", "wav", &wav_recorder.finish()),
+                        None => {
+                            println!("Audio capture started");
+                            wav_recorder = Some(WavRecorder::new(SAMPLE_RATE));
+                        }
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::F7), repeat: false, .. } => {
+                    match chip8_emulator.stop_profiling() {
+                        Some(profiler) => {
+                            save_file("profile", "txt", profiler.to_text().as_bytes());
+                            save_file("profile", "json", profiler.to_json().as_bytes());
+                            save_file("profile", "folded", profiler.to_collapsed().as_bytes());
+                        },
+                        None => {
+                            println!("Profiling started");
+                            chip8_emulator.start_profiling();
+                        }
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::F6), repeat: false, .. } => {
+                    match chip8_emulator.stop_coverage() {
+                        Some(coverage) => save_file("coverage", "html", coverage.to_html().as_bytes()),
+                        None => {
+                            println!("Coverage tracking started");
+                            chip8_emulator.start_coverage();
+                        }
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::F2), repeat: false, .. } => {
+                    let mut quirks = chip8_emulator.quirks();
+                    quirks.key_wait_on_press = !quirks.key_wait_on_press;
+                    println!("FX0A completes on key {}", if quirks.key_wait_on_press { "press" } else { "release" });
+                    chip8_emulator.set_quirks(quirks);
+                }
+                Event::KeyDown { scancode: Some(Scancode::F3), repeat: false, .. } => {
+                    builtin_font = (builtin_font + 1) % BuiltinFont::ALL.len();
+                    let font = BuiltinFont::ALL[builtin_font];
+                    match chip8_emulator.set_font(Font::builtin(font)) {
+                        Ok(()) => println!("Font {}", font.name()),
+                        Err(err) => println!("Font {}: {}", font.name(), err),
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::F4), repeat: false, .. } => {
+                    let mode = match chip8_emulator.timing_mode() {
+                        TimingMode::Flat => TimingMode::Vip,
+                        TimingMode::Vip => TimingMode::Flat,
+                    };
+                    println!("Timing mode {:?}", mode);
+                    chip8_emulator.set_timing_mode(mode);
+                }
+                Event::KeyDown { scancode: Some(Scancode::F5), repeat: false, .. } => {
+                    match chip8_emulator.stop_call_trace() {
+                        Some(call_trace) => {
+                            let symbols = fs::read_to_string(Path::new(&args[1]).with_extension("sym")).ok().map(|text| SymbolMap::parse(&text));
+                            save_file("trace", "json", call_trace.to_chrome_json(symbols.as_ref()).as_bytes());
+                        },
+                        None => {
+                            println!("Call tracing started");
+                            chip8_emulator.start_call_trace();
+                        }
+                    }
+                }
+                Event::KeyDown { scancode: Some(Scancode::Backspace), .. } => rewinding = true,
+                Event::KeyUp { scancode: Some(Scancode::Backspace), .. } => rewinding = false,
+                Event::KeyDown { scancode: Some(Scancode::PageUp), repeat: false, .. } => {
+                    rewind_speed = (rewind_speed + 1).min(MAX_REWIND_SPEED);
+                    println!("Rewind speed {}", rewind_speed);
+                }
+                Event::KeyDown { scancode: Some(Scancode::PageDown), repeat: false, .. } => {
+                    rewind_speed = (rewind_speed - 1).max(1);
+                    println!("Rewind speed {}", rewind_speed);
+                }
                 Event::KeyDown { scancode: Some(scancode), .. } => {
                     if let Some(idx) = scancode2idx(scancode) {
                         chip8_emulator.set_key(idx, true)
                     }
+                    if let Some(idx) = scancode2idx_second(scancode) {
+                        chip8_emulator.set_second_key(idx, true)
+                    }
                 }
                 Event::KeyUp { scancode: Some(scancode), .. } => {
                     if let Some(idx) = scancode2idx(scancode) {
                         chip8_emulator.set_key(idx, false)
                     }
+                    if let Some(idx) = scancode2idx_second(scancode) {
+                        chip8_emulator.set_second_key(idx, false)
+                    }
                 }
                 _ => (),
             }
         }
         
-        chip8_emulator.emulate_cycle();
-        chip8_emulator.advance_timers();
+        cheat_console.poll(&mut chip8_emulator);
+
+        // VIP timing runs a whole frame's budget per call, so frames are paced
+        // at 60 Hz rather than on the 1 ms instruction tick.
+        let vip_timing = chip8_emulator.timing_mode() == TimingMode::Vip;
+        let frame_due = !vip_timing || time::Instant::now() >= next_frame;
+        if vip_timing && frame_due {
+            next_frame = (next_frame + FRAME_INTERVAL).max(time::Instant::now());
+        }
+
+        if !frame_due {
+            // Between VIP frames; only input is handled.
+        } else if rewinding && !chip8_emulator.is_recording_movie() {
+            // Not while recording: rewinding would break the movie's power-on sync.
+            for _ in 0..rewind_speed {
+                if !rewinder.step_back(&mut chip8_emulator) {
+                    break;
+                }
+            }
+            state = chip8_emulator.cycle_state();
+        } else {
+            state = match state {
+                CycleState::Halted => {
+                    chip8_emulator.advance_timers();
+                    chip8_emulator.cycle_state()
+                },
+                _ => chip8_emulator.run_frame(1),
+            };
+            rewinder.tick(&chip8_emulator);
+        }
+
+        for fault in chip8_emulator.take_memory_faults() {
+            eprintln!("Memory fault: {}", fault);
+        }
+        if let Some(dump) = chip8_emulator.take_crash_dump() {
+            eprintln!("Crashed: {}", dump);
+            save_file("crash", "c8d", dump.to_text().as_bytes());
+        }
+
+        let now = time::Instant::now();
+        let mut ticks = 0;
+        while next_tick <= now {
+            next_tick += FRAME_INTERVAL;
+            ticks += 1;
+        }
+
+        if let Some(recorder) = recorder.as_mut() {
+            for _ in 0..ticks {
+                recorder.capture(&chip8_emulator);
+            }
+        }
+        // Achievement conditions count frames; a rewound state is not played.
+        if !rewinding || chip8_emulator.is_recording_movie() {
+            for _ in 0..ticks {
+                achievements.update(&chip8_emulator);
+            }
+        }
+
+        if let Some(wav_recorder) = wav_recorder.as_mut() {
+            wav_recorder.capture_duration(&chip8_emulator, now - last_tick);
+        }
+        last_tick = now;
+
+        if achievements.needs_redraw() {
+            chip8_emulator.set_draw_flag(true);
+        }
 
         if chip8_emulator.should_render() {
             canvas.clear();
-            for (idx, val) in chip8_emulator.get_color_array().into_iter().enumerate() {
-                if *val == 0x01 {
-                    canvas.set_draw_color(Color::WHITE);
-                } else {
-                    canvas.set_draw_color(Color::BLACK);
-                }
-                let ul_x = (idx % 64) as u32;
-                let ul_y = (idx / 64) as u32;
+            for idx in 0..chip8_emulator.get_color_array().len() {
+                let [r, g, b] = chip8_emulator.pixel_rgb(&palette, idx);
+                canvas.set_draw_color(Color::RGB(r, g, b));
+                let ul_x = idx as u32 % WIDTH;
+                let ul_y = idx as u32 / WIDTH;
                 canvas.fill_rect(Rect::new((ul_x * CELL_SIZE) as i32, (ul_y * CELL_SIZE) as i32, CELL_SIZE, CELL_SIZE)).unwrap();
             }
+            achievements.draw_overlay(&mut canvas, foreground, background);
             canvas.present();
 
             chip8_emulator.set_draw_flag(false);
         }
 
-        thread::sleep(time::Duration::from_millis(1));
+        if state == CycleState::Running {
+            if vip_timing {
+                thread::sleep(next_frame.saturating_duration_since(time::Instant::now()));
+            } else {
+                thread::sleep(time::Duration::from_millis(CYCLE_INTERVAL_MS as u64));
+            }
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        save_file("recording", "gif", &recorder.finish());
+    }
+    if let Some(wav_recorder) = wav_recorder {
+        save_file("audio", "wav", &wav_recorder.finish());
+    }
+    if let Some(movie) = chip8_emulator.stop_movie_recording() {
+        save_file("movie", "c8m", movie.to_text().as_bytes());
+    }
+    if let Some(profiler) = chip8_emulator.stop_profiling() {
+        save_file("profile", "txt", profiler.to_text().as_bytes());
+    }
+    if let Some(coverage) = chip8_emulator.stop_coverage() {
+        save_file("coverage", "html", coverage.to_html().as_bytes());
+    }
+}
+
+fn save_file(prefix: &str, extension: &str, bytes: &[u8]) {
+    let timestamp = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_millis();
+    let path = format!("{}-{}.{}", prefix, timestamp, extension);
+    match fs::write(&path, bytes) {
+        Ok(()) => println!("Saved {}", path),
+        Err(err) => eprintln!("Failed to save {}: {}", path, err),
     }
 }
 
@@ -110,3 +371,26 @@ fn scancode2idx(code: Scancode) -> Option<usize> {
         _ => None,
     }
 }
+
+/// CHIP-8X second keypad, on the right-hand side of the keyboard.
+fn scancode2idx_second(code: Scancode) -> Option<usize> {
+    match code {
+        Scancode::Num7 => Some(0x1),
+        Scancode::Num8 => Some(0x2),
+        Scancode::Num9 => Some(0x3),
+        Scancode::Num0 => Some(0xC),
+        Scancode::U => Some(0x4),
+        Scancode::I => Some(0x5),
+        Scancode::O => Some(0x6),
+        Scancode::P => Some(0xD),
+        Scancode::J => Some(0x7),
+        Scancode::K => Some(0x8),
+        Scancode::L => Some(0x9),
+        Scancode::Semicolon => Some(0xE),
+        Scancode::M => Some(0xA),
+        Scancode::Comma => Some(0x0),
+        Scancode::Period => Some(0xB),
+        Scancode::Slash => Some(0xF),
+        _ => None,
+    }
+}
@@ -0,0 +1,151 @@
+// Rewind buffer: a ring of machine snapshots under a memory budget. The
+// newest snapshot is kept whole; every older one is stored as the XOR
+// against its successor, run-length encoded, which is tiny since little
+// changes between frames.
+
+use std::collections::VecDeque;
+
+use chip8emulator::{Chip8Emulator, Snapshot};
+
+pub struct Rewinder {
+    budget: usize,
+    interval: usize,
+    ticks: usize,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+impl Rewinder {
+    /// Takes a snapshot every `interval` ticks, keeping at most `budget` bytes.
+    pub fn new(budget: usize, interval: usize) -> Self {
+        Self {
+            budget,
+            interval: interval.max(1),
+            ticks: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    pub fn tick(&mut self, chip8_emulator: &Chip8Emulator) {
+        self.ticks += 1;
+        if self.ticks < self.interval {
+            return;
+        }
+        self.ticks = 0;
+
+        let snapshot = chip8_emulator.snapshot().as_bytes().to_vec();
+        match self.newest.take() {
+            Some(previous) if previous.len() == snapshot.len() => {
+                let delta = encode_delta(&previous, &snapshot);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            },
+            // RAM size or extensions changed; older snapshots no longer apply.
+            Some(_) => {
+                self.deltas.clear();
+                self.used = 0;
+            },
+            None => (),
+        }
+        self.newest = Some(snapshot);
+
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the newest snapshot and drops it. Returns false once the buffer
+    /// is empty; snapshots the machine no longer matches are all dropped.
+    pub fn step_back(&mut self, chip8_emulator: &mut Chip8Emulator) -> bool {
+        let newest = match self.newest.take() {
+            Some(newest) => newest,
+            None => return false,
+        };
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used -= delta.len();
+            self.newest = Some(decode_delta(&newest, &delta));
+        }
+        self.ticks = 0;
+
+        match Snapshot::from_bytes(newest).map(|snapshot| chip8_emulator.restore(&snapshot)) {
+            Some(Ok(())) => {
+                chip8_emulator.set_draw_flag(true);
+                true
+            },
+            Some(Err(_)) | None => {
+                self.clear();
+                false
+            },
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.used = 0;
+        self.ticks = 0;
+    }
+}
+
+// Delta format: repeated (zero run length, literal length, literal bytes),
+// lengths as LEB128 varints.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = old.iter().zip(new).map(|(a, b)| a ^ b).collect();
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < xor.len() {
+        let zeros = xor[pos..].iter().take_while(|byte| **byte == 0).count();
+        pos += zeros;
+        let literal = xor[pos..].iter().take_while(|byte| **byte != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literal);
+        out.extend_from_slice(&xor[pos..pos + literal]);
+        pos += literal;
+    }
+    out
+}
+
+fn decode_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut old = new.to_vec();
+    let mut pos = 0;
+    let mut idx = 0;
+    while idx < delta.len() {
+        pos += read_varint(delta, &mut idx);
+        let literal = read_varint(delta, &mut idx);
+        for byte in &delta[idx..idx + literal] {
+            old[pos] ^= byte;
+            pos += 1;
+        }
+        idx += literal;
+    }
+    old
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(bytes: &[u8], idx: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*idx];
+        *idx += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,303 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+use chip8emulator::{BuiltinFont, Chip8Emulator, CrashDump, Debugger, Font, GifRecorder, MemoryConfig, MemoryLayout, MemoryProtection, Movie, MoviePlayer, Palette, SymbolMap, TimingMode, Variant, VipHybrid, WavRecorder};
+
+const CYCLES_PER_FRAME: usize = 3;
+const SCALE: usize = 1;
+const SAMPLE_RATE: u32 = 44100;
+const CHECKPOINT_INTERVAL: u64 = 1000;
+const CRASH_DUMP_PATH: &str = "crash.c8d";
+
+struct Options {
+    rom: String,
+    frames: usize,
+    png: Option<String>,
+    gif: Option<String>,
+    wav: Option<String>,
+    movie: Option<String>,
+    debug: bool,
+    golden: Option<String>,
+    profile: Option<String>,
+    coverage: Option<String>,
+    trace: Option<String>,
+    symbols: Option<String>,
+    crash_dump: Option<String>,
+    vip_timing: bool,
+    vip_layout: bool,
+    key_wait_on_press: bool,
+    memory_config: MemoryConfig,
+    font: Option<String>,
+    chip8x: bool,
+}
+
+fn main() {
+    let options = parse_args();
+
+    let buffer = fs::read(&options.rom).unwrap();
+    let mut chip8_emulator = Chip8Emulator::new();
+    chip8_emulator.set_memory_protection(MemoryProtection::Report);
+    chip8_emulator.add_extension(Box::new(VipHybrid::new()));
+    if options.vip_timing {
+        chip8_emulator.set_timing_mode(TimingMode::Vip);
+    }
+    if options.vip_layout {
+        chip8_emulator.set_memory_layout(MemoryLayout::Vip);
+    }
+    if options.key_wait_on_press {
+        let mut quirks = chip8_emulator.quirks();
+        quirks.key_wait_on_press = true;
+        chip8_emulator.set_quirks(quirks);
+    }
+    if options.chip8x {
+        chip8_emulator.set_variant(Variant::Chip8X);
+    }
+    if let Err(err) = chip8_emulator.set_memory_config(options.memory_config) {
+        fail(&format!("Invalid memory configuration: {}", err));
+    }
+    if let Some(name) = &options.font {
+        let font = load_font(name).unwrap_or_else(|err| fail(&format!("Invalid font {}: {}", name, err)));
+        if let Err(err) = chip8_emulator.set_font(font) {
+            fail(&format!("Invalid font {}: {}", name, err));
+        }
+    }
+    if let Err(err) = chip8_emulator.try_init(&buffer) {
+        fail(&err.to_string());
+    }
+
+    if let Some(path) = &options.crash_dump {
+        let dump = CrashDump::parse(&fs::read_to_string(path).unwrap()).unwrap_or_else(|err| fail(&format!("Invalid crash dump {}: {}", path, err)));
+        if let Err(err) = chip8_emulator.load_crash_dump(&dump) {
+            fail(&format!("Cannot load crash dump {}: {}", path, err));
+        }
+        println!("Loaded crash dump: {}", dump);
+    }
+
+    if options.debug {
+        debug(&mut chip8_emulator, (options.frames * CYCLES_PER_FRAME) as u64);
+        return;
+    }
+
+    let mut player = options.movie.as_ref().map(|path| {
+        let movie = Movie::parse(&fs::read_to_string(path).unwrap()).unwrap_or_else(|err| fail(&format!("Invalid movie {}: {}", path, err)));
+        MoviePlayer::start(movie, &mut chip8_emulator, &buffer).unwrap_or_else(|err| fail(&err.to_string()))
+    });
+
+    if options.profile.is_some() {
+        chip8_emulator.start_profiling();
+    }
+    if options.coverage.is_some() {
+        chip8_emulator.start_coverage();
+    }
+    if options.trace.is_some() {
+        chip8_emulator.start_call_trace();
+    }
+
+    let mut recorder = options.gif.as_ref().map(|_| GifRecorder::new(Palette::default(), SCALE, 1));
+    let mut wav_recorder = options.wav.as_ref().map(|_| WavRecorder::new(SAMPLE_RATE));
+
+    for _ in 0..options.frames {
+        match player.as_mut() {
+            Some(player) if player.is_finished() => break,
+            Some(player) => {
+                if let Err(err) = player.play_frame(&mut chip8_emulator) {
+                    fail(&format!("Movie playback failed: {}", err));
+                }
+            },
+            None => run_frame(&mut chip8_emulator),
+        }
+
+        for fault in chip8_emulator.take_memory_faults() {
+            eprintln!("Memory fault: {}", fault);
+        }
+        if let Some(dump) = chip8_emulator.take_crash_dump() {
+            fs::write(CRASH_DUMP_PATH, dump.to_text()).unwrap();
+            fail(&format!("Crashed: {}, dump written to {}", dump, CRASH_DUMP_PATH));
+        }
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.capture(&chip8_emulator);
+        }
+        if let Some(wav_recorder) = wav_recorder.as_mut() {
+            wav_recorder.capture_tick(&chip8_emulator);
+        }
+    }
+
+    if let (Some(path), Some(recorder)) = (&options.gif, recorder) {
+        fs::write(path, recorder.finish()).unwrap();
+    }
+
+    if let (Some(path), Some(wav_recorder)) = (&options.wav, wav_recorder) {
+        fs::write(path, wav_recorder.finish()).unwrap();
+    }
+
+    if let (Some(path), Some(profiler)) = (&options.profile, chip8_emulator.stop_profiling()) {
+        let report = match Path::new(path).extension().and_then(OsStr::to_str) {
+            Some("json") => profiler.to_json(),
+            Some("folded") => profiler.to_collapsed(),
+            _ => profiler.to_text(),
+        };
+        fs::write(path, report).unwrap();
+    }
+
+    if let (Some(path), Some(coverage)) = (&options.coverage, chip8_emulator.stop_coverage()) {
+        let report = match Path::new(path).extension().and_then(OsStr::to_str) {
+            Some("html") => coverage.to_html(),
+            _ => coverage.to_listing(),
+        };
+        fs::write(path, report).unwrap();
+    }
+
+    if let (Some(path), Some(call_trace)) = (&options.trace, chip8_emulator.stop_call_trace()) {
+        let symbols = options.symbols.as_ref().map(|path| SymbolMap::parse(&fs::read_to_string(path).unwrap()));
+        fs::write(path, call_trace.to_chrome_json(symbols.as_ref())).unwrap();
+    }
+
+    let png = chip8_emulator.screenshot_png(&Palette::default(), SCALE);
+
+    if let Some(path) = &options.png {
+        fs::write(path, &png).unwrap();
+    }
+
+    if let Some(path) = &options.golden {
+        let expected = fs::read(path).unwrap();
+        if expected != png {
+            fail(&format!("Screen after {} frames does not match {}", options.frames, path));
+        }
+    }
+}
+
+fn run_frame(chip8_emulator: &mut Chip8Emulator) {
+    chip8_emulator.run_frame(CYCLES_PER_FRAME);
+}
+
+fn debug(chip8_emulator: &mut Chip8Emulator, max_cycles: u64) {
+    let help = "s [n] step | c continue | sb step back | rc reverse continue | b/db ADDR breakpoint | \
+                w/dw ADDR watchpoint | lw ADDR last write | k KEY 0/1 key | q quit";
+    let mut debugger = Debugger::new(CYCLES_PER_FRAME as u64, CHECKPOINT_INTERVAL);
+    debugger.attach(chip8_emulator);
+
+    let stdin = io::stdin();
+    print!("> ");
+    io::stdout().flush().unwrap();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap();
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let hex = |idx: usize| args.get(idx).and_then(|s| usize::from_str_radix(s.trim_start_matches("0x"), 16).ok());
+
+        let reason = match (args.first().copied(), hex(1)) {
+            (Some("s"), _) => {
+                let count = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(1);
+                (0..count).map(|_| debugger.step(chip8_emulator)).last()
+            },
+            (Some("c"), _) => Some(debugger.run(chip8_emulator, max_cycles)),
+            (Some("sb"), _) => Some(debugger.step_back(chip8_emulator)),
+            (Some("rc"), _) => Some(debugger.reverse_continue(chip8_emulator)),
+            (Some("b"), Some(address)) => { debugger.add_breakpoint(address as u16); None },
+            (Some("db"), Some(address)) => { debugger.remove_breakpoint(address as u16); None },
+            (Some("w"), Some(address)) => { debugger.add_watchpoint(address); None },
+            (Some("dw"), Some(address)) => { debugger.remove_watchpoint(address); None },
+            (Some("lw"), Some(address)) => {
+                match debugger.last_write(chip8_emulator, address) {
+                    Some(write) => println!("{:#05x} last written at cycle {} by {:#05x} ({:#06x})", address, write.cycle, write.pc, write.opcode),
+                    None => println!("{:#05x} not written in recorded history", address),
+                }
+                None
+            },
+            (Some("k"), Some(key)) if key < 16 => {
+                debugger.set_key(chip8_emulator, key, args.get(2) == Some(&"1"));
+                None
+            },
+            (Some("q"), _) => break,
+            _ => { println!("{}", help); None },
+        };
+
+        if let Some(reason) = reason {
+            println!("{:?} at cycle {}, PC {:#05x}", reason, chip8_emulator.cycles(), chip8_emulator.program_counter());
+        }
+        print!("> ");
+        io::stdout().flush().unwrap();
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let usage = "Run: headless /path/to/.ch8/file <frames> [--png out.png] [--gif out.gif] [--wav out.wav] [--movie in.c8m] [--debug] [--golden expected.png] [--profile out.txt|.json|.folded] [--coverage out.txt|.html] [--trace out.json] [--symbols map.sym] [--crash-dump in.c8d] [--vip-timing] [--vip-layout] [--key-wait-on-press] [--ram-size N] [--load-address ADDR] [--font-address ADDR] [--font NAME|font.txt|font.bin] [--chip8x]";
+    let mut args = env::args().skip(1);
+    let rom = args.next().expect(usage);
+    let frames = args.next().and_then(|s| s.parse().ok()).expect(usage);
+    let mut options = Options { rom, frames, png: None, gif: None, wav: None, movie: None, debug: false, golden: None, profile: None, coverage: None, trace: None, symbols: None, crash_dump: None, vip_timing: false, vip_layout: false, key_wait_on_press: false, memory_config: MemoryConfig::default(), font: None, chip8x: false };
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--png" => options.png = Some(args.next().expect(usage)),
+            "--gif" => options.gif = Some(args.next().expect(usage)),
+            "--wav" => options.wav = Some(args.next().expect(usage)),
+            "--movie" => options.movie = Some(args.next().expect(usage)),
+            "--debug" => options.debug = true,
+            "--golden" => options.golden = Some(args.next().expect(usage)),
+            "--profile" => options.profile = Some(args.next().expect(usage)),
+            "--coverage" => options.coverage = Some(args.next().expect(usage)),
+            "--trace" => options.trace = Some(args.next().expect(usage)),
+            "--symbols" => options.symbols = Some(args.next().expect(usage)),
+            "--crash-dump" => options.crash_dump = Some(args.next().expect(usage)),
+            "--vip-timing" => options.vip_timing = true,
+            "--vip-layout" => options.vip_layout = true,
+            "--key-wait-on-press" => options.key_wait_on_press = true,
+            "--ram-size" => options.memory_config.ram_size = parse_number(&args.next().expect(usage)).expect(usage),
+            "--load-address" => options.memory_config.program_start = parse_address(&args.next().expect(usage)),
+            "--font-address" => options.memory_config.font_address = parse_address(&args.next().expect(usage)),
+            "--font" => options.font = Some(args.next().expect(usage)),
+            // Loads at 0x300 unless --load-address follows.
+            "--chip8x" => {
+                options.chip8x = true;
+                options.memory_config.program_start = MemoryConfig::CHIP_8X.program_start;
+            },
+            _ => panic!("{}", usage),
+        }
+    }
+    // The debugger drives the machine itself and cannot feed a movie's input.
+    if options.debug && options.movie.is_some() {
+        fail("--debug cannot be combined with --movie");
+    }
+    options
+}
+
+/// A built-in font by name, or a font file: text if it starts with the font
+/// header, raw bytes otherwise.
+fn load_font(name: &str) -> Result<Font, String> {
+    if let Some(font) = BuiltinFont::from_name(name) {
+        return Ok(Font::builtin(font));
+    }
+    let bytes = fs::read(name).map_err(|err| err.to_string())?;
+    let font = match std::str::from_utf8(&bytes) {
+        Ok(text) if text.starts_with("chip8-font") => Font::parse(text),
+        _ => Font::from_bytes(&bytes),
+    };
+    font.map_err(|err| err.to_string())
+}
+
+/// Decimal, or hex with a `0x` prefix.
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// A 16-bit address, decimal or hex with a `0x` prefix.
+fn parse_address(text: &str) -> u16 {
+    let address = match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    address.unwrap_or_else(|| fail(&format!("Invalid address {}: expected 0 to 0xFFFF", text)))
+}
@@ -3,6 +3,8 @@ import init, * as wasm from "./pkg/chip8emu.js"
 const WIDTH = 64;
 const HEIGHT = 32;
 const CELL_SIZE = 18;
+const RECORD_CELL_SIZE = 6;
+const MAX_CHEAT_CANDIDATES = 20;
 let animation_frame = 0;
 
 const TICKS_PER_FRAME = 3;
@@ -15,6 +17,53 @@ const ctx = canvas.getContext("2d");
 ctx.fillStyle = "black";
 ctx.fillRect(0, 0, WIDTH * CELL_SIZE, HEIGHT * CELL_SIZE);
 
+function download(blob, filename) {
+    const url = URL.createObjectURL(blob);
+    const link = document.createElement("a");
+    link.href = url;
+    link.download = filename;
+    link.click();
+    URL.revokeObjectURL(url);
+}
+
+function hex_input(id) {
+    return parseInt(document.getElementById(id).value, 16);
+}
+
+// Loaded from the font file input; used while "custom file" is selected.
+let custom_font = null;
+
+function apply_font(chip8_emulator_wasm) {
+    const name = document.getElementById("font").value;
+    if (name != "custom") {
+        chip8_emulator_wasm.set_font(name);
+    } else if (custom_font) {
+        chip8_emulator_wasm.load_font(custom_font);
+    }
+}
+
+// Machine settings from the page; they reset the machine, so they are only
+// applied right before a ROM is loaded. The font can change at any time.
+function configure(chip8_emulator_wasm, rom_name) {
+    const chip8x = document.getElementById("chip8x").checked || rom_name.endsWith(".c8x");
+    chip8_emulator_wasm.set_chip8x(chip8x);
+    if (!chip8x) {
+        chip8_emulator_wasm.set_memory_config(hex_input("ram-size"), hex_input("program-start"), hex_input("font-address"));
+    }
+    apply_font(chip8_emulator_wasm);
+}
+
+// The display grows to 64x64 for hi-res programs, so the canvas follows the
+// emulator's display size.
+function resize_canvas(chip8_emulator_wasm) {
+    const width = chip8_emulator_wasm.display_width() * CELL_SIZE;
+    const height = chip8_emulator_wasm.display_height() * CELL_SIZE;
+    if (canvas.width != width || canvas.height != height) {
+        canvas.width = width;
+        canvas.height = height;
+    }
+}
+
 async function run() {
     await init();
     let chip8_emulator_wasm = new wasm.Chip8EmulatorWasm();
@@ -28,6 +77,80 @@ async function run() {
         chip8_emulator_wasm.keypress(evt, false)
     });
 
+    let screenshot_button = document.getElementById("screenshot");
+    screenshot_button.addEventListener("click", function() {
+        const png = chip8_emulator_wasm.screenshot(CELL_SIZE);
+        download(new Blob([png], { type: "image/png" }), `screenshot-${Date.now()}.png`);
+    });
+
+    let record_button = document.getElementById("record");
+    record_button.addEventListener("click", function() {
+        if (record_button.textContent == "Record") {
+            chip8_emulator_wasm.start_recording(RECORD_CELL_SIZE);
+            record_button.textContent = "Stop";
+        } else {
+            const gif = chip8_emulator_wasm.stop_recording();
+            record_button.textContent = "Record";
+            if (gif) {
+                download(new Blob([gif], { type: "image/gif" }), `recording-${Date.now()}.gif`);
+            }
+        }
+    });
+
+    const cheat_codes = document.getElementById("cheat-codes");
+    const cheat_candidates = document.getElementById("cheat-candidates");
+    document.getElementById("cheat-apply").addEventListener("click", function() {
+        try {
+            chip8_emulator_wasm.set_cheats(cheat_codes.value);
+            localStorage.setItem(`cheats-${chip8_emulator_wasm.rom_sha1()}`, cheat_codes.value);
+        } catch (err) {
+            alert(err);
+        }
+    });
+    document.getElementById("cheat-search").addEventListener("click", function() {
+        chip8_emulator_wasm.cheat_search_start();
+        cheat_candidates.textContent = "Search started";
+    });
+    document.getElementById("cheat-filter").addEventListener("click", function() {
+        const filter = document.getElementById("cheat-filter-kind").value;
+        const value = parseInt(document.getElementById("cheat-filter-value").value || "0", 16);
+        try {
+            const count = chip8_emulator_wasm.cheat_search_filter(filter, value);
+            cheat_candidates.textContent = `${count} candidates\n` + chip8_emulator_wasm.cheat_candidates(MAX_CHEAT_CANDIDATES);
+        } catch (err) {
+            alert(err);
+        }
+    });
+
+    const font_select = document.getElementById("font");
+    font_select.addEventListener("change", function() {
+        try {
+            apply_font(chip8_emulator_wasm);
+        } catch (err) {
+            alert(err);
+        }
+    });
+    document.getElementById("font-file").addEventListener("change", function(evt) {
+        const file = evt.target.files[0];
+        if (!file) {
+            return;
+        }
+        file.arrayBuffer().then(function(data) {
+            custom_font = new Uint8Array(data);
+            font_select.value = "custom";
+            try {
+                apply_font(chip8_emulator_wasm);
+            } catch (err) {
+                custom_font = null;
+                alert(err);
+            }
+        });
+    });
+
+    document.getElementById("key-wait-on-press").addEventListener("change", function(evt) {
+        chip8_emulator_wasm.set_key_wait_on_press(evt.target.checked);
+    });
+
     let file_input = document.getElementById("fileinput");
     file_input.addEventListener("change", function(evt) {
         if (animation_frame != 0) {
@@ -42,11 +165,20 @@ async function run() {
 
         let fr = new FileReader()
         fr.onload = function(e) {
-            ctx.fillStyle = "black";
-            ctx.fillRect(0, 0, WIDTH * CELL_SIZE, HEIGHT * CELL_SIZE);
             const  buffer = new Uint8Array(fr.result);
-            chip8_emulator_wasm.reset();
-            chip8_emulator_wasm.init(buffer);
+            try {
+                configure(chip8_emulator_wasm, file.name);
+                chip8_emulator_wasm.reset();
+                chip8_emulator_wasm.init(buffer);
+            } catch (err) {
+                alert(err);
+                return;
+            }
+            resize_canvas(chip8_emulator_wasm);
+            ctx.fillStyle = "black";
+            ctx.fillRect(0, 0, canvas.width, canvas.height);
+            cheat_codes.value = localStorage.getItem(`cheats-${chip8_emulator_wasm.rom_sha1()}`) || "";
+            chip8_emulator_wasm.set_cheats(cheat_codes.value);
             emulate_cycle_and_draw(chip8_emulator_wasm);
         }
         fr.readAsArrayBuffer(file);
@@ -55,12 +187,15 @@ async function run() {
 
 function emulate_cycle_and_draw(chip8_emulator_wasm) {
     for (let i = 0; i < TICKS_PER_FRAME; i++) {
-        chip8_emulator_wasm.emulate_cycle();
+        if (!chip8_emulator_wasm.emulate_cycle()) {
+            break;
+        }
     }
     chip8_emulator_wasm.advance_timers();
 
+    resize_canvas(chip8_emulator_wasm);
     ctx.fillStyle = "black";
-    ctx.fillRect(0, 0, WIDTH * CELL_SIZE, HEIGHT * CELL_SIZE);
+    ctx.fillRect(0, 0, canvas.width, canvas.height);
     ctx.fillStyle = "white";
     chip8_emulator_wasm.render(CELL_SIZE);
 
@@ -1,71 +1,127 @@
 /* tslint:disable */
 /* eslint-disable */
-/**
-*/
+
 export class Chip8EmulatorWasm {
-  free(): void;
-/**
-*/
-  constructor();
-/**
-* @param {Uint8Array} data
-*/
-  init(data: Uint8Array): void;
-/**
-*/
-  emulate_cycle(): void;
-/**
-*/
-  advance_timers(): void;
-/**
-* @param {KeyboardEvent} evt
-* @param {boolean} pressed
-*/
-  keypress(evt: KeyboardEvent, pressed: boolean): void;
-/**
-*/
-  reset(): void;
-/**
-* @param {number} cell_size
-*/
-  render(cell_size: number): void;
+    free(): void;
+    [Symbol.dispose](): void;
+    advance_timers(): void;
+    /**
+     * The first `limit` candidates as cheat codes, one per line.
+     */
+    cheat_candidates(limit: number): string;
+    /**
+     * Narrows the search with "eq", "changed", "same", "inc" or "dec" and
+     * returns the number of candidates left.
+     */
+    cheat_search_filter(filter: string, value: number): number;
+    cheat_search_start(): void;
+    /**
+     * Display height in pixels; 64 once `init` detects a hi-res program.
+     */
+    display_height(): number;
+    /**
+     * Display width in pixels.
+     */
+    display_width(): number;
+    /**
+     * Returns false once the program is idle or halted, so the caller can
+     * stop stepping until the next frame.
+     */
+    emulate_cycle(): boolean;
+    /**
+     * Loads a ROM; fails if it does not fit the memory configuration.
+     */
+    init(data: Uint8Array): void;
+    keypress(evt: KeyboardEvent, pressed: boolean): void;
+    /**
+     * Loads a custom font, either the text format or raw bytes.
+     */
+    load_font(data: Uint8Array): void;
+    constructor();
+    render(cell_size: number): void;
+    reset(): void;
+    rom_sha1(): string;
+    screenshot(cell_size: number): Uint8Array;
+    /**
+     * Replaces all active cheats with the given codes.
+     */
+    set_cheats(text: string): void;
+    /**
+     * Switches to CHIP-8X, loading programs at 0x300, or back to plain
+     * CHIP-8; call before `init`.
+     */
+    set_chip8x(enabled: boolean): void;
+    /**
+     * Switches to a built-in font by name, e.g. "vip" or "schip".
+     */
+    set_font(name: string): void;
+    /**
+     * Makes FX0A finish when a key goes down rather than when it is released.
+     */
+    set_key_wait_on_press(enabled: boolean): void;
+    /**
+     * Sets RAM size, load address and font address; call before `init`.
+     */
+    set_memory_config(ram_size: number, program_start: number, font_address: number): void;
+    start_recording(cell_size: number): void;
+    stop_recording(): Uint8Array | undefined;
 }
 
 export type InitInput = RequestInfo | URL | Response | BufferSource | WebAssembly.Module;
 
 export interface InitOutput {
-  readonly memory: WebAssembly.Memory;
-  readonly __wbg_chip8emulatorwasm_free: (a: number) => void;
-  readonly chip8emulatorwasm_new: (a: number) => void;
-  readonly chip8emulatorwasm_init: (a: number, b: number) => void;
-  readonly chip8emulatorwasm_emulate_cycle: (a: number) => void;
-  readonly chip8emulatorwasm_advance_timers: (a: number) => void;
-  readonly chip8emulatorwasm_keypress: (a: number, b: number, c: number) => void;
-  readonly chip8emulatorwasm_reset: (a: number) => void;
-  readonly chip8emulatorwasm_render: (a: number, b: number) => void;
-  readonly __wbindgen_malloc: (a: number) => number;
-  readonly __wbindgen_realloc: (a: number, b: number, c: number) => number;
-  readonly __wbindgen_add_to_stack_pointer: (a: number) => number;
-  readonly __wbindgen_exn_store: (a: number) => void;
+    readonly memory: WebAssembly.Memory;
+    readonly __wbg_chip8emulatorwasm_free: (a: number, b: number) => void;
+    readonly chip8emulatorwasm_advance_timers: (a: number) => void;
+    readonly chip8emulatorwasm_cheat_candidates: (a: number, b: number) => [number, number];
+    readonly chip8emulatorwasm_cheat_search_filter: (a: number, b: number, c: number, d: number) => [number, number, number];
+    readonly chip8emulatorwasm_cheat_search_start: (a: number) => void;
+    readonly chip8emulatorwasm_display_height: (a: number) => number;
+    readonly chip8emulatorwasm_display_width: (a: number) => number;
+    readonly chip8emulatorwasm_emulate_cycle: (a: number) => number;
+    readonly chip8emulatorwasm_init: (a: number, b: any) => [number, number];
+    readonly chip8emulatorwasm_keypress: (a: number, b: any, c: number) => void;
+    readonly chip8emulatorwasm_load_font: (a: number, b: any) => [number, number];
+    readonly chip8emulatorwasm_new: () => [number, number, number];
+    readonly chip8emulatorwasm_render: (a: number, b: number) => void;
+    readonly chip8emulatorwasm_reset: (a: number) => void;
+    readonly chip8emulatorwasm_rom_sha1: (a: number) => [number, number];
+    readonly chip8emulatorwasm_screenshot: (a: number, b: number) => [number, number];
+    readonly chip8emulatorwasm_set_cheats: (a: number, b: number, c: number) => [number, number];
+    readonly chip8emulatorwasm_set_chip8x: (a: number, b: number) => [number, number];
+    readonly chip8emulatorwasm_set_font: (a: number, b: number, c: number) => [number, number];
+    readonly chip8emulatorwasm_set_key_wait_on_press: (a: number, b: number) => void;
+    readonly chip8emulatorwasm_set_memory_config: (a: number, b: number, c: number, d: number) => [number, number];
+    readonly chip8emulatorwasm_start_recording: (a: number, b: number) => void;
+    readonly chip8emulatorwasm_stop_recording: (a: number) => [number, number];
+    readonly __wbindgen_malloc: (a: number, b: number) => number;
+    readonly __wbindgen_realloc: (a: number, b: number, c: number, d: number) => number;
+    readonly __wbindgen_exn_store: (a: number) => void;
+    readonly __externref_table_alloc: () => number;
+    readonly __wbindgen_externrefs: WebAssembly.Table;
+    readonly __wbindgen_free: (a: number, b: number, c: number) => void;
+    readonly __externref_table_dealloc: (a: number) => void;
+    readonly __wbindgen_start: () => void;
 }
 
 export type SyncInitInput = BufferSource | WebAssembly.Module;
+
 /**
-* Instantiates the given `module`, which can either be bytes or
-* a precompiled `WebAssembly.Module`.
-*
-* @param {SyncInitInput} module
-*
-* @returns {InitOutput}
-*/
-export function initSync(module: SyncInitInput): InitOutput;
+ * Instantiates the given `module`, which can either be bytes or
+ * a precompiled `WebAssembly.Module`.
+ *
+ * @param {{ module: SyncInitInput }} module - Passing `SyncInitInput` directly is deprecated.
+ *
+ * @returns {InitOutput}
+ */
+export function initSync(module: { module: SyncInitInput } | SyncInitInput): InitOutput;
 
 /**
-* If `module_or_path` is {RequestInfo} or {URL}, makes a request and
-* for everything else, calls `WebAssembly.instantiate` directly.
-*
-* @param {InitInput | Promise<InitInput>} module_or_path
-*
-* @returns {Promise<InitOutput>}
-*/
-export default function init (module_or_path?: InitInput | Promise<InitInput>): Promise<InitOutput>;
+ * If `module_or_path` is {RequestInfo} or {URL}, makes a request and
+ * for everything else, calls `WebAssembly.instantiate` directly.
+ *
+ * @param {{ module_or_path: InitInput | Promise<InitInput> }} module_or_path - Passing `InitInput` directly is deprecated.
+ *
+ * @returns {Promise<InitOutput>}
+ */
+export default function __wbg_init (module_or_path?: { module_or_path: InitInput | Promise<InitInput> } | InitInput | Promise<InitInput>): Promise<InitOutput>;
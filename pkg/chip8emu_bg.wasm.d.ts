@@ -1,15 +1,34 @@
 /* tslint:disable */
 /* eslint-disable */
 export const memory: WebAssembly.Memory;
-export function __wbg_chip8emulatorwasm_free(a: number): void;
-export function chip8emulatorwasm_new(a: number): void;
-export function chip8emulatorwasm_init(a: number, b: number): void;
-export function chip8emulatorwasm_emulate_cycle(a: number): void;
-export function chip8emulatorwasm_advance_timers(a: number): void;
-export function chip8emulatorwasm_keypress(a: number, b: number, c: number): void;
-export function chip8emulatorwasm_reset(a: number): void;
-export function chip8emulatorwasm_render(a: number, b: number): void;
-export function __wbindgen_malloc(a: number): number;
-export function __wbindgen_realloc(a: number, b: number, c: number): number;
-export function __wbindgen_add_to_stack_pointer(a: number): number;
-export function __wbindgen_exn_store(a: number): void;
+export const __wbg_chip8emulatorwasm_free: (a: number, b: number) => void;
+export const chip8emulatorwasm_advance_timers: (a: number) => void;
+export const chip8emulatorwasm_cheat_candidates: (a: number, b: number) => [number, number];
+export const chip8emulatorwasm_cheat_search_filter: (a: number, b: number, c: number, d: number) => [number, number, number];
+export const chip8emulatorwasm_cheat_search_start: (a: number) => void;
+export const chip8emulatorwasm_display_height: (a: number) => number;
+export const chip8emulatorwasm_display_width: (a: number) => number;
+export const chip8emulatorwasm_emulate_cycle: (a: number) => number;
+export const chip8emulatorwasm_init: (a: number, b: any) => [number, number];
+export const chip8emulatorwasm_keypress: (a: number, b: any, c: number) => void;
+export const chip8emulatorwasm_load_font: (a: number, b: any) => [number, number];
+export const chip8emulatorwasm_new: () => [number, number, number];
+export const chip8emulatorwasm_render: (a: number, b: number) => void;
+export const chip8emulatorwasm_reset: (a: number) => void;
+export const chip8emulatorwasm_rom_sha1: (a: number) => [number, number];
+export const chip8emulatorwasm_screenshot: (a: number, b: number) => [number, number];
+export const chip8emulatorwasm_set_cheats: (a: number, b: number, c: number) => [number, number];
+export const chip8emulatorwasm_set_chip8x: (a: number, b: number) => [number, number];
+export const chip8emulatorwasm_set_font: (a: number, b: number, c: number) => [number, number];
+export const chip8emulatorwasm_set_key_wait_on_press: (a: number, b: number) => void;
+export const chip8emulatorwasm_set_memory_config: (a: number, b: number, c: number, d: number) => [number, number];
+export const chip8emulatorwasm_start_recording: (a: number, b: number) => void;
+export const chip8emulatorwasm_stop_recording: (a: number) => [number, number];
+export const __wbindgen_malloc: (a: number, b: number) => number;
+export const __wbindgen_realloc: (a: number, b: number, c: number, d: number) => number;
+export const __wbindgen_exn_store: (a: number) => void;
+export const __externref_table_alloc: () => number;
+export const __wbindgen_externrefs: WebAssembly.Table;
+export const __wbindgen_free: (a: number, b: number, c: number) => void;
+export const __externref_table_dealloc: (a: number) => void;
+export const __wbindgen_start: () => void;
@@ -9,6 +9,8 @@ use chip8emulator::*;
 pub struct Chip8EmulatorWasm {
     chip8_emulator: Chip8Emulator,
     ctx: CanvasRenderingContext2d,
+    recorder: Option<GifRecorder>,
+    cheat_search: Option<CheatSearch>,
 }
 
 #[wasm_bindgen]
@@ -29,17 +31,79 @@ impl Chip8EmulatorWasm {
             .dyn_into::<CanvasRenderingContext2d>()
             .unwrap();
 
-        Ok(Chip8EmulatorWasm{ chip8_emulator, ctx })
+        Ok(Chip8EmulatorWasm{ chip8_emulator, ctx, recorder: None, cheat_search: None })
     }
 
+    /// Loads a ROM; fails if it does not fit the memory configuration.
     #[wasm_bindgen]
-    pub fn init(&mut self, data: Uint8Array) {
-        self.chip8_emulator.init(&data.to_vec());
+    pub fn init(&mut self, data: Uint8Array) -> Result<(), JsValue> {
+        self.chip8_emulator.try_init(&data.to_vec()).map_err(|err| JsValue::from_str(&err.to_string()))
     }
 
+    /// Sets RAM size, load address and font address; call before `init`.
     #[wasm_bindgen]
-    pub fn emulate_cycle(&mut self) {
-        self.chip8_emulator.emulate_cycle();
+    pub fn set_memory_config(&mut self, ram_size: usize, program_start: u16, font_address: u16) -> Result<(), JsValue> {
+        let config = MemoryConfig { ram_size, program_start, font_address };
+        self.chip8_emulator.set_memory_config(config).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Display width in pixels.
+    #[wasm_bindgen]
+    pub fn display_width(&self) -> usize {
+        self.chip8_emulator.display_size().0
+    }
+
+    /// Display height in pixels; 64 once `init` detects a hi-res program.
+    #[wasm_bindgen]
+    pub fn display_height(&self) -> usize {
+        self.chip8_emulator.display_size().1
+    }
+
+    /// Switches to CHIP-8X, loading programs at 0x300, or back to plain
+    /// CHIP-8; call before `init`.
+    #[wasm_bindgen]
+    pub fn set_chip8x(&mut self, enabled: bool) -> Result<(), JsValue> {
+        if enabled {
+            self.chip8_emulator.set_memory_config(MemoryConfig::CHIP_8X).map_err(|err| JsValue::from_str(&err.to_string()))?;
+            self.chip8_emulator.set_variant(Variant::Chip8X);
+        } else if self.chip8_emulator.variant() == Variant::Chip8X {
+            self.chip8_emulator.set_variant(Variant::Chip8);
+        }
+        Ok(())
+    }
+
+    /// Makes FX0A finish when a key goes down rather than when it is released.
+    #[wasm_bindgen]
+    pub fn set_key_wait_on_press(&mut self, enabled: bool) {
+        let mut quirks = self.chip8_emulator.quirks();
+        quirks.key_wait_on_press = enabled;
+        self.chip8_emulator.set_quirks(quirks);
+    }
+
+    /// Switches to a built-in font by name, e.g. "vip" or "schip".
+    #[wasm_bindgen]
+    pub fn set_font(&mut self, name: &str) -> Result<(), JsValue> {
+        let font = BuiltinFont::from_name(name).ok_or_else(|| JsValue::from_str("unknown font"))?;
+        self.chip8_emulator.set_font(Font::builtin(font)).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Loads a custom font, either the text format or raw bytes.
+    #[wasm_bindgen]
+    pub fn load_font(&mut self, data: Uint8Array) -> Result<(), JsValue> {
+        let bytes = data.to_vec();
+        let font = match std::str::from_utf8(&bytes) {
+            Ok(text) if text.starts_with("chip8-font") => Font::parse(text),
+            _ => Font::from_bytes(&bytes),
+        };
+        let font = font.map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.chip8_emulator.set_font(font).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Returns false once the program is idle or halted, so the caller can
+    /// stop stepping until the next frame.
+    #[wasm_bindgen]
+    pub fn emulate_cycle(&mut self) -> bool {
+        self.chip8_emulator.emulate_cycle() == CycleState::Running
     }
 
     #[wasm_bindgen]
@@ -53,6 +117,9 @@ impl Chip8EmulatorWasm {
         if let Some(k) = key2idx(&key) {
             self.chip8_emulator.set_key(k, pressed);
         }
+        if let Some(k) = key2idx_second(&key) {
+            self.chip8_emulator.set_second_key(k, pressed);
+        }
     }
 
     #[wasm_bindgen]
@@ -60,9 +127,87 @@ impl Chip8EmulatorWasm {
         self.chip8_emulator.reset();
     }
 
+    #[wasm_bindgen]
+    pub fn screenshot(&self, cell_size: usize) -> Vec<u8> {
+        self.chip8_emulator.screenshot_png(&Palette::default(), cell_size)
+    }
+
+    #[wasm_bindgen]
+    pub fn start_recording(&mut self, cell_size: usize) {
+        self.recorder = Some(GifRecorder::new(Palette::default(), cell_size, 1));
+    }
+
+    #[wasm_bindgen]
+    pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        self.recorder.take().map(GifRecorder::finish)
+    }
+
+    #[wasm_bindgen]
+    pub fn rom_sha1(&self) -> String {
+        self.chip8_emulator.rom_sha1()
+    }
+
+    #[wasm_bindgen]
+    pub fn cheat_search_start(&mut self) {
+        self.cheat_search = Some(CheatSearch::new(&self.chip8_emulator));
+    }
+
+    /// Narrows the search with "eq", "changed", "same", "inc" or "dec" and
+    /// returns the number of candidates left.
+    #[wasm_bindgen]
+    pub fn cheat_search_filter(&mut self, filter: &str, value: u8) -> Result<usize, JsValue> {
+        let filter = match filter {
+            "eq" => SearchFilter::Equal(value),
+            "changed" => SearchFilter::Changed,
+            "same" => SearchFilter::Unchanged,
+            "inc" => SearchFilter::Increased,
+            "dec" => SearchFilter::Decreased,
+            _ => return Err(JsValue::from_str("unknown filter")),
+        };
+        let search = self.cheat_search.as_mut().ok_or_else(|| JsValue::from_str("no search running"))?;
+        search.filter(&self.chip8_emulator, filter);
+        Ok(search.candidates().len())
+    }
+
+    /// The first `limit` candidates as cheat codes, one per line.
+    #[wasm_bindgen]
+    pub fn cheat_candidates(&self, limit: usize) -> String {
+        let candidates = self.cheat_search.as_ref().map_or(&[][..], |search| search.candidates());
+        let cheats: Vec<Cheat> = candidates.iter().take(limit).map(|(target, value)| Cheat { target: *target, value: *value }).collect();
+        Cheat::codes_to_text(&cheats)
+    }
+
+    /// Replaces all active cheats with the given codes.
+    #[wasm_bindgen]
+    pub fn set_cheats(&mut self, text: &str) -> Result<(), JsValue> {
+        let cheats = Cheat::parse_codes(text).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.chip8_emulator.clear_cheats();
+        cheats.into_iter().for_each(|cheat| self.chip8_emulator.add_cheat(cheat));
+        Ok(())
+    }
+
     #[wasm_bindgen]
     pub fn render(&mut self, cell_size: usize) {
-        for (idx, val) in self.chip8_emulator.get_color_array().into_iter().enumerate() {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.capture(&self.chip8_emulator);
+        }
+
+        // CHIP-8X colours every pixel, background included.
+        if self.chip8_emulator.variant() == Variant::Chip8X {
+            for idx in 0..self.chip8_emulator.get_color_array().len() {
+                let [r, g, b] = self.chip8_emulator.pixel_rgb(&Palette::default(), idx);
+                self.ctx.set_fill_style_str(&format!("rgb({}, {}, {})", r, g, b));
+                self.ctx.fill_rect(
+                    ((idx % WIDTH) * cell_size) as f64,
+                    ((idx / WIDTH) * cell_size) as f64,
+                    cell_size as f64,
+                    cell_size as f64
+                );
+            }
+            return;
+        }
+
+        for (idx, val) in self.chip8_emulator.get_color_array().iter().enumerate() {
             if *val == 0x01 {
                 let ul_x = idx % WIDTH;
                 let ul_y = idx / WIDTH;
@@ -98,3 +243,26 @@ fn key2idx(key: &str) -> Option<usize> {
         _ =>   None,
     }
 }
+
+/// CHIP-8X second keypad, on the right-hand side of the keyboard.
+fn key2idx_second(key: &str) -> Option<usize> {
+    match key {
+        "7" => Some(0x1),
+        "8" => Some(0x2),
+        "9" => Some(0x3),
+        "0" => Some(0xC),
+        "u" => Some(0x4),
+        "i" => Some(0x5),
+        "o" => Some(0x6),
+        "p" => Some(0xD),
+        "j" => Some(0x7),
+        "k" => Some(0x8),
+        "l" => Some(0x9),
+        ";" => Some(0xE),
+        "m" => Some(0xA),
+        "," => Some(0x0),
+        "." => Some(0xB),
+        "/" => Some(0xF),
+        _ =>   None,
+    }
+}